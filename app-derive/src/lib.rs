@@ -0,0 +1,292 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned, ToTokens};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    spanned::Spanned,
+    Data, DeriveInput, Error, Field, Fields, GenericArgument, Ident, PathArguments, Token, Type,
+};
+
+/// Generates `InjectedTo` and `Reduced` impls from the fields of an app state.
+///
+/// - `#[inject(from = Event)]` sets the field to an injected `Event`.
+/// - `#[inject(from = Event, merge)]` sets the field to the same-named
+///   `Option` field of `Event` when it is `Some`. Every field merged from one
+///   `Event` goes into a single impl.
+/// - `#[inject(push)]` pushes an injected element into a `Vec` field.
+/// - `#[reduce(take)]` hands the field over and leaves its `Default` behind.
+///   Consumers get every value taken but the `Default` one, so the field
+///   has to be `PartialEq` like anything a consumer gets.
+/// - `#[reduce(clone)]` hands over a clone of the field.
+///
+/// Events are named after their type, without the module path.
+#[proc_macro_derive(AppState, attributes(inject, reduce))]
+pub fn derive_app_state(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+enum Inject {
+    From(Type),
+    Merge(Type),
+    Push,
+}
+
+enum Reduce {
+    Take,
+    Clone,
+}
+
+enum InjectArg {
+    From(Box<Type>),
+    Merge(Ident),
+    Push(Ident),
+}
+
+impl Parse for InjectArg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let ident: Ident = input.parse()?;
+        if ident == "from" {
+            input.parse::<Token![=]>()?;
+            Ok(Self::From(Box::new(input.parse()?)))
+        } else if ident == "merge" {
+            Ok(Self::Merge(ident))
+        } else if ident == "push" {
+            Ok(Self::Push(ident))
+        } else {
+            Err(Error::new(
+                ident.span(),
+                "expected `from = Type`, `merge` or `push`",
+            ))
+        }
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(not_a_struct(&input)),
+        },
+        _ => return Err(not_a_struct(&input)),
+    };
+    if !input.generics.params.is_empty() {
+        return Err(Error::new(
+            input.generics.span(),
+            "AppState cannot be derived for generic structs",
+        ));
+    }
+
+    let state = &input.ident;
+    let mut impls = Vec::new();
+    let mut merged: Vec<(Type, Vec<&Field>)> = Vec::new();
+    for field in fields {
+        let name = field.ident.as_ref().expect("named field");
+        let ty = &field.ty;
+
+        match inject(field)? {
+            Some(Inject::From(event)) => {
+                let event_name = event_name(&event);
+                impls.push(quote! {
+                    impl ::app::InjectedTo<#state> for #event {
+                        const NAME: &'static str = #event_name;
+
+                        fn inject_to(self, mut state: #state) -> #state {
+                            state.#name = self;
+                            state
+                        }
+                    }
+                });
+            }
+            Some(Inject::Merge(event)) => {
+                let key = event.to_token_stream().to_string();
+                match merged
+                    .iter_mut()
+                    .find(|(ty, _)| ty.to_token_stream().to_string() == key)
+                {
+                    Some((_, merged_fields)) => merged_fields.push(field),
+                    None => merged.push((event, vec![field])),
+                }
+            }
+            Some(Inject::Push) => {
+                let element = vec_element(ty).ok_or_else(|| {
+                    Error::new(ty.span(), "`inject(push)` needs a `Vec<T>` field")
+                })?;
+                let event_name = event_name(element);
+                impls.push(quote! {
+                    impl ::app::InjectedTo<#state> for #element {
+                        const NAME: &'static str = #event_name;
+
+                        fn inject_to(self, mut state: #state) -> #state {
+                            state.#name.push(self);
+                            state
+                        }
+                    }
+                });
+            }
+            None => {}
+        }
+
+        match reduce(field)? {
+            Some(Reduce::Take) => {
+                let take = quote_spanned! {ty.span()=> ::std::mem::take(&mut self.#name) };
+                let taken = quote_spanned! {ty.span()=>
+                    *reduced != <#ty as ::std::default::Default>::default()
+                };
+                impls.push(quote! {
+                    impl ::app::Reduced<#ty> for #state {
+                        fn reduce(&mut self) -> #ty {
+                            #take
+                        }
+
+                        fn is_new(reduced: &#ty, _last: ::std::option::Option<&#ty>) -> bool
+                        where
+                            #ty: ::std::cmp::PartialEq,
+                        {
+                            #taken
+                        }
+                    }
+                });
+            }
+            Some(Reduce::Clone) => impls.push(quote! {
+                impl ::app::Reduced<#ty> for #state {
+                    fn reduce(&mut self) -> #ty {
+                        ::std::clone::Clone::clone(&self.#name)
+                    }
+                }
+            }),
+            None => {}
+        }
+    }
+
+    for (event, fields) in merged {
+        let event_name = event_name(&event);
+        let merges = fields.iter().map(|field| {
+            let name = &field.ident;
+            quote_spanned! {field.ty.span()=>
+                if let ::std::option::Option::Some(value) = self.#name {
+                    state.#name = value;
+                }
+            }
+        });
+        impls.push(quote! {
+            impl ::app::InjectedTo<#state> for #event {
+                const NAME: &'static str = #event_name;
+
+                fn inject_to(self, mut state: #state) -> #state {
+                    #(#merges)*
+                    state
+                }
+            }
+        });
+    }
+
+    Ok(quote! { #(#impls)* })
+}
+
+/// The last path segment, so moving an event to another module keeps the
+/// name its journal entries are recorded under.
+fn event_name(event: &Type) -> String {
+    match event {
+        Type::Path(path) => match path.path.segments.last() {
+            Some(segment) => segment.ident.to_string(),
+            None => event.to_token_stream().to_string(),
+        },
+        _ => event.to_token_stream().to_string(),
+    }
+}
+
+fn not_a_struct(input: &DeriveInput) -> Error {
+    Error::new(
+        input.ident.span(),
+        "AppState can only be derived for structs with named fields",
+    )
+}
+
+fn inject(field: &Field) -> syn::Result<Option<Inject>> {
+    let mut result = None;
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("inject"))
+    {
+        if result.is_some() {
+            return Err(Error::new(attr.span(), "duplicate `inject` attribute"));
+        }
+        let args = attr.parse_args_with(Punctuated::<InjectArg, Token![,]>::parse_terminated)?;
+        let mut from = None;
+        let mut merge = None;
+        let mut push = None;
+        for arg in args {
+            match arg {
+                InjectArg::From(ty) => from = Some(*ty),
+                InjectArg::Merge(ident) => merge = Some(ident),
+                InjectArg::Push(ident) => push = Some(ident),
+            }
+        }
+        result = Some(match (from, merge, push) {
+            (Some(ty), None, None) => Inject::From(ty),
+            (Some(ty), Some(_), None) => Inject::Merge(ty),
+            (None, None, Some(_)) => Inject::Push,
+            (None, Some(merge), None) => {
+                return Err(Error::new(merge.span(), "`merge` needs `from = Type`"))
+            }
+            (_, _, Some(push)) => {
+                return Err(Error::new(
+                    push.span(),
+                    "`push` cannot be combined with `from` or `merge`",
+                ))
+            }
+            (None, None, None) => {
+                return Err(Error::new(
+                    attr.span(),
+                    "expected `from = Type`, `merge` or `push`",
+                ))
+            }
+        });
+    }
+    Ok(result)
+}
+
+fn reduce(field: &Field) -> syn::Result<Option<Reduce>> {
+    let mut result = None;
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("reduce"))
+    {
+        if result.is_some() {
+            return Err(Error::new(attr.span(), "duplicate `reduce` attribute"));
+        }
+        let ident: Ident = attr.parse_args()?;
+        result = Some(if ident == "take" {
+            Reduce::Take
+        } else if ident == "clone" {
+            Reduce::Clone
+        } else {
+            return Err(Error::new(ident.span(), "expected `take` or `clone`"));
+        });
+    }
+    Ok(result)
+}
+
+fn vec_element(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Vec" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(element) => Some(element),
+        _ => None,
+    }
+}
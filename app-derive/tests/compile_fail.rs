@@ -0,0 +1,4 @@
+#[test]
+fn misuse_is_rejected() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
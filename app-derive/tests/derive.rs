@@ -0,0 +1,92 @@
+use app::{AppState, InjectedTo, Reduced};
+
+#[derive(Debug, Default, PartialEq)]
+struct Candles(Vec<f64>);
+
+#[derive(Debug, Default, PartialEq)]
+struct OrderBook(u32);
+
+struct Feed {
+    candles: Option<Candles>,
+    orderbook: Option<OrderBook>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Signal(&'static str);
+
+#[derive(Debug, PartialEq)]
+struct Threshold(f64);
+
+#[derive(AppState, Default)]
+struct Bot {
+    #[inject(from = Feed, merge)]
+    candles: Candles,
+    #[inject(from = Feed, merge)]
+    #[reduce(take)]
+    orderbook: OrderBook,
+    #[inject(push)]
+    #[reduce(take)]
+    signals: Vec<Signal>,
+    #[inject(from = Threshold)]
+    threshold: Threshold,
+    #[reduce(clone)]
+    ticks: u64,
+}
+
+impl Default for Threshold {
+    fn default() -> Self {
+        Threshold(0.5)
+    }
+}
+
+#[test]
+fn merge_only_overwrites_present_fields() {
+    let bot = Feed {
+        candles: Some(Candles(vec![1.0])),
+        orderbook: None,
+    }
+    .inject_to(Bot {
+        orderbook: OrderBook(7),
+        ..Bot::default()
+    });
+
+    assert_eq!(bot.candles, Candles(vec![1.0]));
+    assert_eq!(bot.orderbook, OrderBook(7));
+}
+
+#[test]
+fn push_and_take() {
+    let mut bot = Signal("b").inject_to(Signal("a").inject_to(Bot::default()));
+    bot = Threshold(0.1).inject_to(bot);
+    bot.ticks = 3;
+
+    assert_eq!(
+        Reduced::<Vec<Signal>>::reduce(&mut bot),
+        [Signal("a"), Signal("b")]
+    );
+    assert!(Reduced::<Vec<Signal>>::reduce(&mut bot).is_empty());
+    assert_eq!(Reduced::<OrderBook>::reduce(&mut bot), OrderBook(0));
+    assert_eq!(Reduced::<u64>::reduce(&mut bot), 3);
+    assert_eq!(Reduced::<u64>::reduce(&mut bot), 3);
+    assert_eq!(bot.threshold, Threshold(0.1));
+}
+
+#[test]
+fn events_are_named_after_their_type() {
+    assert_eq!(<Feed as InjectedTo<Bot>>::NAME, "Feed");
+    assert_eq!(<Signal as InjectedTo<Bot>>::NAME, "Signal");
+    assert_eq!(<Threshold as InjectedTo<Bot>>::NAME, "Threshold");
+}
+
+#[test]
+fn taken_values_are_new_unless_nothing_was_taken() {
+    let signals = vec![Signal("a")];
+
+    assert!(<Bot as Reduced<Vec<Signal>>>::is_new(
+        &signals,
+        Some(&signals)
+    ));
+    assert!(!<Bot as Reduced<Vec<Signal>>>::is_new(&Vec::new(), None));
+    assert!(!<Bot as Reduced<u64>>::is_new(&3, Some(&3)));
+    assert!(<Bot as Reduced<u64>>::is_new(&0, None));
+}
@@ -0,0 +1,8 @@
+use app::AppState;
+
+#[derive(AppState)]
+enum State {
+    Idle,
+}
+
+fn main() {}
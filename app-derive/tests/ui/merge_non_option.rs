@@ -0,0 +1,13 @@
+use app::AppState;
+
+struct Feed {
+    price: f64,
+}
+
+#[derive(AppState)]
+struct State {
+    #[inject(from = Feed, merge)]
+    price: f64,
+}
+
+fn main() {}
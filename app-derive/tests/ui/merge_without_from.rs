@@ -0,0 +1,9 @@
+use app::AppState;
+
+#[derive(AppState)]
+struct State {
+    #[inject(merge)]
+    candles: Vec<f64>,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use app::AppState;
+
+#[derive(AppState)]
+struct State {
+    #[inject(push)]
+    last: Option<u32>,
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use app::AppState;
+
+#[derive(PartialEq)]
+struct Connection;
+
+#[derive(AppState)]
+struct State {
+    #[reduce(take)]
+    connection: Connection,
+}
+
+fn main() {}
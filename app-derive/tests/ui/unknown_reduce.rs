@@ -0,0 +1,9 @@
+use app::AppState;
+
+#[derive(AppState)]
+struct State {
+    #[reduce(drain)]
+    signals: Vec<u32>,
+}
+
+fn main() {}
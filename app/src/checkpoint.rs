@@ -0,0 +1,305 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::task::{self, JoinHandle};
+use tracing::{debug, error};
+
+/// Where and how often the app state is snapshotted. A snapshot is always
+/// written on shutdown; `every` adds periodic ones on top of that.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    path: PathBuf,
+    version: u32,
+    interval: Option<Duration>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CheckpointError {
+    #[error("Cannot access checkpoint: {0}")]
+    Io(#[from] io::Error),
+    #[error("Cannot decode checkpoint: {0}")]
+    Decode(#[from] serde_json::Error),
+    #[error("Checkpoint version {found} does not match expected {expected}")]
+    VersionMismatch { found: u32, expected: u32 },
+}
+
+#[derive(Serialize)]
+struct Snapshot<'s, S> {
+    version: u32,
+    state: &'s S,
+}
+
+#[derive(Deserialize)]
+struct Versioned {
+    version: u32,
+    state: serde_json::Value,
+}
+
+impl Checkpoint {
+    /// `version` must be bumped whenever the serialized shape of the state
+    /// changes, so that an old snapshot is rejected instead of misread.
+    pub fn new(path: impl Into<PathBuf>, version: u32) -> Self {
+        Self {
+            path: path.into(),
+            version,
+            interval: None,
+        }
+    }
+
+    pub fn every(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `Ok(None)` when there is no snapshot yet.
+    pub fn load<S: DeserializeOwned>(&self) -> Result<Option<S>, CheckpointError> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        let versioned: Versioned = serde_json::from_reader(BufReader::new(file))?;
+        if versioned.version != self.version {
+            return Err(CheckpointError::VersionMismatch {
+                found: versioned.version,
+                expected: self.version,
+            });
+        }
+        Ok(Some(serde_json::from_value(versioned.state)?))
+    }
+
+    /// Writes to a temporary file first and syncs it before the rename, so a
+    /// crash or power loss mid-write never leaves a truncated snapshot behind.
+    pub fn save<S: Serialize>(&self, state: &S) -> Result<(), CheckpointError> {
+        let tmp = self.path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        let snapshot = Snapshot {
+            version: self.version,
+            state,
+        };
+        serde_json::to_writer(&mut writer, &snapshot)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Type-erased `Checkpoint::save`, so that `App` itself does not need the
+/// serde bounds. Snapshots are written on the blocking pool, as serializing
+/// a large state and the file IO would hold up the store.
+pub(crate) struct Checkpointer<S> {
+    interval: Option<Duration>,
+    save: Box<dyn Fn(S) -> JoinHandle<()> + Send + Sync>,
+    saving: Option<JoinHandle<()>>,
+}
+
+impl<S: Serialize + Send + 'static> Checkpointer<S> {
+    pub(crate) fn new(checkpoint: Checkpoint) -> Self {
+        Self {
+            interval: checkpoint.interval,
+            save: Box::new(move |state| {
+                let checkpoint = checkpoint.clone();
+                task::spawn_blocking(move || match checkpoint.save(&state) {
+                    Ok(()) => debug!(path = ?checkpoint.path, "checkpoint saved"),
+                    Err(error) => error!(path = ?checkpoint.path, %error, "Cannot save checkpoint"),
+                })
+            }),
+            saving: None,
+        }
+    }
+}
+
+impl<S> Checkpointer<S> {
+    pub(crate) fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    /// Skipped while the previous snapshot is still being written.
+    pub(crate) fn save_in_background(&mut self, state: S) {
+        if let Some(saving) = &self.saving {
+            if !saving.is_finished() {
+                debug!("previous checkpoint is still being written - skip this one");
+                return;
+            }
+        }
+        self.saving = Some((self.save)(state));
+    }
+
+    /// Waits for the snapshot still being written, if any, so that `state`
+    /// is the one which stays.
+    pub(crate) async fn save(&mut self, state: S) {
+        if let Some(saving) = self.saving.take() {
+            let _ = saving.await;
+        }
+        let _ = (self.save)(state).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{channel::mpsc, future::BoxFuture, FutureExt, SinkExt};
+
+    use super::*;
+    use crate::{
+        shutdown::Shutdown,
+        supervisor::RestartPolicy,
+        worker::{ProducerWorker, WorkerError},
+        App, InjectedTo,
+    };
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct State {
+        candles: Vec<f64>,
+    }
+
+    #[derive(Debug, Serialize)]
+    struct Candle(f64);
+
+    impl InjectedTo<State> for Candle {
+        const NAME: &'static str = "Candle";
+
+        fn inject_to(self, mut state: State) -> State {
+            state.candles.push(self.0);
+            state
+        }
+    }
+
+    struct OneCandle;
+
+    impl<'f> ProducerWorker<'f, Candle> for OneCandle {
+        fn work(
+            self: Box<Self>,
+            mut state_tx: mpsc::Sender<Candle>,
+            _shutdown: Shutdown,
+        ) -> BoxFuture<'f, Result<(), WorkerError>> {
+            async move { Ok(state_tx.send(Candle(3.0)).await?) }.boxed()
+        }
+    }
+
+    /// Keeps the app running until shutdown
+    struct Idle;
+
+    impl<'f> ProducerWorker<'f, Candle> for Idle {
+        fn work(
+            self: Box<Self>,
+            _state_tx: mpsc::Sender<Candle>,
+            mut shutdown: Shutdown,
+        ) -> BoxFuture<'f, Result<(), WorkerError>> {
+            async move {
+                shutdown.wait().await;
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("checkpoint-{name}-{}.json", std::process::id()))
+    }
+
+    #[test]
+    fn restores_saved_state() {
+        let checkpoint = Checkpoint::new(path("restore"), 1);
+        let state = State {
+            candles: vec![1.0, 2.5],
+        };
+
+        checkpoint.save(&state).unwrap();
+        let restored: Option<State> = checkpoint.load().unwrap();
+        fs::remove_file(checkpoint.path()).unwrap();
+
+        assert_eq!(restored, Some(state));
+    }
+
+    #[test]
+    fn missing_snapshot_is_not_an_error() {
+        let checkpoint = Checkpoint::new(path("missing"), 1);
+
+        assert!(matches!(checkpoint.load::<State>(), Ok(None)));
+    }
+
+    #[test]
+    fn rejects_other_version() {
+        let path = path("version");
+        Checkpoint::new(&path, 1)
+            .save(&State { candles: vec![] })
+            .unwrap();
+
+        let loaded = Checkpoint::new(&path, 2).load::<State>();
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            loaded,
+            Err(CheckpointError::VersionMismatch {
+                found: 1,
+                expected: 2
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn app_resumes_from_checkpoint_and_saves_on_shutdown() {
+        let checkpoint = Checkpoint::new(path("app"), 1);
+        checkpoint
+            .save(&State {
+                candles: vec![1.0, 2.0],
+            })
+            .unwrap();
+
+        let app = App::build(State { candles: vec![] })
+            .checkpoint(checkpoint.clone())
+            .add_producer(|| OneCandle, RestartPolicy::Never)
+            .build();
+        app.run().await;
+        let saved: Option<State> = checkpoint.load().unwrap();
+        fs::remove_file(checkpoint.path()).unwrap();
+
+        assert_eq!(
+            saved,
+            Some(State {
+                candles: vec![1.0, 2.0, 3.0]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn snapshots_periodically_while_running() {
+        let checkpoint = Checkpoint::new(path("periodic"), 1).every(Duration::from_millis(10));
+        let _ = fs::remove_file(checkpoint.path());
+
+        let app = App::build(State { candles: vec![] })
+            .checkpoint(checkpoint.clone())
+            .add_producer(|| OneCandle, RestartPolicy::Never)
+            .add_producer(|| Idle, RestartPolicy::Never)
+            .build();
+        let shutdown = app.shutdown_handle();
+        let snapshotted = async {
+            let saved = tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    if let Ok(Some(saved)) = checkpoint.load::<State>() {
+                        return saved;
+                    }
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+            })
+            .await;
+            shutdown.shutdown();
+            saved
+        };
+        let (_, saved) = futures::future::join(app.run(), snapshotted).await;
+        fs::remove_file(checkpoint.path()).unwrap();
+
+        assert!(saved.is_ok(), "no snapshot before shutdown");
+    }
+}
@@ -0,0 +1,361 @@
+use core::fmt;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use futures::{channel::mpsc, future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    metrics::Metrics,
+    shutdown::ShutdownHandle,
+    wiring::{Scheduled, Wiring},
+    worker::{self, Schedule, WorkerError, WorkerId, WorkerOptions},
+    InjectedTo, Reduced, Runner,
+};
+
+type Wire<'f, S> = Box<dyn FnOnce(&Wiring<'f, S>) -> Runner<'f> + Send + 'f>;
+
+pub(crate) enum ControlCommand<'f, S> {
+    Spawn(WorkerId, Wire<'f, S>),
+    Stop(WorkerId),
+}
+
+type Stopped<'f> = BoxFuture<'f, (WorkerId, Result<(), WorkerError>)>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorkerState {
+    Running,
+    /// Asked to stop, but has not returned yet
+    Stopping,
+    Stopped,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerStatus {
+    pub id: WorkerId,
+    pub state: WorkerState,
+    pub restarts: usize,
+}
+
+/// Adds, stops and lists workers of a running `App`. Workers added before
+/// the app runs are started along with the ones added to the builder.
+pub struct Control<'f, S> {
+    commands: mpsc::UnboundedSender<ControlCommand<'f, S>>,
+    next_index: Arc<AtomicUsize>,
+    states: Arc<Mutex<HashMap<WorkerId, WorkerState>>>,
+    metrics: Arc<Metrics>,
+}
+
+impl<'f, S> Clone for Control<'f, S> {
+    fn clone(&self) -> Self {
+        Self {
+            commands: self.commands.clone(),
+            next_index: self.next_index.clone(),
+            states: self.states.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+impl<'f, S> Control<'f, S> {
+    pub(crate) fn new(
+        metrics: Arc<Metrics>,
+    ) -> (Self, mpsc::UnboundedReceiver<ControlCommand<'f, S>>) {
+        let (commands, commands_rx) = mpsc::unbounded();
+        let control = Self {
+            commands,
+            next_index: Arc::new(AtomicUsize::new(0)),
+            states: Arc::new(Mutex::new(HashMap::new())),
+            metrics,
+        };
+        (control, commands_rx)
+    }
+
+    pub(crate) fn next_id<W>(&self) -> WorkerId {
+        WorkerId::new::<W>(self.next_index.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_state(&self, id: &WorkerId, state: WorkerState) {
+        self.states.lock().unwrap().insert(id.clone(), state);
+    }
+
+    /// Asks the worker to shut down; it is not restarted afterwards.
+    pub fn stop(&self, id: &WorkerId) {
+        self.send(ControlCommand::Stop(id.clone()));
+    }
+
+    /// Every worker started so far, including the ones which have returned.
+    pub fn workers(&self) -> Vec<WorkerStatus> {
+        let states = self.states.lock().unwrap();
+        self.metrics
+            .workers()
+            .into_iter()
+            .filter_map(|worker| {
+                let state = states.get(&worker.id)?.clone();
+                Some(WorkerStatus {
+                    id: worker.id.clone(),
+                    state,
+                    restarts: worker.restarts.load(Ordering::Relaxed),
+                })
+            })
+            .collect()
+    }
+
+    fn send(&self, command: ControlCommand<'f, S>) {
+        if self.commands.unbounded_send(command).is_err() {
+            warn!("App has stopped - control command is ignored");
+        }
+    }
+}
+
+/// The workers `App::run` runs, each with a shutdown of its own so that it
+/// can be stopped alone; the receiving end of `Control`.
+pub(crate) struct Running<'f, S> {
+    futures: FuturesUnordered<Stopped<'f>>,
+    stops: HashMap<WorkerId, ShutdownHandle>,
+    control: Control<'f, S>,
+}
+
+impl<'f, S> Running<'f, S> {
+    pub(crate) fn new(control: Control<'f, S>) -> Self {
+        Self {
+            futures: FuturesUnordered::new(),
+            stops: HashMap::new(),
+            control,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.futures.len()
+    }
+
+    pub(crate) fn start(&mut self, id: WorkerId, runner: Runner<'f>) {
+        let stop = ShutdownHandle::new();
+        let shutdown = stop.subscribe();
+        self.control.set_state(&id, WorkerState::Running);
+        self.stops.insert(id.clone(), stop);
+        self.futures
+            .push(runner(shutdown).map(move |result| (id, result)).boxed());
+    }
+
+    pub(crate) fn apply(&mut self, command: ControlCommand<'f, S>, wiring: &Wiring<'f, S>) {
+        match command {
+            ControlCommand::Spawn(id, wire) => {
+                info!(%id, "spawn worker");
+                let runner = wire(wiring);
+                self.start(id, runner);
+            }
+            ControlCommand::Stop(id) => match self.stops.get(&id) {
+                Some(stop) => {
+                    info!(%id, "stop worker");
+                    self.control.set_state(&id, WorkerState::Stopping);
+                    stop.trigger();
+                }
+                None => warn!(%id, "Worker is not running - nothing to stop"),
+            },
+        }
+    }
+
+    /// Resolves to the next worker which returned, `None` once there are no
+    /// workers left.
+    pub(crate) async fn next(&mut self) -> Option<(WorkerId, Result<(), WorkerError>)> {
+        let (id, result) = self.futures.next().await?;
+        self.stops.remove(&id);
+        let state = match &result {
+            Ok(()) => WorkerState::Stopped,
+            Err(error) => WorkerState::Failed(error.to_string()),
+        };
+        self.control.set_state(&id, state);
+        Some((id, result))
+    }
+
+    pub(crate) fn stop_all(&self) {
+        for (id, stop) in &self.stops {
+            self.control.set_state(id, WorkerState::Stopping);
+            stop.trigger();
+        }
+    }
+
+    /// Drops the workers which are still running and returns their ids.
+    pub(crate) fn abandon(self) -> Vec<WorkerId> {
+        let mut not_stopped: Vec<_> = self.stops.into_keys().collect();
+        not_stopped.sort_by_key(|id| id.index);
+        not_stopped
+    }
+}
+
+impl<'f, S> Control<'f, S>
+where
+    'f: 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    pub fn add_producer<W, Produced>(
+        &self,
+        factory: impl Fn() -> W + Send + Sync + 'static,
+        options: impl Into<WorkerOptions>,
+    ) -> WorkerId
+    where
+        W: worker::ProducerWorker<'f, Produced> + Send + Sync + 'static,
+        Produced: InjectedTo<S> + Serialize + fmt::Debug + Send + Sync + 'f,
+    {
+        let id = self.next_id::<W>();
+        let options = options.into();
+        let wired = id.clone();
+        self.send(ControlCommand::Spawn(
+            id.clone(),
+            Box::new(move |wiring| wiring.producer(wired, factory, options)),
+        ));
+        id
+    }
+
+    pub fn add_scheduled<W, Produced>(
+        &self,
+        schedule: Schedule,
+        factory: impl Fn() -> W + Send + Sync + 'static,
+        options: impl Into<WorkerOptions>,
+    ) -> WorkerId
+    where
+        W: worker::ScheduledWorker<'f, S, Produced> + Send + Sync + 'static,
+        Produced: InjectedTo<S> + Serialize + fmt::Debug + Send + Sync + 'f,
+    {
+        let id = self.next_id::<Scheduled<W, S>>();
+        let options = options.into();
+        let wired = id.clone();
+        self.send(ControlCommand::Spawn(
+            id.clone(),
+            Box::new(move |wiring| wiring.scheduled(wired, schedule, factory, options)),
+        ));
+        id
+    }
+
+    pub fn add_consumer<W, Consumed, E>(
+        &self,
+        factory: impl Fn() -> W + Send + Sync + 'static,
+        options: impl Into<WorkerOptions>,
+    ) -> WorkerId
+    where
+        W: worker::ConsumerWorker<'f, Consumed, E> + Send + Sync + 'static,
+        Consumed: PartialEq + Clone + fmt::Debug + Send + Sync + 'f,
+        E: fmt::Debug + Send + Sync + 'f,
+        S: Reduced<Consumed>,
+    {
+        let id = self.next_id::<W>();
+        let options = options.into();
+        let wired = id.clone();
+        self.send(ControlCommand::Spawn(
+            id.clone(),
+            Box::new(move |wiring| wiring.consumer(wired, factory, options)),
+        ));
+        id
+    }
+
+    pub fn add_worker<W, Consumed, II, Produced, E>(
+        &self,
+        factory: impl Fn() -> W + Send + Sync + 'static,
+        options: impl Into<WorkerOptions>,
+    ) -> WorkerId
+    where
+        W: worker::Worker<'f, Consumed, II, Produced, E> + Send + Sync + 'static,
+        Consumed: PartialEq + Clone + fmt::Debug + Send + Sync + 'f,
+        II: fmt::Debug + Send + Sync + 'f,
+        Produced: InjectedTo<S> + Serialize + fmt::Debug + Send + Sync + 'f,
+        E: fmt::Debug + Send + Sync + 'f,
+        S: Reduced<Consumed>,
+    {
+        let id = self.next_id::<W>();
+        let options = options.into();
+        let wired = id.clone();
+        self.send(ControlCommand::Spawn(
+            id.clone(),
+            Box::new(move |wiring| wiring.worker(wired, factory, options)),
+        ));
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::{future::BoxFuture, SinkExt};
+
+    use super::*;
+    use crate::{shutdown::Shutdown, supervisor::RestartPolicy, worker::ProducerWorker, App};
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Total(u64);
+
+    #[derive(Debug, Serialize)]
+    struct Add(u64);
+
+    impl InjectedTo<Total> for Add {
+        const NAME: &'static str = "Add";
+
+        fn inject_to(self, state: Total) -> Total {
+            Total(state.0 + self.0)
+        }
+    }
+
+    /// Adds once, then waits to be stopped
+    struct Adder(u64);
+
+    impl<'f> ProducerWorker<'f, Add> for Adder {
+        fn work(
+            self: Box<Self>,
+            mut state_tx: mpsc::Sender<Add>,
+            mut shutdown: Shutdown,
+        ) -> BoxFuture<'f, Result<(), WorkerError>> {
+            async move {
+                state_tx.send(Add(self.0)).await?;
+                shutdown.wait().await;
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn spawns_lists_and_stops_workers_while_running() {
+        let app = App::build(Total::default())
+            .add_producer(|| Adder(1), RestartPolicy::Always)
+            .build();
+        let control = app.control();
+        let mut state = app.state();
+
+        let drive = async {
+            let spawned = control.add_producer(|| Adder(10), RestartPolicy::Always);
+            while *state.borrow_and_update() != Total(11) {
+                state.changed().await.unwrap();
+            }
+            let workers = control.workers();
+            assert_eq!(workers.len(), 2);
+            assert!(workers.iter().all(|w| w.state == WorkerState::Running));
+
+            control.stop(&spawned);
+            while control.workers()[1].state != WorkerState::Stopped {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            assert_eq!(control.workers()[0].state, WorkerState::Running);
+            control.stop(&control.workers()[0].id);
+        };
+        let (outcome, ()) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(app.run(), drive)
+        })
+        .await
+        .expect("stopping every worker stops the app");
+
+        assert!(outcome.is_clean());
+        assert_eq!(outcome.restarts, vec![]);
+        assert!(control
+            .workers()
+            .iter()
+            .all(|w| w.state == WorkerState::Stopped));
+    }
+}
@@ -0,0 +1,333 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufWriter, Write},
+    path::Path,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::error;
+
+use crate::{worker::WorkerId, InjectedTo, Reduced};
+
+/// Append-only log of every event injected into the app state, one JSON
+/// object per line, in the order the store applied them. Entries are written
+/// by a thread of their own, so the store never waits for the disk.
+pub struct Journal {
+    entries: Option<mpsc::Sender<JournalEntry>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Milliseconds since the UNIX epoch
+    pub ts: u64,
+    pub worker: String,
+    pub kind: String,
+    pub event: serde_json::Value,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JournalError {
+    #[error("Cannot read journal: {0}")]
+    Io(#[from] io::Error),
+    #[error("Cannot decode journal line {line}: {error}")]
+    Decode {
+        line: usize,
+        error: serde_json::Error,
+    },
+    #[error("Unknown event kind {kind} at journal line {line}")]
+    UnknownKind { line: usize, kind: String },
+}
+
+impl Journal {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (entries, rx) = mpsc::channel();
+        let writer = thread::Builder::new()
+            .name("journal".to_string())
+            .spawn(move || write_entries(BufWriter::new(file), rx))?;
+        Ok(Self {
+            entries: Some(entries),
+            writer: Some(writer),
+        })
+    }
+
+    pub(crate) fn record<S, T>(&mut self, worker: &WorkerId, event: &T)
+    where
+        T: InjectedTo<S> + Serialize,
+    {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let entry = match serde_json::to_value(event) {
+            Ok(event) => JournalEntry {
+                ts,
+                worker: worker.to_string(),
+                kind: T::NAME.to_string(),
+                event,
+            },
+            Err(error) => {
+                error!(%worker, ?error, "Cannot serialize event for journal");
+                return;
+            }
+        };
+        let sent = self.entries.as_ref().map(|entries| entries.send(entry));
+        if !matches!(sent, Some(Ok(()))) {
+            error!(%worker, "Journal writer is gone, event is not recorded");
+        }
+    }
+}
+
+/// Writes whatever is queued and flushes once the queue runs dry.
+fn write_entries(mut writer: BufWriter<File>, entries: mpsc::Receiver<JournalEntry>) {
+    while let Ok(entry) = entries.recv() {
+        let written = std::iter::once(entry)
+            .chain(entries.try_iter())
+            .try_for_each(|entry| {
+                serde_json::to_writer(&mut writer, &entry)?;
+                writer.write_all(b"\n")
+            })
+            .and_then(|_| writer.flush());
+        if let Err(error) = written {
+            error!(?error, "Cannot write events to journal");
+        }
+    }
+}
+
+/// Waits for the writer to drain the queue.
+impl Drop for Journal {
+    fn drop(&mut self) {
+        drop(self.entries.take());
+        if let Some(writer) = self.writer.take() {
+            if writer.join().is_err() {
+                error!("Journal writer panicked");
+            }
+        }
+    }
+}
+
+type Decoder<S> = Box<dyn Fn(S, serde_json::Value) -> Result<S, serde_json::Error>>;
+type Deliver<S> = Box<dyn Fn(&mut S, &mut dyn Any)>;
+
+struct ReplayConsumer<S> {
+    consumed: Box<dyn Any>,
+    deliver: Deliver<S>,
+}
+
+/// Feeds a journal back through the `InjectedTo` and `Reduced` impls of the
+/// app state, without any workers, and collects what every consumer would
+/// have received.
+pub struct Replay<S> {
+    state: S,
+    decoders: HashMap<String, Decoder<S>>,
+    consumers: Vec<ReplayConsumer<S>>,
+}
+
+#[derive(Default)]
+pub struct ReplayOutput {
+    consumed: HashMap<TypeId, Box<dyn Any>>,
+}
+
+impl<S: 'static> Replay<S> {
+    pub fn new(initial_state: S) -> Self {
+        Self {
+            state: initial_state,
+            decoders: HashMap::new(),
+            consumers: Vec::new(),
+        }
+    }
+
+    pub fn event<T>(mut self) -> Self
+    where
+        T: InjectedTo<S> + DeserializeOwned,
+    {
+        let decoder: Decoder<S> = Box::new(|state, value| {
+            serde_json::from_value::<T>(value).map(|event| event.inject_to(state))
+        });
+        self.decoders.insert(T::NAME.to_string(), decoder);
+        self
+    }
+
+    pub fn consumer<T>(mut self) -> Self
+    where
+        S: Reduced<T>,
+        T: PartialEq + 'static,
+    {
+        self.consumers.push(ReplayConsumer {
+            consumed: Box::new(Vec::<T>::new()),
+            deliver: Box::new(|state, consumed| {
+                if let Some(consumed) = consumed.downcast_mut::<Vec<T>>() {
+                    let reduced = state.reduce();
+                    if S::is_new(&reduced, consumed.last()) {
+                        consumed.push(reduced);
+                    }
+                }
+            }),
+        });
+        self
+    }
+
+    /// Consumers get the initial state and then the state after every
+    /// journal entry, as far as `Reduced::is_new` lets it through, just like
+    /// the store delivers it to a consumer which keeps up.
+    pub fn run(mut self, journal: impl BufRead) -> Result<(S, ReplayOutput), JournalError> {
+        self.deliver();
+        for (index, line) in journal.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let decode_error = |error| JournalError::Decode {
+                line: index + 1,
+                error,
+            };
+            let entry: JournalEntry = serde_json::from_str(&line).map_err(decode_error)?;
+            let decoder =
+                self.decoders
+                    .get(&entry.kind)
+                    .ok_or_else(|| JournalError::UnknownKind {
+                        line: index + 1,
+                        kind: entry.kind.clone(),
+                    })?;
+            self.state = decoder(self.state, entry.event).map_err(decode_error)?;
+            self.deliver();
+        }
+
+        let output = ReplayOutput {
+            consumed: self
+                .consumers
+                .into_iter()
+                .map(|consumer| ((*consumer.consumed).type_id(), consumer.consumed))
+                .collect(),
+        };
+        Ok((self.state, output))
+    }
+
+    fn deliver(&mut self) {
+        for consumer in &mut self.consumers {
+            (consumer.deliver)(&mut self.state, consumer.consumed.as_mut());
+        }
+    }
+}
+
+impl ReplayOutput {
+    /// Everything a consumer of `T` received, in order.
+    pub fn consumed<T: 'static>(&self) -> &[T] {
+        self.consumed
+            .get(&TypeId::of::<Vec<T>>())
+            .and_then(|consumed| consumed.downcast_ref::<Vec<T>>())
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufReader, Cursor},
+        time::Duration,
+    };
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::testing::{Harness, Recording, Script};
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Total(i64);
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct Add(i64);
+
+    impl InjectedTo<Total> for Add {
+        const NAME: &'static str = "Add";
+
+        fn inject_to(self, state: Total) -> Total {
+            Total(state.0 + self.0)
+        }
+    }
+
+    impl Reduced<Total> for Total {
+        fn reduce(&mut self) -> Total {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn replay_reproduces_recorded_events() {
+        let path = std::env::temp_dir().join(format!("journal-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let worker = WorkerId::new::<Add>(0);
+        {
+            let mut journal = Journal::create(&path).unwrap();
+            for value in [1, 2, -5] {
+                journal.record::<Total, _>(&worker, &Add(value));
+            }
+        }
+
+        let file = BufReader::new(File::open(&path).unwrap());
+        let (state, output) = Replay::new(Total::default())
+            .event::<Add>()
+            .consumer::<Total>()
+            .run(file)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(state, Total(-2));
+        assert_eq!(
+            output.consumed::<Total>(),
+            &[Total(0), Total(1), Total(3), Total(-2)]
+        );
+    }
+
+    #[test]
+    fn replay_rejects_unknown_events() {
+        let line = r#"{"ts":0,"worker":"X#0","kind":"Nope","event":null}"#;
+        let result = Replay::new(Total::default())
+            .event::<Add>()
+            .run(Cursor::new(line));
+
+        assert!(matches!(
+            result,
+            Err(JournalError::UnknownKind { line: 1, .. })
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replay_delivers_what_the_app_delivered() {
+        let path = std::env::temp_dir().join(format!("journal-live-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let totals = Recording::new();
+        let script = Script::new()
+            .send(Add(1))
+            .sleep(Duration::from_secs(1))
+            .send(Add(0))
+            .sleep(Duration::from_secs(1))
+            .send(Add(2));
+        let journal = Journal::create(&path).unwrap();
+        Harness::new(Total::default())
+            .with(|app| app.journal(journal))
+            .script(script)
+            .record(&totals)
+            .run()
+            .await;
+
+        let file = BufReader::new(File::open(&path).unwrap());
+        let (_, output) = Replay::new(Total::default())
+            .event::<Add>()
+            .consumer::<Total>()
+            .run(file)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // `Add(0)` changes nothing, so neither delivers it
+        assert_eq!(totals.values(), vec![Total(0), Total(1), Total(3)]);
+        assert_eq!(output.consumed::<Total>(), totals.values());
+    }
+}
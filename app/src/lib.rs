@@ -1,215 +1,425 @@
 use core::fmt;
+use std::{
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
 
+#[cfg(feature = "derive")]
+pub use app_derive::AppState;
+use checkpoint::{Checkpoint, Checkpointer};
+use control::{Control, ControlCommand, Running};
+use futures::future::FusedFuture;
 pub use futures::{
     channel::mpsc, future::BoxFuture, stream::FuturesUnordered, FutureExt, SinkExt, StreamExt,
 };
 pub use futures::{Sink, Stream};
-use tokio::sync::watch;
-use tokio_stream::wrappers::WatchStream;
-use tracing::{debug, error, info};
+use journal::Journal;
+use metrics::Metrics;
+use outcome::AppOutcome;
+use registry::{Registry, RegistryError, WorkerSpec};
+use serde::{de::DeserializeOwned, Serialize};
+use shutdown::{termination_signal, Shutdown, ShutdownHandle};
+use store::Store;
+use supervisor::FailurePolicy;
+use tokio::{
+    sync::watch,
+    time::{Instant, Interval, MissedTickBehavior},
+};
+use tracing::{error, info, warn};
+use wiring::{Scheduled, Subscriptions, Wiring};
+use worker::{Schedule, WorkerError, WorkerId, WorkerOptions};
 
+pub mod checkpoint;
+pub mod control;
+pub mod journal;
+pub mod metrics;
+pub mod outcome;
+mod queue;
+pub mod registry;
+pub mod shutdown;
+mod store;
+pub mod supervisor;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod wiring;
 pub mod worker;
 
+const DEFAULT_SHUTDOWN_DEADLINE: Duration = Duration::from_secs(5);
+
+type Runner<'f> = Box<dyn FnOnce(Shutdown) -> BoxFuture<'f, Result<(), WorkerError>> + Send + 'f>;
+
 pub trait Reduced<T> {
     fn reduce(&mut self) -> T;
+
+    /// Whether a consumer which last got `last` has to get `reduced` too. A
+    /// value equal to the last one tells it nothing new, unless `reduce`
+    /// takes values out of the state.
+    fn is_new(reduced: &T, last: Option<&T>) -> bool
+    where
+        T: PartialEq,
+    {
+        last != Some(reduced)
+    }
 }
 
 pub trait InjectedTo<S> {
+    /// Names the event in the journal, so it has to stay the same across
+    /// releases for old journals to replay
+    const NAME: &'static str;
+
     fn inject_to(self, state: S) -> S;
 }
 
 pub struct App<'f, S> {
     state: watch::Receiver<S>,
-    state_updater: BoxFuture<'f, ()>,
-    runners: Vec<Box<dyn FnOnce(watch::Receiver<S>) -> BoxFuture<'f, ()>>>,
+    store: BoxFuture<'f, ()>,
+    runners: Vec<(WorkerId, Runner<'f>)>,
+    wiring: Wiring<'f, S>,
+    control: Control<'f, S>,
+    control_rx: mpsc::UnboundedReceiver<ControlCommand<'f, S>>,
+    metrics: Arc<Metrics>,
+    metrics_addr: Option<SocketAddr>,
+    shutdown: ShutdownHandle,
+    shutdown_deadline: Duration,
+    failure_policy: FailurePolicy,
+    checkpointer: Option<Checkpointer<S>>,
 }
 
 pub struct AppBuilder<'f, S> {
-    runners: Vec<Box<dyn FnOnce(watch::Receiver<S>) -> BoxFuture<'f, ()>>>,
-    state_updater: BoxFuture<'f, ()>,
-    state_tx: mpsc::Sender<S>,
+    runners: Vec<(WorkerId, Runner<'f>)>,
+    wiring: Wiring<'f, S>,
+    control: Control<'f, S>,
+    control_rx: mpsc::UnboundedReceiver<ControlCommand<'f, S>>,
+    metrics: Arc<Metrics>,
+    metrics_addr: Option<SocketAddr>,
+    store: Store<'f, S>,
     state_rx: watch::Receiver<S>,
+    shutdown_deadline: Duration,
+    failure_policy: FailurePolicy,
+    checkpointer: Option<Checkpointer<S>>,
 }
 
 impl<'f, S> App<'f, S>
 where
-    S: fmt::Debug + Send + 'f + Sync,
+    S: fmt::Debug + Clone + Send + 'f + Sync,
 {
     pub fn build(initial_state: S) -> AppBuilder<'f, S> {
-        let (state_tx, mut state_rx) = mpsc::channel(1);
-        let (wstate_tx, wstate_rx) = watch::channel(initial_state);
-        let state_updater = async move {
-            while let Some(new_state) = state_rx.next().await {
-                wstate_tx.send(new_state).unwrap();
-            }
-        }
-        .boxed();
+        let metrics = Arc::new(Metrics::new());
+        let (store, store_tx, state_rx) = Store::new(initial_state, metrics.clone());
+        let (control, control_rx) = Control::new(metrics.clone());
 
         AppBuilder {
             runners: Vec::new(),
-            state_updater,
-            state_tx,
-            state_rx: wstate_rx,
+            wiring: Wiring {
+                metrics: metrics.clone(),
+                store_tx,
+                state_rx: state_rx.clone(),
+                subscriptions: Subscriptions::new(),
+                failure_policies: Default::default(),
+            },
+            control,
+            control_rx,
+            metrics,
+            metrics_addr: None,
+            store,
+            state_rx,
+            shutdown_deadline: DEFAULT_SHUTDOWN_DEADLINE,
+            failure_policy: FailurePolicy::default(),
+            checkpointer: None,
         }
     }
 
-    pub async fn run(self) {
-        let mut futures = FuturesUnordered::new();
-        futures.push(self.state_updater);
-        for runner in self.runners {
-            futures.push(runner(self.state.clone()));
+    pub fn state(&self) -> watch::Receiver<S> {
+        self.state.clone()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    pub fn control(&self) -> Control<'f, S> {
+        self.control.clone()
+    }
+
+    /// Resolves once every consumer has had the state reduced for it once.
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn subscribed(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        self.wiring.subscriptions.all()
+    }
+
+    /// Runs workers until they all return or shutdown is requested (by
+    /// `ShutdownHandle` or SIGINT/SIGTERM), then waits up to the shutdown
+    /// deadline for the rest of them to stop. A worker failure stops the app
+    /// too under `FailurePolicy::AbortAll`.
+    pub async fn run(mut self) -> AppOutcome {
+        let mut running = Running::new(self.control.clone());
+        for (id, runner) in self.runners {
+            running.start(id, runner);
+        }
+        while let Ok(Some(command)) = self.control_rx.try_next() {
+            running.apply(command, &self.wiring);
         }
 
-        let total_len = futures.len();
+        let total_len = running.len();
         info!(?total_len, "run futures");
 
-        while futures.next().await.is_some() {}
-        info!("all futures returned");
+        let signals = {
+            let shutdown = self.shutdown.clone();
+            tokio::spawn(async move {
+                termination_signal().await;
+                shutdown.shutdown();
+            })
+        };
+        let metrics_server = match self.metrics_addr {
+            Some(addr) => metrics::bind(addr)
+                .await
+                .map(|listener| tokio::spawn(metrics::serve(listener, self.metrics.clone()))),
+            None => None,
+        };
+
+        let mut checkpoints = self
+            .checkpointer
+            .as_ref()
+            .and_then(Checkpointer::interval)
+            .map(|period| {
+                let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                interval
+            });
+
+        let mut failures = Vec::new();
+        let mut aborted = false;
+        let mut store = self.store.fuse();
+        let mut shutdown = self.shutdown.subscribe();
+        loop {
+            tokio::select! {
+                stopped = running.next() => match stopped {
+                    Some((id, Ok(()))) => {
+                        info!(%id, "worker returned");
+                    }
+                    Some((id, Err(error))) => {
+                        let policy = self.wiring.failure_policy(&id).unwrap_or(self.failure_policy);
+                        error!(%id, %error, ?policy, "worker failed");
+                        failures.push((id, error));
+                        if policy == FailurePolicy::AbortAll {
+                            aborted = true;
+                            break;
+                        }
+                    }
+                    None => break,
+                },
+                // `self.control` keeps the channel open, so it never ends
+                Some(command) = self.control_rx.next() => running.apply(command, &self.wiring),
+                _ = &mut store => {},
+                _ = shutdown.wait() => break,
+                _ = next_checkpoint(&mut checkpoints) => {
+                    if let Some(checkpointer) = &mut self.checkpointer {
+                        let state = self.state.borrow().clone();
+                        checkpointer.save_in_background(state);
+                    }
+                }
+            }
+        }
+
+        signals.abort();
+        if let Some(server) = metrics_server {
+            server.abort();
+        }
+        self.shutdown.shutdown();
+        running.stop_all();
+        let deadline = self.shutdown_deadline;
+        let drain = async {
+            while let Some((id, result)) = running.next().await {
+                info!(%id, "worker stopped");
+                if let Err(error) = result {
+                    failures.push((id, error));
+                }
+            }
+        };
+        if tokio::time::timeout(deadline, drain).await.is_err() {
+            warn!(?deadline, "some workers did not stop in time");
+        }
+        // Dropping the remaining workers closes the store, which then applies
+        // whatever is still queued and stops.
+        let not_stopped = running.abandon();
+        drop(self.wiring);
+        if !store.is_terminated() {
+            store.await;
+        }
+        if let Some(checkpointer) = &mut self.checkpointer {
+            let state = self.state.borrow().clone();
+            checkpointer.save(state).await;
+        }
+
+        let restarts = self
+            .metrics
+            .workers()
+            .into_iter()
+            .map(|worker| (worker.id.clone(), worker.restarts.load(Ordering::Relaxed)))
+            .filter(|(_, restarts)| *restarts > 0)
+            .collect();
+        let outcome = AppOutcome {
+            deadline,
+            not_stopped,
+            restarts,
+            failures,
+            aborted,
+        };
+        info!(?outcome, "app stopped");
+        outcome
+    }
+}
+
+async fn next_checkpoint(checkpoints: &mut Option<Interval>) {
+    match checkpoints {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => futures::future::pending().await,
     }
 }
 
 impl<'f, AppState> AppBuilder<'f, AppState>
 where
-    AppState: Eq + Clone + Send + Sync + 'static,
+    AppState: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
 {
-    async fn state_reducer<WorkerState, E>(
-        mut state_stream: impl Stream<Item = AppState> + Unpin,
-        mut state_sink: impl Sink<AppState> + Unpin,
-        mut consumer: impl Sink<WorkerState, Error = E> + Send + Unpin,
-    ) where
-        WorkerState: Send + fmt::Debug,
-        E: fmt::Debug + Send + Sync,
-        AppState: Reduced<WorkerState>,
-    {
-        while let Some(mut state) = state_stream.next().await {
-            let old_state = state.clone(); // this seems like a wrong idea here
-            let worker_data = state.reduce();
-
-            consumer
-                .send(worker_data)
-                .await
-                .expect("Cannot send data to channel");
-            if old_state != state {
-                state_sink.send(state).await;
+    /// Restores the initial state from the checkpoint, if there is a
+    /// compatible one, and keeps snapshotting the state while the app runs.
+    pub fn checkpoint(mut self, checkpoint: Checkpoint) -> Self {
+        let path = checkpoint.path().to_path_buf();
+        match checkpoint.load() {
+            Ok(Some(state)) => {
+                info!(?path, "restore state from checkpoint");
+                self.store.restore(state);
             }
+            Ok(None) => info!(?path, "no checkpoint yet - start from initial state"),
+            Err(error) => warn!(?path, %error, "checkpoint rejected - start from initial state"),
         }
+        self.checkpointer = Some(Checkpointer::new(checkpoint));
+        self
     }
+}
 
-    async fn state_injector<WorkerState, SinkError>(
-        mut provider_stream: impl Stream<Item = WorkerState> + Unpin,
-        app_state_watch: watch::Receiver<AppState>,
-        mut app_state_sink: impl Sink<AppState, Error = SinkError> + Unpin,
-    ) where
-        WorkerState: InjectedTo<AppState> + fmt::Debug,
-        SinkError: fmt::Debug,
-    {
-        while let Some(data) = provider_stream.next().await {
-            debug!(?data, "Got some data");
+impl<'f, AppState> AppBuilder<'f, AppState>
+where
+    'f: 'static,
+    AppState: Clone + Send + Sync + 'static,
+{
+    /// A handle to add workers while the app runs, e.g. from a worker which
+    /// itself is added here.
+    pub fn control(&self) -> Control<'f, AppState> {
+        self.control.clone()
+    }
 
-            let prev_state = app_state_watch.borrow().clone();
-            let new_state = data.inject_to(prev_state);
-            app_state_sink
-                .send(new_state)
-                .await
-                .expect("Cannot send data to channel");
-        }
+    /// Serves Prometheus metrics on `GET /metrics` while the app runs.
+    pub fn metrics(mut self, addr: SocketAddr) -> Self {
+        self.metrics_addr = Some(addr);
+        self
+    }
+
+    /// Records every injected event, see `journal::Replay` to play it back.
+    pub fn journal(mut self, journal: Journal) -> Self {
+        self.store.journal(journal);
+        self
+    }
+
+    pub fn on_failure(mut self, policy: FailurePolicy) -> Self {
+        self.failure_policy = policy;
+        self
+    }
+
+    pub fn shutdown_deadline(mut self, deadline: Duration) -> Self {
+        self.shutdown_deadline = deadline;
+        self
+    }
+
+    /// Adds a worker for every spec, built by the factory registered under
+    /// its `kind`.
+    pub fn add_workers(
+        self,
+        registry: &Registry<'f, AppState>,
+        specs: &[WorkerSpec],
+    ) -> Result<Self, RegistryError> {
+        specs
+            .iter()
+            .try_fold(self, |builder, spec| registry.add(builder, spec))
     }
 
-    pub fn add_producer<WorkerState>(
+    /// Every `add_*` takes a factory rather than a worker, so that a worker
+    /// which panicked or returned early can be rebuilt according to its
+    /// `RestartPolicy`.
+    pub fn add_producer<W, WorkerState>(
         mut self,
-        worker: impl worker::ProducerWorker<'f, WorkerState> + Send + Sync + 'static,
+        factory: impl Fn() -> W + Send + Sync + 'static,
+        options: impl Into<WorkerOptions>,
     ) -> Self
     where
-        WorkerState: InjectedTo<AppState> + fmt::Debug + Send + Sync + 'f,
+        W: worker::ProducerWorker<'f, WorkerState> + Send + Sync + 'static,
+        WorkerState: InjectedTo<AppState> + Serialize + fmt::Debug + Send + Sync + 'f,
     {
-        let state_update = self.state_tx.clone();
-        let worker = Box::new(worker);
-        let runner = |state_channel: watch::Receiver<AppState>| {
-            let mut data_futures = Vec::new();
-            let (inducer_tx, inducer_rx) = mpsc::channel::<WorkerState>(100);
-            // `state_reducer and `state_mapper` propagate state to each consumer
-            data_futures
-                .push(Self::state_injector(inducer_rx, state_channel, state_update).boxed());
-            data_futures.push(worker.work(inducer_tx));
-
-            async move {
-                futures::future::join_all(data_futures).await;
-            }
-            .boxed()
-        };
+        let id = self.control.next_id::<W>();
+        let runner = self.wiring.producer(id.clone(), factory, options.into());
+        self.runners.push((id, runner));
+        self
+    }
 
-        self.runners.push(Box::new(runner));
+    /// Runs the worker on `schedule`, injecting whatever its ticks return.
+    pub fn add_scheduled<W, WorkerState>(
+        mut self,
+        schedule: Schedule,
+        factory: impl Fn() -> W + Send + Sync + 'static,
+        options: impl Into<WorkerOptions>,
+    ) -> Self
+    where
+        W: worker::ScheduledWorker<'f, AppState, WorkerState> + Send + Sync + 'static,
+        WorkerState: InjectedTo<AppState> + Serialize + fmt::Debug + Send + Sync + 'f,
+    {
+        let id = self.control.next_id::<Scheduled<W, AppState>>();
+        let runner = self
+            .wiring
+            .scheduled(id.clone(), schedule, factory, options.into());
+        self.runners.push((id, runner));
         self
     }
 
-    pub fn add_consumer<WorkerState, E>(
+    pub fn add_consumer<W, WorkerState, E>(
         mut self,
-        worker: impl worker::ConsumerWorker<'f, WorkerState, E> + Send + Sync + 'static,
+        factory: impl Fn() -> W + Send + Sync + 'static,
+        options: impl Into<WorkerOptions>,
     ) -> Self
     where
-        WorkerState: fmt::Debug + Send + Sync + 'f,
+        W: worker::ConsumerWorker<'f, WorkerState, E> + Send + Sync + 'static,
+        WorkerState: PartialEq + Clone + fmt::Debug + Send + Sync + 'f,
         E: fmt::Debug + Send + Sync + 'f,
         AppState: Reduced<WorkerState>,
     {
-        let worker = Box::new(worker);
-        let state_tx = self.state_tx.clone();
-        let runner = |state_channel: watch::Receiver<AppState>| {
-            let mut data_futures = Vec::new();
-
-            let state_stream = WatchStream::new(state_channel);
-
-            let (reduced_state_tx, reduced_state_rx) = worker.provide_input_stream();
-
-            data_futures
-                .push(Self::state_reducer(state_stream, state_tx, reduced_state_tx).boxed());
-            data_futures.push(worker.work(reduced_state_rx));
-
-            async move {
-                futures::future::join_all(data_futures).await;
-            }
-            .boxed()
-        };
-
-        self.runners.push(Box::new(runner));
+        let id = self.control.next_id::<W>();
+        let runner = self.wiring.consumer(id.clone(), factory, options.into());
+        self.runners.push((id, runner));
         self
     }
 
-    pub fn add_worker<Consumed, II, Produced, E>(
+    pub fn add_worker<W, Consumed, II, Produced, E>(
         mut self,
-        worker: impl worker::Worker<'f, Consumed, II, Produced, E> + Send + Sync + 'static,
+        factory: impl Fn() -> W + Send + Sync + 'static,
+        options: impl Into<WorkerOptions>,
     ) -> Self
     where
-        Consumed: fmt::Debug + Send + Sync + 'f,
+        W: worker::Worker<'f, Consumed, II, Produced, E> + Send + Sync + 'static,
+        Consumed: PartialEq + Clone + fmt::Debug + Send + Sync + 'f,
         II: fmt::Debug + Send + Sync + 'f,
-        Produced: InjectedTo<AppState> + fmt::Debug + Send + Sync + 'f,
+        Produced: InjectedTo<AppState> + Serialize + fmt::Debug + Send + Sync + 'f,
         E: fmt::Debug + Send + Sync + 'f,
         AppState: Reduced<Consumed>,
     {
-        let state_update = self.state_tx.clone();
-        let worker = Box::new(worker);
-        let runner = |state_channel: watch::Receiver<AppState>| {
-            let mut data_futures = Vec::new();
-            let (inducer_tx, inducer_rx) = mpsc::channel::<Produced>(100);
-
-            let state_stream = WatchStream::new(state_channel.clone());
-
-            let (reduced_state_tx, reduced_state_rx) = worker.provide_input_stream();
-
-            // `state_reducer and `state_mapper` propagate state to each consumer
-            data_futures.push(
-                Self::state_reducer(state_stream, state_update.clone(), reduced_state_tx).boxed(),
-            );
-            data_futures
-                .push(Self::state_injector(inducer_rx, state_channel, state_update).boxed());
-            data_futures.push(worker.work(reduced_state_rx, inducer_tx));
-
-            async move {
-                futures::future::join_all(data_futures).await;
-            }
-            .boxed()
-        };
-
-        self.runners.push(Box::new(runner));
+        let id = self.control.next_id::<W>();
+        let runner = self.wiring.worker(id.clone(), factory, options.into());
+        self.runners.push((id, runner));
         self
     }
 
@@ -217,7 +427,16 @@ where
         App {
             state: self.state_rx,
             runners: self.runners,
-            state_updater: self.state_updater,
+            wiring: self.wiring,
+            control: self.control,
+            control_rx: self.control_rx,
+            metrics: self.metrics,
+            metrics_addr: self.metrics_addr,
+            store: self.store.run().boxed(),
+            shutdown: ShutdownHandle::new(),
+            shutdown_deadline: self.shutdown_deadline,
+            failure_policy: self.failure_policy,
+            checkpointer: self.checkpointer,
         }
     }
 }
@@ -0,0 +1,306 @@
+use std::{
+    fmt::{Display, Write as _},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, info, warn};
+
+use crate::worker::{Backpressure, WorkerId};
+
+/// Runtime counters of an `App`, rendered in the Prometheus text format.
+#[derive(Debug)]
+pub struct Metrics {
+    state_updates: AtomicU64,
+    store_queue: AtomicI64,
+    workers: Mutex<Vec<Arc<WorkerMetrics>>>,
+    last_render: Mutex<(Instant, u64)>,
+}
+
+#[derive(Debug)]
+pub(crate) struct WorkerMetrics {
+    pub(crate) id: WorkerId,
+    pub(crate) produced: AtomicU64,
+    pub(crate) consumed: AtomicU64,
+    /// Values waiting in the worker's queue
+    pub(crate) queued: AtomicI64,
+    /// Values its `Backpressure` dropped from a full queue
+    pub(crate) dropped: AtomicU64,
+    pub(crate) backpressure: Backpressure,
+    pub(crate) restarts: Arc<AtomicUsize>,
+    pub(crate) inject: Timing,
+    pub(crate) reduce: Timing,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Timing {
+    count: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl Timing {
+    pub(crate) fn record(&self, elapsed: Duration) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn time<T>(&self, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.record(started.elapsed());
+        result
+    }
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        Self {
+            state_updates: AtomicU64::new(0),
+            store_queue: AtomicI64::new(0),
+            workers: Mutex::new(Vec::new()),
+            last_render: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    pub(crate) fn worker(&self, id: WorkerId, backpressure: Backpressure) -> Arc<WorkerMetrics> {
+        let worker = Arc::new(WorkerMetrics {
+            id,
+            produced: AtomicU64::new(0),
+            consumed: AtomicU64::new(0),
+            queued: AtomicI64::new(0),
+            dropped: AtomicU64::new(0),
+            backpressure,
+            restarts: Arc::new(AtomicUsize::new(0)),
+            inject: Timing::default(),
+            reduce: Timing::default(),
+        });
+        self.workers.lock().unwrap().push(worker.clone());
+        worker
+    }
+
+    pub(crate) fn workers(&self) -> Vec<Arc<WorkerMetrics>> {
+        self.workers.lock().unwrap().clone()
+    }
+
+    pub(crate) fn state_updated(&self) {
+        self.state_updates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn store_queued(&self, delta: i64) {
+        self.store_queue.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    /// `app_state_updates_per_second` is the rate since the previous render.
+    pub fn render(&self) -> String {
+        let state_updates = self.state_updates.load(Ordering::Relaxed);
+        let per_second = {
+            let mut last = self.last_render.lock().unwrap();
+            let (at, updates) = *last;
+            *last = (Instant::now(), state_updates);
+            let elapsed = at.elapsed().as_secs_f64();
+            if elapsed > 0.0 {
+                (state_updates - updates) as f64 / elapsed
+            } else {
+                0.0
+            }
+        };
+
+        let mut out = String::new();
+        metric(&mut out, "app_state_updates_total", "counter");
+        writeln!(out, "app_state_updates_total {state_updates}").ok();
+        metric(&mut out, "app_state_updates_per_second", "gauge");
+        writeln!(out, "app_state_updates_per_second {per_second}").ok();
+        metric(&mut out, "app_store_queue_length", "gauge");
+        writeln!(
+            out,
+            "app_store_queue_length {}",
+            self.store_queue.load(Ordering::Relaxed)
+        )
+        .ok();
+
+        let workers = self.workers();
+        let load = |value: &AtomicU64| value.load(Ordering::Relaxed);
+        per_worker(
+            &mut out,
+            "app_worker_produced_total",
+            "counter",
+            &workers,
+            |w| load(&w.produced),
+        );
+        per_worker(
+            &mut out,
+            "app_worker_consumed_total",
+            "counter",
+            &workers,
+            |w| load(&w.consumed),
+        );
+        per_worker(
+            &mut out,
+            "app_worker_queue_length",
+            "gauge",
+            &workers,
+            |w| w.queued.load(Ordering::Relaxed),
+        );
+        per_worker(
+            &mut out,
+            "app_worker_dropped_total",
+            "counter",
+            &workers,
+            |w| load(&w.dropped),
+        );
+        metric(&mut out, "app_worker_backpressure", "gauge");
+        for worker in &workers {
+            writeln!(
+                out,
+                "app_worker_backpressure{{worker=\"{}\",policy=\"{}\"}} 1",
+                worker.id, worker.backpressure
+            )
+            .ok();
+        }
+        per_worker(
+            &mut out,
+            "app_worker_restarts_total",
+            "counter",
+            &workers,
+            |w| w.restarts.load(Ordering::Relaxed),
+        );
+        timings(&mut out, "app_inject_duration_seconds", &workers, |w| {
+            &w.inject
+        });
+        timings(&mut out, "app_reduce_duration_seconds", &workers, |w| {
+            &w.reduce
+        });
+        out
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str) {
+    writeln!(out, "# TYPE {name} {kind}").ok();
+}
+
+fn per_worker<T: Display>(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    workers: &[Arc<WorkerMetrics>],
+    value: impl Fn(&WorkerMetrics) -> T,
+) {
+    metric(out, name, kind);
+    for worker in workers {
+        writeln!(out, "{name}{{worker=\"{}\"}} {}", worker.id, value(worker)).ok();
+    }
+}
+
+fn timings(
+    out: &mut String,
+    name: &str,
+    workers: &[Arc<WorkerMetrics>],
+    timing: impl Fn(&WorkerMetrics) -> &Timing,
+) {
+    metric(out, name, "summary");
+    for worker in workers {
+        let timing = timing(worker);
+        let seconds = timing.nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let count = timing.count.load(Ordering::Relaxed);
+        writeln!(out, "{name}_sum{{worker=\"{}\"}} {seconds}", worker.id).ok();
+        writeln!(out, "{name}_count{{worker=\"{}\"}} {count}", worker.id).ok();
+    }
+}
+
+/// Serves `GET /metrics` until the listener fails.
+pub(crate) async fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    info!(addr = ?listener.local_addr().ok(), "serve metrics");
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = respond(stream, &metrics).await {
+                        debug!(?peer, ?error, "Cannot serve metrics");
+                    }
+                });
+            }
+            Err(error) => {
+                warn!(?error, "Metrics listener failed");
+                return;
+            }
+        }
+    }
+}
+
+pub(crate) async fn bind(addr: SocketAddr) -> Option<TcpListener> {
+    match TcpListener::bind(addr).await {
+        Ok(listener) => Some(listener),
+        Err(error) => {
+            warn!(
+                ?addr,
+                ?error,
+                "Cannot bind metrics endpoint - run without it"
+            );
+            None
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let mut request = [0; 1024];
+    let read = stream.read(&mut request).await?;
+    let request = String::from_utf8_lossy(&request[..read]);
+    let path = request.split_whitespace().nth(1);
+
+    let response = if request.starts_with("GET ") && path == Some("/metrics") {
+        let body = metrics.render();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_worker_metrics() {
+        let metrics = Arc::new(Metrics::new());
+        let worker = metrics.worker(WorkerId::new::<Metrics>(0), Backpressure::DropOldest);
+        worker.produced.fetch_add(3, Ordering::Relaxed);
+        worker.inject.record(Duration::from_millis(500));
+        metrics.state_updated();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, metrics));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        server.abort();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("app_state_updates_total 1\n"));
+        assert!(response.contains("app_worker_produced_total{worker=\"Metrics#0\"} 3\n"));
+        assert!(response
+            .contains("app_worker_backpressure{worker=\"Metrics#0\",policy=\"drop_oldest\"} 1\n"));
+        assert!(response.contains("app_inject_duration_seconds_sum{worker=\"Metrics#0\"} 0.5\n"));
+        assert!(response.contains("app_inject_duration_seconds_count{worker=\"Metrics#0\"} 1\n"));
+    }
+}
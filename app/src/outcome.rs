@@ -0,0 +1,21 @@
+use std::time::Duration;
+
+use crate::worker::{WorkerError, WorkerId};
+
+/// What `App::run` ended with.
+#[derive(Debug, Default)]
+pub struct AppOutcome {
+    pub deadline: Duration,
+    pub not_stopped: Vec<WorkerId>,
+    pub restarts: Vec<(WorkerId, usize)>,
+    /// Workers which failed and were not restarted, in the order they failed
+    pub failures: Vec<(WorkerId, WorkerError)>,
+    /// Whether a failure stopped the app under `FailurePolicy::AbortAll`
+    pub aborted: bool,
+}
+
+impl AppOutcome {
+    pub fn is_clean(&self) -> bool {
+        self.not_stopped.is_empty() && self.failures.is_empty()
+    }
+}
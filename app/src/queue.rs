@@ -0,0 +1,230 @@
+use std::{
+    collections::VecDeque,
+    sync::{atomic::Ordering, Arc, Mutex},
+};
+
+use futures::{stream, Stream, StreamExt};
+use tokio::sync::Notify;
+
+use crate::{metrics::WorkerMetrics, worker::Backpressure};
+
+/// Bounded queue in front of a worker; what happens once it is full is up
+/// to the worker's `Backpressure`.
+pub(crate) fn bounded<T>(
+    backpressure: Backpressure,
+    capacity: usize,
+    worker: Arc<WorkerMetrics>,
+) -> (Sender<T>, Receiver<T>) {
+    let capacity = match backpressure {
+        Backpressure::CoalesceLatest => 1,
+        _ => capacity.max(1),
+    };
+    let shared = Arc::new(Shared {
+        backpressure,
+        capacity,
+        inner: Mutex::new(Inner {
+            items: VecDeque::new(),
+            sender: true,
+            receiver: true,
+        }),
+        pushed: Notify::new(),
+        popped: Notify::new(),
+        worker,
+    });
+    (Sender(shared.clone()), Receiver(shared))
+}
+
+struct Shared<T> {
+    backpressure: Backpressure,
+    capacity: usize,
+    inner: Mutex<Inner<T>>,
+    pushed: Notify,
+    popped: Notify,
+    worker: Arc<WorkerMetrics>,
+}
+
+struct Inner<T> {
+    items: VecDeque<T>,
+    sender: bool,
+    receiver: bool,
+}
+
+impl<T> Shared<T> {
+    fn push(&self, inner: &mut Inner<T>, value: T) {
+        inner.items.push_back(value);
+        self.queued(inner);
+        self.pushed.notify_one();
+    }
+
+    fn dropped(&self, count: usize) {
+        self.worker
+            .dropped
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    fn queued(&self, inner: &Inner<T>) {
+        self.worker
+            .queued
+            .store(inner.items.len() as i64, Ordering::Relaxed);
+    }
+}
+
+pub(crate) struct Sender<T>(Arc<Shared<T>>);
+
+pub(crate) struct Receiver<T>(Arc<Shared<T>>);
+
+impl<T> Sender<T> {
+    /// Only `Backpressure::Block` ever waits here. `false` once the receiver
+    /// is gone.
+    pub(crate) async fn send(&self, value: T) -> bool {
+        let shared = &self.0;
+        let mut value = Some(value);
+        loop {
+            {
+                let mut inner = shared.inner.lock().unwrap();
+                if !inner.receiver {
+                    return false;
+                }
+                if inner.items.len() < shared.capacity {
+                    shared.push(&mut inner, value.take().unwrap());
+                    return true;
+                }
+                match shared.backpressure {
+                    Backpressure::Block => {}
+                    Backpressure::DropOldest => {
+                        inner.items.pop_front();
+                        shared.dropped(1);
+                        shared.push(&mut inner, value.take().unwrap());
+                        return true;
+                    }
+                    Backpressure::DropNewest => {
+                        shared.dropped(1);
+                        return true;
+                    }
+                    Backpressure::CoalesceLatest => {
+                        shared.dropped(inner.items.len());
+                        inner.items.clear();
+                        shared.push(&mut inner, value.take().unwrap());
+                        return true;
+                    }
+                }
+            }
+            shared.popped.notified().await;
+        }
+    }
+
+    /// Moves what `stream` yields into the queue until either of them ends.
+    pub(crate) async fn send_all(self, stream: impl Stream<Item = T>) {
+        futures::pin_mut!(stream);
+        while let Some(value) = stream.next().await {
+            if !self.send(value).await {
+                return;
+            }
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// `None` once the sender is gone and the queue is drained.
+    pub(crate) async fn recv(&self) -> Option<T> {
+        let shared = &self.0;
+        loop {
+            {
+                let mut inner = shared.inner.lock().unwrap();
+                if let Some(value) = inner.items.pop_front() {
+                    shared.queued(&inner);
+                    shared.popped.notify_one();
+                    return Some(value);
+                }
+                if !inner.sender {
+                    return None;
+                }
+            }
+            shared.pushed.notified().await;
+        }
+    }
+
+    pub(crate) fn into_stream(self) -> impl Stream<Item = T> {
+        stream::unfold(self, |queue| async move {
+            let value = queue.recv().await?;
+            Some((value, queue))
+        })
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.0.inner.lock().unwrap().sender = false;
+        self.0.pushed.notify_one();
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.0.inner.lock().unwrap().receiver = false;
+        self.0.popped.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{metrics::Metrics, worker::WorkerId};
+
+    fn queue(backpressure: Backpressure) -> (Sender<u32>, Receiver<u32>, Arc<WorkerMetrics>) {
+        let worker = Metrics::new().worker(WorkerId::new::<u32>(0), backpressure);
+        let (tx, rx) = bounded(backpressure, 2, worker.clone());
+        (tx, rx, worker)
+    }
+
+    async fn drain(tx: Sender<u32>, rx: Receiver<u32>) -> Vec<u32> {
+        drop(tx);
+        let mut received = Vec::new();
+        while let Some(value) = rx.recv().await {
+            received.push(value);
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn full_queue_follows_its_backpressure() {
+        for (backpressure, expected, dropped) in [
+            (Backpressure::DropOldest, vec![3, 4], 2),
+            (Backpressure::DropNewest, vec![1, 2], 2),
+            (Backpressure::CoalesceLatest, vec![4], 3),
+        ] {
+            let (tx, rx, worker) = queue(backpressure);
+            for value in 1..=4 {
+                assert!(tx.send(value).await);
+            }
+            assert_eq!(drain(tx, rx).await, expected, "{backpressure:?}");
+            assert_eq!(worker.dropped.load(Ordering::Relaxed), dropped);
+        }
+    }
+
+    #[tokio::test]
+    async fn blocking_queue_waits_for_room() {
+        let (tx, rx, worker) = queue(Backpressure::Block);
+        assert!(tx.send(1).await);
+        assert!(tx.send(2).await);
+        assert_eq!(worker.queued.load(Ordering::Relaxed), 2);
+
+        {
+            let send = tx.send(3);
+            tokio::pin!(send);
+            assert!(futures::poll!(send.as_mut()).is_pending());
+            assert_eq!(rx.recv().await, Some(1));
+            assert!(send.await);
+        }
+
+        assert_eq!(drain(tx, rx).await, vec![2, 3]);
+        assert_eq!(worker.dropped.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn send_fails_once_receiver_is_gone() {
+        let (tx, rx, _) = queue(Backpressure::Block);
+        drop(rx);
+        assert!(!tx.send(1).await);
+    }
+}
@@ -0,0 +1,279 @@
+use core::fmt;
+use std::{collections::HashMap, sync::Arc};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    supervisor::RestartPolicy,
+    worker::{Backpressure, ConsumerWorker, ProducerWorker, Worker, WorkerOptions},
+    AppBuilder, InjectedTo, Reduced,
+};
+
+type Factory<'f, S> = Box<
+    dyn Fn(AppBuilder<'f, S>, serde_json::Value, WorkerOptions) -> FactoryResult<'f, S>
+        + Send
+        + Sync,
+>;
+
+type FactoryResult<'f, S> = Result<AppBuilder<'f, S>, serde_json::Error>;
+
+/// One entry of a `workers:` list in config, e.g.
+///
+/// ```yaml
+/// - kind: price_feed
+///   restart: { policy: backoff, initial: 1s, max: 1m }
+///   backpressure: drop_oldest
+///   capacity: 100
+///   config:
+///     ticker: ETHUSDT
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct WorkerSpec {
+    pub kind: String,
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    #[serde(default)]
+    pub backpressure: Backpressure,
+    pub capacity: Option<usize>,
+    #[serde(default)]
+    pub compute_bound: bool,
+    #[serde(default)]
+    pub config: serde_json::Value,
+}
+
+impl WorkerSpec {
+    pub fn options(&self) -> WorkerOptions {
+        let mut options = WorkerOptions::from(self.restart.clone()).backpressure(self.backpressure);
+        if let Some(capacity) = self.capacity {
+            options = options.capacity(capacity);
+        }
+        if self.compute_bound {
+            options = options.compute_bound();
+        }
+        options
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RegistryError {
+    #[error("Unknown worker kind `{0}`")]
+    UnknownKind(String),
+    #[error("Invalid config for worker `{kind}`: {source}")]
+    Config {
+        kind: String,
+        source: serde_json::Error,
+    },
+}
+
+/// Named worker factories, so that an app can be assembled from config with
+/// `AppBuilder::add_workers`. Every factory gets the `config` of its
+/// `WorkerSpec` deserialized into its own config type.
+pub struct Registry<'f, S> {
+    factories: HashMap<String, Factory<'f, S>>,
+}
+
+impl<'f, S> Default for Registry<'f, S> {
+    fn default() -> Self {
+        Self {
+            factories: HashMap::new(),
+        }
+    }
+}
+
+impl<'f, S> Registry<'f, S>
+where
+    'f: 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn producer<W, C, T>(
+        mut self,
+        kind: &str,
+        make: impl Fn(C) -> W + Send + Sync + 'static,
+    ) -> Self
+    where
+        C: DeserializeOwned + Clone + Send + Sync + 'static,
+        W: ProducerWorker<'f, T> + Send + Sync + 'static,
+        T: InjectedTo<S> + Serialize + fmt::Debug + Send + Sync + 'f,
+    {
+        let make = Arc::new(make);
+        let factory = move |builder: AppBuilder<'f, S>, config, options| {
+            let config: C = serde_json::from_value(config)?;
+            let make = make.clone();
+            Ok(builder.add_producer(move || make(config.clone()), options))
+        };
+        self.factories.insert(kind.to_string(), Box::new(factory));
+        self
+    }
+
+    pub fn consumer<W, C, T, E>(
+        mut self,
+        kind: &str,
+        make: impl Fn(C) -> W + Send + Sync + 'static,
+    ) -> Self
+    where
+        C: DeserializeOwned + Clone + Send + Sync + 'static,
+        W: ConsumerWorker<'f, T, E> + Send + Sync + 'static,
+        T: PartialEq + Clone + fmt::Debug + Send + Sync + 'f,
+        E: fmt::Debug + Send + Sync + 'f,
+        S: Reduced<T>,
+    {
+        let make = Arc::new(make);
+        let factory = move |builder: AppBuilder<'f, S>, config, options| {
+            let config: C = serde_json::from_value(config)?;
+            let make = make.clone();
+            Ok(builder.add_consumer(move || make(config.clone()), options))
+        };
+        self.factories.insert(kind.to_string(), Box::new(factory));
+        self
+    }
+
+    pub fn worker<W, C, Consumed, II, Produced, E>(
+        mut self,
+        kind: &str,
+        make: impl Fn(C) -> W + Send + Sync + 'static,
+    ) -> Self
+    where
+        C: DeserializeOwned + Clone + Send + Sync + 'static,
+        W: Worker<'f, Consumed, II, Produced, E> + Send + Sync + 'static,
+        Consumed: PartialEq + Clone + fmt::Debug + Send + Sync + 'f,
+        II: fmt::Debug + Send + Sync + 'f,
+        Produced: InjectedTo<S> + Serialize + fmt::Debug + Send + Sync + 'f,
+        E: fmt::Debug + Send + Sync + 'f,
+        S: Reduced<Consumed>,
+    {
+        let make = Arc::new(make);
+        let factory = move |builder: AppBuilder<'f, S>, config, options| {
+            let config: C = serde_json::from_value(config)?;
+            let make = make.clone();
+            Ok(builder.add_worker(move || make(config.clone()), options))
+        };
+        self.factories.insert(kind.to_string(), Box::new(factory));
+        self
+    }
+
+    pub(crate) fn add(
+        &self,
+        builder: AppBuilder<'f, S>,
+        spec: &WorkerSpec,
+    ) -> Result<AppBuilder<'f, S>, RegistryError> {
+        let factory = self
+            .factories
+            .get(&spec.kind)
+            .ok_or_else(|| RegistryError::UnknownKind(spec.kind.clone()))?;
+        factory(builder, spec.config.clone(), spec.options()).map_err(|source| {
+            RegistryError::Config {
+                kind: spec.kind.clone(),
+                source,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::{channel::mpsc, future::BoxFuture, FutureExt, SinkExt};
+
+    use super::*;
+    use crate::{shutdown::Shutdown, worker::WorkerError, App};
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Total(u64);
+
+    #[derive(Debug, Serialize)]
+    struct Add(u64);
+
+    impl InjectedTo<Total> for Add {
+        const NAME: &'static str = "Add";
+
+        fn inject_to(self, state: Total) -> Total {
+            Total(state.0 + self.0)
+        }
+    }
+
+    #[derive(Clone, Deserialize)]
+    struct AdderConfig {
+        step: u64,
+        times: usize,
+    }
+
+    struct Adder(AdderConfig);
+
+    impl<'f> ProducerWorker<'f, Add> for Adder {
+        fn work(
+            self: Box<Self>,
+            mut state_tx: mpsc::Sender<Add>,
+            _shutdown: Shutdown,
+        ) -> BoxFuture<'f, Result<(), WorkerError>> {
+            async move {
+                for _ in 0..self.0.times {
+                    state_tx.send(Add(self.0.step)).await?;
+                }
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    fn registry() -> Registry<'static, Total> {
+        Registry::new().producer("adder", Adder)
+    }
+
+    fn specs(yaml: &str) -> Vec<WorkerSpec> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[tokio::test]
+    async fn assembles_app_from_specs() {
+        let specs = specs(
+            "
+            - kind: adder
+              config: { step: 2, times: 3 }
+            - kind: adder
+              restart: { policy: backoff, initial: 1s, max: 1m, max_restarts: 0, window: 1h }
+              backpressure: drop_newest
+              capacity: 4
+              config: { step: 10, times: 1 }
+            ",
+        );
+        assert_eq!(specs[0].backpressure, Backpressure::Block);
+        assert_eq!(specs[1].backpressure, Backpressure::DropNewest);
+        assert_eq!(specs[1].capacity, Some(4));
+        assert_eq!(
+            specs[1].restart,
+            RestartPolicy::backoff(Duration::from_secs(1), Duration::from_secs(60))
+                .max_restarts(0, Duration::from_secs(60 * 60))
+        );
+
+        let app = App::build(Total::default())
+            .add_workers(&registry(), &specs)
+            .unwrap()
+            .build();
+        let state = app.state();
+        let outcome = app.run().await;
+
+        assert!(outcome.is_clean());
+        assert_eq!(*state.borrow(), Total(16));
+    }
+
+    #[test]
+    fn rejects_unknown_kind_and_bad_config() {
+        let unknown = App::build(Total::default())
+            .add_workers(&registry(), &specs("[{ kind: multiplier }]"))
+            .err();
+        assert!(matches!(unknown, Some(RegistryError::UnknownKind(kind)) if kind == "multiplier"));
+
+        let bad = App::build(Total::default())
+            .add_workers(
+                &registry(),
+                &specs("[{ kind: adder, config: { step: 1 } }]"),
+            )
+            .err();
+        assert!(matches!(bad, Some(RegistryError::Config { kind, .. }) if kind == "adder"));
+    }
+}
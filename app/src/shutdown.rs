@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+use tracing::info;
+
+/// Cancellation signal passed into every worker.
+#[derive(Clone, Debug)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+/// Triggers shutdown of a running `App`.
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle(Arc<watch::Sender<bool>>);
+
+impl Shutdown {
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once shutdown is triggered or the app is gone.
+    pub async fn wait(&mut self) {
+        while !*self.0.borrow_and_update() {
+            if self.0.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new() -> Self {
+        let (tx, _rx) = watch::channel(false);
+        Self(Arc::new(tx))
+    }
+
+    pub fn shutdown(&self) {
+        info!("shutdown requested");
+        self.trigger();
+    }
+
+    pub(crate) fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub fn subscribe(&self) -> Shutdown {
+        Shutdown(self.0.subscribe())
+    }
+}
+
+pub(crate) async fn termination_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Cannot listen to SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => info!("got SIGINT"),
+            _ = terminate.recv() => info!("got SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.ok();
+        info!("got ctrl-c");
+    }
+}
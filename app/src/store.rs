@@ -0,0 +1,381 @@
+use std::sync::Arc;
+
+use futures::{channel::mpsc, StreamExt};
+use serde::Serialize;
+use tokio::sync::{oneshot, watch};
+use tracing::debug;
+
+use crate::{
+    journal::Journal,
+    metrics::{Metrics, WorkerMetrics},
+    InjectedTo, Reduced,
+};
+
+pub(crate) type Event<'f, S> = Box<dyn FnOnce(S, Option<&mut Journal>) -> S + Send + 'f>;
+
+/// Reduces the state for one consumer and hands the result over.
+pub(crate) type Reduction<'f, S> = Box<dyn FnOnce(&mut S) + Send + 'f>;
+
+pub(crate) enum Command<'f, S> {
+    Inject(Event<'f, S>),
+    Reduce(Reduction<'f, S>),
+}
+
+/// The only owner of the app state. Every change, be it an injected event or
+/// a reduction for a consumer, is applied here one at a time, in the order
+/// the commands arrive. Consumers ask for their reduction once they have
+/// room for it, so the store never waits for them.
+pub(crate) struct Store<'f, S> {
+    state: S,
+    commands: mpsc::Receiver<Command<'f, S>>,
+    /// Changes on every injected event, which is what consumers wait for
+    published: watch::Sender<S>,
+    journal: Option<Journal>,
+    metrics: Arc<Metrics>,
+}
+
+impl<'f, S> Store<'f, S>
+where
+    S: Clone + Send + Sync + 'f,
+{
+    pub(crate) fn new(
+        initial_state: S,
+        metrics: Arc<Metrics>,
+    ) -> (Self, mpsc::Sender<Command<'f, S>>, watch::Receiver<S>) {
+        let (commands_tx, commands) = mpsc::channel(1);
+        let (published, state_rx) = watch::channel(initial_state.clone());
+        let store = Self {
+            state: initial_state,
+            commands,
+            published,
+            journal: None,
+            metrics,
+        };
+        (store, commands_tx, state_rx)
+    }
+
+    pub(crate) fn restore(&mut self, state: S) {
+        self.published.send_replace(state.clone());
+        self.state = state;
+    }
+
+    pub(crate) fn journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    pub(crate) async fn run(mut self) {
+        while let Some(command) = self.commands.next().await {
+            match command {
+                Command::Inject(event) => {
+                    self.metrics.store_queued(-1);
+                    let state = self.state;
+                    self.state = event(state, self.journal.as_mut());
+                    self.metrics.state_updated();
+                    self.published.send_replace(self.state.clone());
+                }
+                Command::Reduce(reduction) => {
+                    reduction(&mut self.state);
+                    // A reduction must not wake up the consumers
+                    self.published.send_if_modified(|published| {
+                        *published = self.state.clone();
+                        false
+                    });
+                }
+            }
+        }
+        debug!("All workers are gone - store stopped");
+    }
+}
+
+pub(crate) fn event<'f, S, T>(worker: Arc<WorkerMetrics>, data: T) -> Command<'f, S>
+where
+    T: InjectedTo<S> + Serialize + Send + 'f,
+{
+    Command::Inject(Box::new(move |state, journal| {
+        if let Some(journal) = journal {
+            journal.record::<S, T>(&worker.id, &data);
+        }
+        worker.inject.time(|| data.inject_to(state))
+    }))
+}
+
+/// Hands the reduced state over to `reduced`, unless nobody waits for it
+/// anymore, so a `take` does not lose anything.
+pub(crate) fn reduction<'f, S, T>(
+    worker: Arc<WorkerMetrics>,
+    reduced: oneshot::Sender<T>,
+) -> Command<'f, S>
+where
+    S: Reduced<T>,
+    T: Send + 'f,
+{
+    Command::Reduce(Box::new(move |state: &mut S| {
+        if reduced.is_closed() {
+            return;
+        }
+        let data = worker.reduce.time(|| state.reduce());
+        let _ = reduced.send(data);
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use futures::{channel::mpsc, future::BoxFuture, FutureExt, SinkExt, StreamExt};
+    use serde::Serialize;
+
+    use crate::{
+        shutdown::Shutdown,
+        supervisor::RestartPolicy,
+        worker::{Backpressure, ConsumerWorker, ProducerWorker, WorkerError, WorkerOptions},
+        App, InjectedTo, Reduced,
+    };
+
+    const EVENTS: usize = 1000;
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Counters {
+        left: usize,
+        right: usize,
+    }
+
+    #[derive(Debug, Serialize)]
+    enum Increment {
+        Left,
+        Right,
+    }
+
+    impl InjectedTo<Counters> for Increment {
+        const NAME: &'static str = "Increment";
+
+        fn inject_to(self, mut state: Counters) -> Counters {
+            match self {
+                Increment::Left => state.left += 1,
+                Increment::Right => state.right += 1,
+            }
+            state
+        }
+    }
+
+    impl Reduced<Counters> for Counters {
+        fn reduce(&mut self) -> Counters {
+            self.clone()
+        }
+    }
+
+    struct Incrementer(fn() -> Increment);
+
+    impl<'f> ProducerWorker<'f, Increment> for Incrementer {
+        fn work(
+            self: Box<Self>,
+            mut state_tx: mpsc::Sender<Increment>,
+            _shutdown: Shutdown,
+        ) -> BoxFuture<'f, Result<(), WorkerError>> {
+            async move {
+                for _ in 0..EVENTS {
+                    state_tx.send((self.0)()).await?;
+                }
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    struct UntilDone;
+
+    impl<'f> ConsumerWorker<'f, Counters, mpsc::SendError> for UntilDone {
+        type Sink = mpsc::Sender<Counters>;
+        type Stream = mpsc::Receiver<Counters>;
+
+        fn provide_input_stream(&self) -> (Self::Sink, Self::Stream) {
+            mpsc::channel(1)
+        }
+
+        fn work(
+            self: Box<Self>,
+            mut state_rx: Self::Stream,
+            _shutdown: Shutdown,
+        ) -> BoxFuture<'f, Result<(), WorkerError>> {
+            async move {
+                while let Some(counters) = state_rx.next().await {
+                    if counters.left == EVENTS && counters.right == EVENTS {
+                        break;
+                    }
+                }
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_producers_do_not_lose_updates() {
+        let app = App::build(Counters::default())
+            .add_producer(|| Incrementer(|| Increment::Left), RestartPolicy::Never)
+            .add_producer(|| Incrementer(|| Increment::Right), RestartPolicy::Never)
+            .add_consumer(|| UntilDone, RestartPolicy::Never)
+            .build();
+        let state = app.state();
+
+        let outcome = tokio::time::timeout(Duration::from_secs(10), app.run())
+            .await
+            .expect("consumer must see every increment");
+
+        assert!(outcome.is_clean());
+        let counters = state.borrow().clone();
+        assert_eq!(counters.left, EVENTS);
+        assert_eq!(counters.right, EVENTS);
+    }
+
+    /// Left counter in buckets of ten
+    #[derive(Debug, Clone, PartialEq)]
+    struct Bucket(usize);
+
+    impl Reduced<Bucket> for Counters {
+        fn reduce(&mut self) -> Bucket {
+            Bucket(self.left / 10)
+        }
+    }
+
+    struct Recorder {
+        received: Arc<Mutex<Vec<usize>>>,
+        delay: Duration,
+    }
+
+    impl<'f> ConsumerWorker<'f, Bucket, mpsc::SendError> for Recorder {
+        type Sink = mpsc::Sender<Bucket>;
+        type Stream = mpsc::Receiver<Bucket>;
+
+        fn provide_input_stream(&self) -> (Self::Sink, Self::Stream) {
+            mpsc::channel(1)
+        }
+
+        fn work(
+            self: Box<Self>,
+            mut state_rx: Self::Stream,
+            _shutdown: Shutdown,
+        ) -> BoxFuture<'f, Result<(), WorkerError>> {
+            async move {
+                while let Some(Bucket(bucket)) = state_rx.next().await {
+                    self.received.lock().unwrap().push(bucket);
+                    if bucket == EVENTS / 10 {
+                        break;
+                    }
+                    tokio::time::sleep(self.delay).await;
+                }
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    async fn record(options: WorkerOptions, delay: Duration) -> Vec<usize> {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let recorder = received.clone();
+        let app = App::build(Counters::default())
+            .add_producer(|| Incrementer(|| Increment::Left), RestartPolicy::Never)
+            .add_consumer(
+                move || Recorder {
+                    received: recorder.clone(),
+                    delay,
+                },
+                options,
+            )
+            .build();
+
+        tokio::time::timeout(Duration::from_secs(10), app.run())
+            .await
+            .expect("consumer must see the last bucket");
+        let received = received.lock().unwrap().clone();
+        received
+    }
+
+    #[tokio::test]
+    async fn unchanged_slices_are_not_delivered() {
+        let received = record(WorkerOptions::new(), Duration::ZERO).await;
+
+        // Reduced only when the consumer has room, so it may skip slices
+        assert_eq!(received.first(), Some(&0));
+        assert_eq!(received.last(), Some(&(EVENTS / 10)));
+        assert!(received.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    /// Holds on to its input but never reads it
+    struct Stalled;
+
+    impl<'f> ConsumerWorker<'f, Counters, mpsc::SendError> for Stalled {
+        type Sink = mpsc::Sender<Counters>;
+        type Stream = mpsc::Receiver<Counters>;
+
+        fn provide_input_stream(&self) -> (Self::Sink, Self::Stream) {
+            mpsc::channel(0)
+        }
+
+        fn work(
+            self: Box<Self>,
+            state_rx: Self::Stream,
+            mut shutdown: Shutdown,
+        ) -> BoxFuture<'f, Result<(), WorkerError>> {
+            async move {
+                shutdown.wait().await;
+                drop(state_rx);
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn stalled_consumer_does_not_hold_up_producers() {
+        let app = App::build(Counters::default())
+            .add_consumer(|| Stalled, RestartPolicy::Never)
+            .add_producer(|| Incrementer(|| Increment::Left), RestartPolicy::Never)
+            .build();
+        let mut state = app.state();
+        let shutdown = app.shutdown_handle();
+
+        let watch = async {
+            let all_injected = async {
+                while state.borrow_and_update().left < EVENTS {
+                    state.changed().await.unwrap();
+                }
+            };
+            let injected = tokio::time::timeout(Duration::from_secs(10), all_injected).await;
+            shutdown.shutdown();
+            injected
+        };
+        let (outcome, injected) = futures::future::join(app.run(), watch).await;
+
+        assert!(injected.is_ok(), "producer got stuck behind the consumer");
+        assert!(outcome.is_clean());
+    }
+
+    #[tokio::test]
+    async fn coalesced_consumer_gets_latest_slice() {
+        let received = record(
+            WorkerOptions::new().backpressure(Backpressure::CoalesceLatest),
+            Duration::from_millis(20),
+        )
+        .await;
+
+        assert!(received.len() < EVENTS / 10, "{received:?}");
+        assert_eq!(received.last(), Some(&(EVENTS / 10)));
+        assert!(received.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[tokio::test]
+    async fn slow_consumer_dropping_oldest_keeps_the_latest_slices() {
+        let options = WorkerOptions::new()
+            .backpressure(Backpressure::DropOldest)
+            .capacity(3);
+        let received = record(options, Duration::from_millis(20)).await;
+
+        assert!(received.len() < EVENTS / 10, "{received:?}");
+        assert_eq!(received.last(), Some(&(EVENTS / 10)));
+        assert!(received.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,373 @@
+use core::fmt;
+use std::{
+    any::Any,
+    collections::VecDeque,
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+use crate::{
+    shutdown::Shutdown,
+    worker::{WorkerError, WorkerId},
+};
+
+/// In config: `{ policy: never }`, `{ policy: always }` or
+/// `{ policy: backoff, initial: 1s, max: 1m, max_restarts: 10, window: 1h }`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    /// Restarts without limit, backing off from 100ms up to 30s while the
+    /// worker keeps failing, so one whose exchange is down does not spin
+    Always,
+    Backoff(Backoff),
+}
+
+/// Exponential backoff: the delay doubles from `initial` up to `max` with
+/// every restart, and the worker is given up once it has been restarted more
+/// than `max_restarts` times within `window`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Backoff {
+    #[serde(with = "humantime_serde")]
+    pub initial: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max: Duration,
+    #[serde(default = "unlimited_restarts")]
+    pub max_restarts: usize,
+    #[serde(default = "unlimited_window", with = "humantime_serde")]
+    pub window: Duration,
+}
+
+fn unlimited_restarts() -> usize {
+    usize::MAX
+}
+
+fn unlimited_window() -> Duration {
+    Duration::MAX
+}
+
+/// `RestartPolicy::Always`; failures older than `window` no longer slow
+/// down a restart.
+const ALWAYS: Backoff = Backoff {
+    initial: Duration::from_millis(100),
+    max: Duration::from_secs(30),
+    max_restarts: usize::MAX,
+    window: Duration::from_secs(10 * 60),
+};
+
+/// What the app does once a worker has failed for good, i.e. returned an
+/// error or panicked and was not restarted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FailurePolicy {
+    #[default]
+    Continue,
+    AbortAll,
+}
+
+enum Exit {
+    Returned,
+    Failed(WorkerError),
+}
+
+pub(crate) struct Supervisor {
+    id: WorkerId,
+    policy: RestartPolicy,
+    restarts: Arc<AtomicUsize>,
+    recent: VecDeque<Instant>,
+}
+
+impl RestartPolicy {
+    pub fn backoff(initial: Duration, max: Duration) -> Self {
+        Self::Backoff(Backoff {
+            initial,
+            max,
+            max_restarts: unlimited_restarts(),
+            window: unlimited_window(),
+        })
+    }
+
+    pub fn max_restarts(self, max_restarts: usize, window: Duration) -> Self {
+        match self {
+            Self::Backoff(backoff) => Self::Backoff(Backoff {
+                max_restarts,
+                window,
+                ..backoff
+            }),
+            policy => policy,
+        }
+    }
+}
+
+impl Supervisor {
+    pub(crate) fn new(id: WorkerId, policy: RestartPolicy, restarts: Arc<AtomicUsize>) -> Self {
+        Self {
+            id,
+            policy,
+            restarts,
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Runs `attempt` until it returns for good: either the policy says so,
+    /// or shutdown has been requested. Resolves to the last failure if the
+    /// worker was given up on; whatever happens after shutdown is not one.
+    pub(crate) async fn supervise<'f>(
+        mut self,
+        mut shutdown: Shutdown,
+        mut attempt: impl FnMut() -> BoxFuture<'f, Result<(), WorkerError>>,
+    ) -> Result<(), WorkerError> {
+        loop {
+            let exit = match AssertUnwindSafe(attempt()).catch_unwind().await {
+                Ok(Ok(())) => Exit::Returned,
+                Ok(Err(error)) => Exit::Failed(error),
+                Err(panic) => Exit::Failed(WorkerError::Panicked(panic_message(panic))),
+            };
+
+            if shutdown.is_triggered() {
+                info!(id = %self.id, %exit, "worker stopped on shutdown");
+                return Ok(());
+            }
+
+            match self.next_delay() {
+                Some(delay) => {
+                    let restarts = self.restarts.fetch_add(1, Ordering::Relaxed) + 1;
+                    warn!(id = %self.id, %exit, ?delay, restarts, "restart worker");
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = shutdown.wait() => {
+                            info!(id = %self.id, "worker is not restarted on shutdown");
+                            return Ok(());
+                        }
+                    }
+                }
+                None => {
+                    error!(id = %self.id, %exit, policy = ?self.policy, "worker is not restarted");
+                    return exit.into();
+                }
+            }
+        }
+    }
+
+    fn next_delay(&mut self) -> Option<Duration> {
+        let backoff = match &self.policy {
+            RestartPolicy::Never => return None,
+            RestartPolicy::Always => &ALWAYS,
+            RestartPolicy::Backoff(backoff) => backoff,
+        };
+        let now = Instant::now();
+        while let Some(first) = self.recent.front() {
+            if now.duration_since(*first) > backoff.window {
+                self.recent.pop_front();
+            } else {
+                break;
+            }
+        }
+        if self.recent.len() >= backoff.max_restarts {
+            return None;
+        }
+        let exponent = self.recent.len().min(31) as u32;
+        self.recent.push_back(now);
+        Some(
+            backoff
+                .initial
+                .saturating_mul(2u32.pow(exponent))
+                .min(backoff.max),
+        )
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exit::Returned => write!(f, "returned"),
+            Exit::Failed(error) => write!(f, "failed: {error}"),
+        }
+    }
+}
+
+impl From<Exit> for Result<(), WorkerError> {
+    fn from(exit: Exit) -> Self {
+        match exit {
+            Exit::Returned => Ok(()),
+            Exit::Failed(error) => Err(error),
+        }
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::{channel::mpsc, future::BoxFuture, FutureExt};
+
+    use super::*;
+    use crate::{
+        worker::{ProducerWorker, WorkerError, WorkerOptions},
+        App, InjectedTo,
+    };
+
+    #[derive(Debug, Clone, Default)]
+    struct Nothing;
+
+    #[derive(Debug, serde::Serialize)]
+    struct Never;
+
+    impl InjectedTo<Nothing> for Never {
+        const NAME: &'static str = "Never";
+
+        fn inject_to(self, state: Nothing) -> Nothing {
+            state
+        }
+    }
+
+    struct Failing;
+
+    impl<'f> ProducerWorker<'f, Never> for Failing {
+        fn work(
+            self: Box<Self>,
+            _state_tx: mpsc::Sender<Never>,
+            _shutdown: Shutdown,
+        ) -> BoxFuture<'f, Result<(), WorkerError>> {
+            async { Err(WorkerError::other("exchange is down")) }.boxed()
+        }
+    }
+
+    struct UntilShutdown;
+
+    impl<'f> ProducerWorker<'f, Never> for UntilShutdown {
+        fn work(
+            self: Box<Self>,
+            _state_tx: mpsc::Sender<Never>,
+            mut shutdown: Shutdown,
+        ) -> BoxFuture<'f, Result<(), WorkerError>> {
+            async move {
+                shutdown.wait().await;
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn abort_all_stops_every_worker() {
+        let app = App::build(Nothing)
+            .on_failure(FailurePolicy::AbortAll)
+            .add_producer(|| Failing, RestartPolicy::Never)
+            .add_producer(|| UntilShutdown, RestartPolicy::Never)
+            .build();
+
+        let outcome = tokio::time::timeout(Duration::from_secs(5), app.run())
+            .await
+            .expect("failure must abort the app");
+
+        assert!(outcome.aborted);
+        assert!(outcome.not_stopped.is_empty());
+        assert_eq!(outcome.failures.len(), 1);
+        assert_eq!(outcome.failures[0].0.name, "Failing");
+        assert!(!outcome.is_clean());
+    }
+
+    #[tokio::test]
+    async fn continue_keeps_other_workers_running() {
+        let app = App::build(Nothing)
+            .add_producer(|| Failing, RestartPolicy::Never)
+            .add_producer(|| UntilShutdown, RestartPolicy::Never)
+            .build();
+        let handle = app.shutdown_handle();
+
+        let run = app.run();
+        futures::pin_mut!(run);
+        let early = tokio::time::timeout(Duration::from_millis(100), &mut run).await;
+        handle.shutdown();
+        let outcome = run.await;
+
+        assert!(early.is_err(), "app must outlive the failed worker");
+
+        assert!(!outcome.aborted);
+        assert_eq!(outcome.failures.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn worker_policy_overrides_abort_all() {
+        let app = App::build(Nothing)
+            .on_failure(FailurePolicy::AbortAll)
+            .add_producer(|| UntilShutdown, RestartPolicy::Never)
+            .build();
+        let handle = app.shutdown_handle();
+        let options = WorkerOptions::from(RestartPolicy::Never).on_failure(FailurePolicy::Continue);
+        app.control().add_producer(|| Failing, options);
+
+        let run = app.run();
+        futures::pin_mut!(run);
+        let early = tokio::time::timeout(Duration::from_millis(100), &mut run).await;
+        handle.shutdown();
+        let outcome = run.await;
+
+        assert!(early.is_err(), "app must outlive the failed worker");
+        assert!(!outcome.aborted);
+        assert_eq!(outcome.failures.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn always_backs_off_a_failing_worker() {
+        let app = App::build(Nothing)
+            .add_producer(|| Failing, RestartPolicy::Always)
+            .build();
+        let handle = app.shutdown_handle();
+        let metrics = app.metrics();
+
+        let run = app.run();
+        futures::pin_mut!(run);
+        let early = tokio::time::timeout(Duration::from_secs(10), &mut run).await;
+        handle.shutdown();
+        let outcome = run.await;
+
+        assert!(early.is_err());
+        assert!(outcome.failures.is_empty());
+        // 0.1 + 0.2 + 0.4 + 0.8 + 1.6 + 3.2 seconds, the next one is 6.4
+        let restarts = metrics.workers()[0].restarts.load(Ordering::Relaxed);
+        assert_eq!(restarts, 7);
+    }
+
+    #[tokio::test]
+    async fn shutdown_interrupts_restart_delay() {
+        let app = App::build(Nothing)
+            .add_producer(
+                || Failing,
+                RestartPolicy::backoff(Duration::from_secs(60), Duration::from_secs(60)),
+            )
+            .build();
+        let handle = app.shutdown_handle();
+
+        let run = app.run();
+        futures::pin_mut!(run);
+        let early = tokio::time::timeout(Duration::from_millis(100), &mut run).await;
+        let stopping = std::time::Instant::now();
+        handle.shutdown();
+        let outcome = run.await;
+
+        assert!(early.is_err());
+        assert!(outcome.not_stopped.is_empty(), "{outcome:?}");
+        assert!(stopping.elapsed() < Duration::from_secs(1));
+    }
+}
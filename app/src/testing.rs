@@ -0,0 +1,328 @@
+//! Runs an `App` in tests without network or wall clock: `Script`s stand in
+//! for producers, `Recording`s take the place of consumers and keep every
+//! reduced value they got. Meant for `#[tokio::test(start_paused = true)]`,
+//! so that script delays and worker timers cost no real time.
+
+use core::fmt;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures::{channel::mpsc, future::BoxFuture, FutureExt, SinkExt, StreamExt};
+use serde::Serialize;
+use tokio::sync::Notify;
+
+use crate::{
+    outcome::AppOutcome,
+    shutdown::Shutdown,
+    supervisor::RestartPolicy,
+    worker::{ConsumerWorker, ProducerWorker, WorkerError, WorkerOptions},
+    App, AppBuilder, InjectedTo, Reduced,
+};
+
+/// Events a scripted producer sends, with pauses in between.
+#[derive(Debug, Clone)]
+pub struct Script<T> {
+    steps: Vec<Step<T>>,
+}
+
+#[derive(Debug, Clone)]
+enum Step<T> {
+    Send(T),
+    Sleep(Duration),
+}
+
+impl<T> Default for Script<T> {
+    fn default() -> Self {
+        Self { steps: Vec::new() }
+    }
+}
+
+impl<T> Script<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn send(mut self, event: T) -> Self {
+        self.steps.push(Step::Send(event));
+        self
+    }
+
+    pub fn send_all(mut self, events: impl IntoIterator<Item = T>) -> Self {
+        self.steps.extend(events.into_iter().map(Step::Send));
+        self
+    }
+
+    pub fn sleep(mut self, duration: Duration) -> Self {
+        self.steps.push(Step::Sleep(duration));
+        self
+    }
+}
+
+struct Scripted<T> {
+    script: Script<T>,
+    running: Arc<Running>,
+}
+
+impl<'f, T> ProducerWorker<'f, T> for Scripted<T>
+where
+    T: Send + 'f,
+{
+    fn work(
+        self: Box<Self>,
+        mut state_tx: mpsc::Sender<T>,
+        _shutdown: Shutdown,
+    ) -> BoxFuture<'f, Result<(), WorkerError>> {
+        async move {
+            self.running.started().await;
+            let mut sent = Ok(());
+            for step in self.script.steps {
+                match step {
+                    Step::Send(event) => sent = state_tx.send(event).await,
+                    Step::Sleep(duration) => tokio::time::sleep(duration).await,
+                }
+                if sent.is_err() {
+                    break;
+                }
+            }
+            self.running.finished();
+            Ok(sent?)
+        }
+        .boxed()
+    }
+}
+
+/// Scripts which have not sent everything yet. They start together, once
+/// every consumer has subscribed.
+#[derive(Default)]
+struct Running {
+    scripts: AtomicUsize,
+    finished: Notify,
+    started: AtomicBool,
+    start: Notify,
+}
+
+impl Running {
+    fn start(&self) {
+        self.started.store(true, Ordering::SeqCst);
+        self.start.notify_waiters();
+    }
+
+    async fn started(&self) {
+        loop {
+            let start = self.start.notified();
+            if self.started.load(Ordering::SeqCst) {
+                return;
+            }
+            start.await;
+        }
+    }
+
+    fn finished(&self) {
+        self.scripts.fetch_sub(1, Ordering::SeqCst);
+        self.finished.notify_one();
+    }
+
+    async fn all_finished(&self) {
+        while self.scripts.load(Ordering::SeqCst) > 0 {
+            self.finished.notified().await;
+        }
+    }
+}
+
+/// Every value the store handed to a consumer of `T`, in order.
+pub struct Recording<T>(Arc<Mutex<Vec<T>>>);
+
+impl<T> Clone for Recording<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Default for Recording<T> {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(Vec::new())))
+    }
+}
+
+impl<T: Clone> Recording<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn values(&self) -> Vec<T> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl<'f, T> ConsumerWorker<'f, T, mpsc::SendError> for Recording<T>
+where
+    T: Send + Sync + 'f,
+{
+    type Sink = mpsc::Sender<T>;
+    type Stream = mpsc::Receiver<T>;
+
+    fn provide_input_stream(&self) -> (Self::Sink, Self::Stream) {
+        mpsc::channel(1)
+    }
+
+    fn work(
+        self: Box<Self>,
+        mut state_rx: Self::Stream,
+        mut shutdown: Shutdown,
+    ) -> BoxFuture<'f, Result<(), WorkerError>> {
+        async move {
+            loop {
+                let value = tokio::select! {
+                    value = state_rx.next() => value,
+                    _ = shutdown.wait() => None,
+                };
+                let Some(value) = value else {
+                    break;
+                };
+                self.0.lock().unwrap().push(value);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// What `Harness::run` ended with.
+#[derive(Debug)]
+pub struct Finished<S> {
+    pub outcome: AppOutcome,
+    pub state: S,
+}
+
+/// Builds an app from real workers, scripts and recordings, and runs it
+/// until every script has sent its last event and the app has settled.
+pub struct Harness<'f, S> {
+    builder: AppBuilder<'f, S>,
+    running: Arc<Running>,
+}
+
+impl<'f, S> Harness<'f, S>
+where
+    'f: 'static,
+    S: fmt::Debug + Clone + Send + Sync + 'static,
+{
+    pub fn new(initial_state: S) -> Self {
+        Self {
+            builder: App::build(initial_state),
+            running: Arc::default(),
+        }
+    }
+
+    /// Registers real workers, e.g. `|app| app.add_worker(..)`.
+    pub fn with(mut self, add: impl FnOnce(AppBuilder<'f, S>) -> AppBuilder<'f, S>) -> Self {
+        self.builder = add(self.builder);
+        self
+    }
+
+    pub fn script<T>(mut self, script: Script<T>) -> Self
+    where
+        T: InjectedTo<S> + Serialize + fmt::Debug + Clone + Send + Sync + 'static,
+    {
+        self.running.scripts.fetch_add(1, Ordering::SeqCst);
+        let running = self.running.clone();
+        let scripted = move || Scripted {
+            script: script.clone(),
+            running: running.clone(),
+        };
+        self.builder = self.builder.add_producer(scripted, RestartPolicy::Never);
+        self
+    }
+
+    /// Records what a consumer of `T` with default `WorkerOptions` would get.
+    pub fn record<T>(mut self, recording: &Recording<T>) -> Self
+    where
+        T: PartialEq + Clone + fmt::Debug + Send + Sync + 'static,
+        S: Reduced<T>,
+    {
+        let recording = recording.clone();
+        self.builder = self.builder.add_consumer(
+            move || recording.clone(),
+            WorkerOptions::from(RestartPolicy::Never),
+        );
+        self
+    }
+
+    /// Starts the scripts once every consumer got the initial state, so none
+    /// of them misses an event. Once the scripts are done, waits until
+    /// nothing but timers is left to run (on a paused clock that is as soon
+    /// as the app is idle), then shuts the app down.
+    pub async fn run(self) -> Finished<S> {
+        let app = self.builder.build();
+        let state = app.state();
+        let shutdown = app.shutdown_handle();
+        let subscribed = app.subscribed();
+        let running = self.running;
+        let settle = async move {
+            subscribed.await;
+            running.start();
+            running.all_finished().await;
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            shutdown.shutdown();
+        };
+        let (outcome, ()) = futures::future::join(app.run(), settle).await;
+        let state = state.borrow().clone();
+        Finished { outcome, state }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Total(u64);
+
+    #[derive(Debug, Clone, Serialize)]
+    struct Add(u64);
+
+    impl InjectedTo<Total> for Add {
+        const NAME: &'static str = "Add";
+
+        fn inject_to(self, state: Total) -> Total {
+            Total(state.0 + self.0)
+        }
+    }
+
+    impl Reduced<Total> for Total {
+        fn reduce(&mut self) -> Total {
+            self.clone()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn records_every_reduced_state_in_order() {
+        let totals = Recording::new();
+        let script = Script::new()
+            .send(Add(1))
+            .sleep(Duration::from_secs(60))
+            .send_all([Add(2), Add(0)])
+            .sleep(Duration::from_secs(60 * 60))
+            .send(Add(3));
+
+        let started = tokio::time::Instant::now();
+        let finished = Harness::new(Total::default())
+            .script(script)
+            .record(&totals)
+            .run()
+            .await;
+
+        assert!(finished.outcome.is_clean());
+        assert_eq!(finished.state, Total(6));
+        // The unchanged total after `Add(0)` is not delivered again
+        assert_eq!(
+            totals.values(),
+            vec![Total(0), Total(1), Total(3), Total(6)]
+        );
+        assert!(started.elapsed() >= Duration::from_secs(61 * 60));
+    }
+}
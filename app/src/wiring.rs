@@ -0,0 +1,486 @@
+use core::fmt;
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use futures::{
+    channel::mpsc,
+    future::{self, BoxFuture, Either},
+    FutureExt, Sink, SinkExt, Stream, StreamExt,
+};
+use serde::Serialize;
+use tokio::{
+    runtime,
+    sync::{oneshot, watch},
+};
+use tracing::debug;
+
+use crate::{
+    metrics::{Metrics, WorkerMetrics},
+    queue,
+    shutdown::Shutdown,
+    store::{self, Command},
+    supervisor::{FailurePolicy, Supervisor},
+    worker::{
+        self, Backpressure, ProducerWorker, Schedule, ScheduledWorker, WorkerError, WorkerId,
+        WorkerOptions,
+    },
+    InjectedTo, Reduced, Runner,
+};
+
+const CONSUMER_CAPACITY: usize = 1;
+const PRODUCER_CAPACITY: usize = 100;
+
+/// Connects workers to the store. Shared by `AppBuilder`, which wires the
+/// workers known upfront, and the run loop, which wires the ones spawned by
+/// `Control` while the app runs.
+pub(crate) struct Wiring<'f, S> {
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) store_tx: mpsc::Sender<Command<'f, S>>,
+    pub(crate) state_rx: watch::Receiver<S>,
+    pub(crate) subscriptions: Subscriptions,
+    /// The workers which override the app's `FailurePolicy`
+    pub(crate) failure_policies: Mutex<HashMap<WorkerId, FailurePolicy>>,
+}
+
+/// Counts the consumers which have not had the state reduced for them yet.
+/// Events injected before that are folded into their first state.
+#[derive(Clone)]
+pub(crate) struct Subscriptions(Arc<watch::Sender<usize>>);
+
+impl Subscriptions {
+    pub(crate) fn new() -> Self {
+        Self(Arc::new(watch::channel(0).0))
+    }
+
+    fn expect(&self) -> Arc<Subscription> {
+        self.0.send_modify(|pending| *pending += 1);
+        Arc::new(Subscription {
+            subscriptions: self.clone(),
+            done: AtomicBool::new(false),
+        })
+    }
+
+    /// Resolves once every consumer wired so far has subscribed, or stopped
+    /// before it could.
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn all(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let mut pending = self.0.subscribe();
+        async move {
+            while *pending.borrow_and_update() > 0 {
+                if pending.changed().await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// A consumer subscribes with its first reduction, restarts don't count.
+struct Subscription {
+    subscriptions: Subscriptions,
+    done: AtomicBool,
+}
+
+impl Subscription {
+    fn subscribed(&self) {
+        if !self.done.swap(true, Ordering::SeqCst) {
+            self.subscriptions.0.send_modify(|pending| *pending -= 1);
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.subscribed();
+    }
+}
+
+/// Moves a compute-bound worker to a thread with a runtime of its own, see
+/// `WorkerOptions::compute_bound`. The thread is started on the first poll
+/// and the work is dropped there once the returned future is dropped. Panics
+/// are carried over, so the supervisor sees them.
+fn maybe_compute_bound<'f, T>(work: BoxFuture<'f, T>, options: &WorkerOptions) -> BoxFuture<'f, T>
+where
+    'f: 'static,
+    T: Send + 'f,
+{
+    if !options.compute_bound {
+        return work;
+    }
+    async move {
+        let (mut done_tx, done_rx) = oneshot::channel();
+        thread::Builder::new()
+            .name("compute-bound".to_string())
+            .spawn(move || {
+                let done = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let runtime = runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .expect("Cannot build runtime for a compute-bound worker");
+                    runtime.block_on(async {
+                        tokio::select! {
+                            done = work => Some(done),
+                            _ = done_tx.closed() => None,
+                        }
+                    })
+                }));
+                match done {
+                    Ok(Some(done)) => drop(done_tx.send(Ok(done))),
+                    Ok(None) => {}
+                    Err(panic) => drop(done_tx.send(Err(panic))),
+                }
+            })
+            .expect("Cannot spawn thread for a compute-bound worker");
+        match done_rx.await {
+            Ok(Ok(done)) => done,
+            Ok(Err(panic)) => panic::resume_unwind(panic),
+            Err(_) => unreachable!("compute-bound worker thread ends with a result"),
+        }
+    }
+    .boxed()
+}
+
+/// Runs a `ScheduledWorker` as a producer.
+pub(crate) struct Scheduled<W, S> {
+    schedule: Schedule,
+    worker: W,
+    state: watch::Receiver<S>,
+}
+
+impl<'f, W, S, T> ProducerWorker<'f, T> for Scheduled<W, S>
+where
+    W: ScheduledWorker<'f, S, T> + Send + 'f,
+    S: Send + Sync + 'f,
+    T: Send + 'f,
+{
+    fn work(
+        self: Box<Self>,
+        mut state_tx: mpsc::Sender<T>,
+        mut shutdown: Shutdown,
+    ) -> BoxFuture<'f, Result<(), WorkerError>> {
+        async move {
+            let Self {
+                schedule,
+                mut worker,
+                state,
+            } = *self;
+            let mut ticks = schedule.ticks();
+            loop {
+                let ticked = tokio::select! {
+                    ticked = ticks.next() => ticked,
+                    _ = shutdown.wait() => false,
+                };
+                if !ticked {
+                    break;
+                }
+                let tick = worker.tick(&state.borrow());
+                if let Some(event) = tick.await? {
+                    state_tx.send(event).await?;
+                }
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+impl<'f, S> Wiring<'f, S> {
+    pub(crate) fn failure_policy(&self, id: &WorkerId) -> Option<FailurePolicy> {
+        self.failure_policies.lock().unwrap().get(id).copied()
+    }
+}
+
+impl<'f, S> Wiring<'f, S>
+where
+    'f: 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    async fn state_injector<WorkerState>(
+        metrics: Arc<Metrics>,
+        worker: Arc<WorkerMetrics>,
+        provider_stream: impl Stream<Item = WorkerState>,
+        mut store_tx: mpsc::Sender<Command<'f, S>>,
+    ) where
+        WorkerState: InjectedTo<S> + Serialize + fmt::Debug + Send + 'f,
+    {
+        futures::pin_mut!(provider_stream);
+        while let Some(data) = provider_stream.next().await {
+            debug!(?data, "Got some data");
+            worker.produced.fetch_add(1, Ordering::Relaxed);
+
+            metrics.store_queued(1);
+            if let Err(error) = store_tx.send(store::event(worker.clone(), data)).await {
+                metrics.store_queued(-1);
+                debug!(?error, "Store is gone - stop injecting");
+                break;
+            }
+        }
+    }
+
+    /// Asks the store for a reduction whenever an event was injected and
+    /// the queue has room, and passes on what differs from the last one.
+    async fn state_reducer<WorkerState, E>(
+        worker: Arc<WorkerMetrics>,
+        mut store_tx: mpsc::Sender<Command<'f, S>>,
+        mut state_rx: watch::Receiver<S>,
+        subscription: Arc<Subscription>,
+        mut consumer: impl Sink<WorkerState, Error = E> + Send + Unpin,
+        backpressure: Backpressure,
+        capacity: usize,
+    ) where
+        WorkerState: PartialEq + Clone + Send + Sync + fmt::Debug + 'f,
+        E: fmt::Debug + Send + Sync,
+        S: Reduced<WorkerState>,
+    {
+        let (queue_tx, queue_rx) = queue::bounded(backpressure, capacity, worker.clone());
+        let reducing = worker.clone();
+        let reduce = async move {
+            let mut last = None;
+            loop {
+                state_rx.borrow_and_update();
+                let (reduced_tx, reduced_rx) = oneshot::channel();
+                let command = store::reduction(reducing.clone(), reduced_tx);
+                if store_tx.send(command).await.is_err() {
+                    debug!("Store is gone - nothing to reduce");
+                    return;
+                }
+                let Ok(data) = reduced_rx.await else {
+                    debug!("Store is gone - nothing to reduce");
+                    return;
+                };
+                subscription.subscribed();
+                if S::is_new(&data, last.as_ref()) {
+                    last = Some(data.clone());
+                    if !queue_tx.send(data).await {
+                        return;
+                    }
+                }
+                if state_rx.changed().await.is_err() {
+                    return;
+                }
+            }
+        };
+        let forward = async move {
+            while let Some(data) = queue_rx.recv().await {
+                if let Err(error) = consumer.send(data).await {
+                    debug!(?error, "Consumer is gone - stop reducing");
+                    return;
+                }
+                worker.consumed.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+        future::join(reduce, forward).await;
+    }
+
+    fn supervisor(
+        &self,
+        id: WorkerId,
+        options: &WorkerOptions,
+    ) -> (Supervisor, Arc<WorkerMetrics>) {
+        if let Some(policy) = options.on_failure {
+            self.failure_policies
+                .lock()
+                .unwrap()
+                .insert(id.clone(), policy);
+        }
+        let worker = self.metrics.worker(id, options.backpressure);
+        let supervisor = Supervisor::new(
+            worker.id.clone(),
+            options.restart.clone(),
+            worker.restarts.clone(),
+        );
+        (supervisor, worker)
+    }
+
+    pub(crate) fn producer<W, WorkerState>(
+        &self,
+        id: WorkerId,
+        factory: impl Fn() -> W + Send + Sync + 'static,
+        options: WorkerOptions,
+    ) -> Runner<'f>
+    where
+        W: worker::ProducerWorker<'f, WorkerState> + Send + Sync + 'static,
+        WorkerState: InjectedTo<S> + Serialize + fmt::Debug + Send + Sync + 'f,
+    {
+        let (supervisor, metrics) = self.supervisor(id, &options);
+        let store_tx = self.store_tx.clone();
+        let app_metrics = self.metrics.clone();
+        let runner = move |shutdown: Shutdown| {
+            let attempt = {
+                let shutdown = shutdown.clone();
+                move || {
+                    let worker = Box::new(factory());
+                    let (inducer_tx, inducer_rx) = mpsc::channel::<WorkerState>(0);
+                    let (queue_tx, queue_rx) = queue::bounded(
+                        options.backpressure,
+                        options.capacity.unwrap_or(PRODUCER_CAPACITY),
+                        metrics.clone(),
+                    );
+                    // `state_injector` ends as soon as the worker drops its sender
+                    // and the queue is drained
+                    let injector = Self::state_injector(
+                        app_metrics.clone(),
+                        metrics.clone(),
+                        queue_rx.into_stream(),
+                        store_tx.clone(),
+                    );
+                    let work =
+                        maybe_compute_bound(worker.work(inducer_tx, shutdown.clone()), &options);
+
+                    async move {
+                        let (result, (), ()) =
+                            future::join3(work, queue_tx.send_all(inducer_rx), injector).await;
+                        result
+                    }
+                    .boxed()
+                }
+            };
+            supervisor.supervise(shutdown, attempt).boxed()
+        };
+        Box::new(runner)
+    }
+
+    pub(crate) fn scheduled<W, WorkerState>(
+        &self,
+        id: WorkerId,
+        schedule: Schedule,
+        factory: impl Fn() -> W + Send + Sync + 'static,
+        options: WorkerOptions,
+    ) -> Runner<'f>
+    where
+        W: ScheduledWorker<'f, S, WorkerState> + Send + Sync + 'static,
+        WorkerState: InjectedTo<S> + Serialize + fmt::Debug + Send + Sync + 'f,
+    {
+        let state = self.state_rx.clone();
+        let scheduled = move || Scheduled {
+            schedule: schedule.clone(),
+            worker: factory(),
+            state: state.clone(),
+        };
+        self.producer(id, scheduled, options)
+    }
+
+    pub(crate) fn consumer<W, WorkerState, E>(
+        &self,
+        id: WorkerId,
+        factory: impl Fn() -> W + Send + Sync + 'static,
+        options: WorkerOptions,
+    ) -> Runner<'f>
+    where
+        W: worker::ConsumerWorker<'f, WorkerState, E> + Send + Sync + 'static,
+        WorkerState: PartialEq + Clone + fmt::Debug + Send + Sync + 'f,
+        E: fmt::Debug + Send + Sync + 'f,
+        S: Reduced<WorkerState>,
+    {
+        let (supervisor, metrics) = self.supervisor(id, &options);
+        let store_tx = self.store_tx.clone();
+        let state_rx = self.state_rx.clone();
+        let subscription = self.subscriptions.expect();
+        let runner = move |shutdown: Shutdown| {
+            let attempt = {
+                let shutdown = shutdown.clone();
+                move || {
+                    let worker = Box::new(factory());
+                    let (reduced_state_tx, reduced_state_rx) = worker.provide_input_stream();
+
+                    let reducer = Self::state_reducer(
+                        metrics.clone(),
+                        store_tx.clone(),
+                        state_rx.clone(),
+                        subscription.clone(),
+                        reduced_state_tx,
+                        options.backpressure,
+                        options.capacity.unwrap_or(CONSUMER_CAPACITY),
+                    )
+                    .boxed();
+                    let work = maybe_compute_bound(
+                        worker.work(reduced_state_rx, shutdown.clone()),
+                        &options,
+                    );
+
+                    // The reducer never ends by itself, so the attempt lasts as long as the worker does
+                    async move {
+                        match future::select(work, reducer).await {
+                            Either::Left((result, _)) => result,
+                            Either::Right(_) => Ok(()),
+                        }
+                    }
+                    .boxed()
+                }
+            };
+            supervisor.supervise(shutdown, attempt).boxed()
+        };
+        Box::new(runner)
+    }
+
+    pub(crate) fn worker<W, Consumed, II, Produced, E>(
+        &self,
+        id: WorkerId,
+        factory: impl Fn() -> W + Send + Sync + 'static,
+        options: WorkerOptions,
+    ) -> Runner<'f>
+    where
+        W: worker::Worker<'f, Consumed, II, Produced, E> + Send + Sync + 'static,
+        Consumed: PartialEq + Clone + fmt::Debug + Send + Sync + 'f,
+        II: fmt::Debug + Send + Sync + 'f,
+        Produced: InjectedTo<S> + Serialize + fmt::Debug + Send + Sync + 'f,
+        E: fmt::Debug + Send + Sync + 'f,
+        S: Reduced<Consumed>,
+    {
+        let (supervisor, metrics) = self.supervisor(id, &options);
+        let store_tx = self.store_tx.clone();
+        let state_rx = self.state_rx.clone();
+        let subscription = self.subscriptions.expect();
+        let app_metrics = self.metrics.clone();
+        let runner = move |shutdown: Shutdown| {
+            let attempt = {
+                let shutdown = shutdown.clone();
+                move || {
+                    let worker = Box::new(factory());
+                    let (inducer_tx, inducer_rx) = mpsc::channel::<Produced>(PRODUCER_CAPACITY);
+                    let (reduced_state_tx, reduced_state_rx) = worker.provide_input_stream();
+
+                    // `state_reducer and `state_injector` propagate state to and from the worker
+                    let reducer = Self::state_reducer(
+                        metrics.clone(),
+                        store_tx.clone(),
+                        state_rx.clone(),
+                        subscription.clone(),
+                        reduced_state_tx,
+                        options.backpressure,
+                        options.capacity.unwrap_or(CONSUMER_CAPACITY),
+                    )
+                    .boxed();
+                    let injector = Self::state_injector(
+                        app_metrics.clone(),
+                        metrics.clone(),
+                        inducer_rx,
+                        store_tx.clone(),
+                    );
+                    let work = maybe_compute_bound(
+                        worker.work(reduced_state_rx, inducer_tx, shutdown.clone()),
+                        &options,
+                    );
+
+                    async move {
+                        let work = future::join(work, injector).map(|(result, ())| result);
+                        match future::select(work.boxed(), reducer).await {
+                            Either::Left((result, _)) => result,
+                            Either::Right(_) => Ok(()),
+                        }
+                    }
+                    .boxed()
+                }
+            };
+            supervisor.supervise(shutdown, attempt).boxed()
+        };
+        Box::new(runner)
+    }
+}
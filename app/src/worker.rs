@@ -1,7 +1,152 @@
 use core::fmt;
+use std::{error::Error, str::FromStr, time::Duration};
 
-use futures::{channel::mpsc, future::BoxFuture, Sink, Stream};
-use tokio::sync::watch;
+use chrono::{DateTime, Utc};
+use futures::{
+    channel::mpsc,
+    future::{self, BoxFuture},
+    FutureExt, Sink, Stream,
+};
+use serde::Deserialize;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+
+use crate::{
+    shutdown::Shutdown,
+    supervisor::{FailurePolicy, RestartPolicy},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WorkerId {
+    pub index: usize,
+    pub name: String,
+}
+
+impl WorkerId {
+    pub(crate) fn new<W>(index: usize) -> Self {
+        let name = std::any::type_name::<W>();
+        let name = name.split('<').next().unwrap_or(name);
+        let name = name.rsplit("::").next().unwrap_or(name);
+        Self {
+            index,
+            name: name.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for WorkerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}#{}", self.name, self.index)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WorkerError {
+    #[error("state channel is closed")]
+    ChannelClosed(#[from] mpsc::SendError),
+    #[error("panicked: {0}")]
+    Panicked(String),
+    #[error(transparent)]
+    Other(Box<dyn Error + Send + Sync>),
+}
+
+impl WorkerError {
+    pub fn other(error: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        Self::Other(error.into())
+    }
+}
+
+/// What happens when a worker does not keep up and its queue is full. The
+/// queue sits in front of what the worker consumes; producers have no input,
+/// so for them it holds what they produce until the store takes it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backpressure {
+    /// Wait for room. A consumer's state is reduced once it has room again,
+    /// a producer waits to hand over what it produced.
+    #[default]
+    Block,
+    /// Make room by dropping the value queued for longest
+    DropOldest,
+    /// Drop the value which does not fit
+    DropNewest,
+    /// Keep only the latest value, whatever the capacity
+    CoalesceLatest,
+}
+
+impl Backpressure {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Block => "block",
+            Self::DropOldest => "drop_oldest",
+            Self::DropNewest => "drop_newest",
+            Self::CoalesceLatest => "coalesce_latest",
+        }
+    }
+}
+
+impl fmt::Display for Backpressure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A consumer is only handed its reduced state when it differs from the last
+/// one it got. Without a `capacity` a consumer's queue holds one value and a
+/// producer's holds a hundred.
+#[derive(Debug, Clone, Default)]
+pub struct WorkerOptions {
+    pub(crate) restart: RestartPolicy,
+    pub(crate) backpressure: Backpressure,
+    pub(crate) capacity: Option<usize>,
+    pub(crate) compute_bound: bool,
+    pub(crate) on_failure: Option<FailurePolicy>,
+}
+
+impl WorkerOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn restart(mut self, policy: RestartPolicy) -> Self {
+        self.restart = policy;
+        self
+    }
+
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// For workers which keep a thread busy between awaits. Such a worker
+    /// runs on a thread with a current-thread runtime of its own, so the
+    /// store and the other workers go on meanwhile. What it borrows has to
+    /// be `'static` then.
+    pub fn compute_bound(mut self) -> Self {
+        self.compute_bound = true;
+        self
+    }
+
+    /// What the app does once this worker has failed for good, instead of
+    /// what `AppBuilder::on_failure` says.
+    pub fn on_failure(mut self, policy: FailurePolicy) -> Self {
+        self.on_failure = Some(policy);
+        self
+    }
+}
+
+impl From<RestartPolicy> for WorkerOptions {
+    fn from(restart: RestartPolicy) -> Self {
+        Self {
+            restart,
+            ..Self::default()
+        }
+    }
+}
 
 pub trait Worker<'f, Consumed, II, Produced, E>
 where
@@ -16,11 +161,16 @@ where
         self: Box<Self>,
         state_rx: Self::InputStream,
         state_tx: mpsc::Sender<Produced>,
-    ) -> BoxFuture<'f, ()>;
+        shutdown: Shutdown,
+    ) -> BoxFuture<'f, Result<(), WorkerError>>;
 }
 
 pub trait ProducerWorker<'f, T> {
-    fn work(self: Box<Self>, state_tx: mpsc::Sender<T>) -> BoxFuture<'f, ()>;
+    fn work(
+        self: Box<Self>,
+        state_tx: mpsc::Sender<T>,
+        shutdown: Shutdown,
+    ) -> BoxFuture<'f, Result<(), WorkerError>>;
 }
 
 pub trait ConsumerWorker<'f, T, E>
@@ -30,5 +180,269 @@ where
     type Stream: Stream<Item = T> + Send + Sync + Unpin;
     type Sink: Sink<T, Error = E> + Send + Sync + Unpin;
     fn provide_input_stream(&self) -> (Self::Sink, Self::Stream);
-    fn work(self: Box<Self>, state_rx: Self::Stream) -> BoxFuture<'f, ()>;
+    fn work(
+        self: Box<Self>,
+        state_rx: Self::Stream,
+        shutdown: Shutdown,
+    ) -> BoxFuture<'f, Result<(), WorkerError>>;
+}
+
+/// When a `ScheduledWorker` runs: every `Duration`, counting from the start
+/// of the app, or on a cron expression in UTC with a leading seconds field,
+/// e.g. `0 0 9 * * *` for every day at 9:00.
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Every(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid cron expression `{expression}`: {source}")]
+pub struct ScheduleError {
+    expression: String,
+    source: cron::error::Error,
+}
+
+impl Schedule {
+    pub fn every(period: Duration) -> Self {
+        Self::Every(period)
+    }
+
+    pub fn cron(expression: &str) -> Result<Self, ScheduleError> {
+        cron::Schedule::from_str(expression)
+            .map(|schedule| Self::Cron(Box::new(schedule)))
+            .map_err(|source| ScheduleError {
+                expression: expression.to_string(),
+                source,
+            })
+    }
+
+    pub(crate) fn ticks(&self) -> Ticks {
+        match self {
+            Self::Every(period) => {
+                let mut interval = tokio::time::interval_at(Instant::now() + *period, *period);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                Ticks::Every(interval)
+            }
+            Self::Cron(schedule) => Ticks::Cron {
+                schedule: schedule.clone(),
+                started: (Utc::now(), Instant::now()),
+            },
+        }
+    }
+}
+
+pub(crate) enum Ticks {
+    Every(Interval),
+    /// The wall clock only tells where the schedule starts, tokio's clock
+    /// runs it, so that it can be paused like the one of `Every`.
+    Cron {
+        schedule: Box<cron::Schedule>,
+        started: (DateTime<Utc>, Instant),
+    },
+}
+
+impl Ticks {
+    /// `false` once a cron schedule has no runs left.
+    pub(crate) async fn next(&mut self) -> bool {
+        match self {
+            Self::Every(interval) => {
+                interval.tick().await;
+                true
+            }
+            Self::Cron {
+                schedule,
+                started: (wall, instant),
+            } => {
+                let elapsed = chrono::Duration::from_std(instant.elapsed())
+                    .unwrap_or_else(|_| chrono::Duration::max_value());
+                let Some(at) = schedule.after(&(*wall + elapsed)).next() else {
+                    return false;
+                };
+                let until_at = (at - *wall).to_std().unwrap_or_default();
+                tokio::time::sleep_until(*instant + until_at).await;
+                true
+            }
+        }
+    }
+}
+
+/// Runs on a `Schedule` with read access to the app state; what a tick
+/// returns is injected. Any `FnMut(&S) -> Option<T>` is one, e.g.
+/// `|_: &S| Some(Heartbeat)`.
+pub trait ScheduledWorker<'f, S, T> {
+    fn tick(&mut self, state: &S) -> BoxFuture<'f, Result<Option<T>, WorkerError>>;
+}
+
+impl<'f, S, T, F> ScheduledWorker<'f, S, T> for F
+where
+    F: FnMut(&S) -> Option<T>,
+    T: Send + 'f,
+{
+    fn tick(&mut self, state: &S) -> BoxFuture<'f, Result<Option<T>, WorkerError>> {
+        future::ready(Ok(self(state))).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::SinkExt;
+    use serde::Serialize;
+
+    use super::*;
+    use crate::{supervisor::RestartPolicy, App, InjectedTo};
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Total(u64);
+
+    #[derive(Debug, Serialize)]
+    struct Add(u64);
+
+    impl InjectedTo<Total> for Add {
+        const NAME: &'static str = "Add";
+
+        fn inject_to(self, state: Total) -> Total {
+            Total(state.0 + self.0)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn scheduled_worker_ticks_with_current_state() {
+        let app = App::build(Total::default())
+            .add_scheduled(
+                Schedule::every(Duration::from_secs(60)),
+                || |total: &Total| (total.0 < 3).then_some(Add(1)),
+                RestartPolicy::Never,
+            )
+            .build();
+        let state = app.state();
+        let shutdown = app.shutdown_handle();
+        let ticks = std::sync::Mutex::new(Vec::new());
+
+        let watch = async {
+            let mut state = state.clone();
+            let started = Instant::now();
+            tokio::time::timeout(Duration::from_secs(5 * 60 + 30), async {
+                while state.changed().await.is_ok() {
+                    ticks.lock().unwrap().push(started.elapsed().as_secs());
+                }
+            })
+            .await
+            .ok();
+            shutdown.shutdown();
+        };
+        let (outcome, ()) = future::join(app.run(), watch).await;
+
+        assert!(outcome.is_clean());
+        assert_eq!(*state.borrow(), Total(3));
+        assert_eq!(*ticks.lock().unwrap(), vec![60, 120, 180]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cron_schedule_follows_the_paused_clock() {
+        let app = App::build(Total::default())
+            .add_scheduled(
+                Schedule::cron("*/20 * * * * *").unwrap(),
+                || |total: &Total| (total.0 < 3).then_some(Add(1)),
+                RestartPolicy::Never,
+            )
+            .build();
+        let state = app.state();
+        let shutdown = app.shutdown_handle();
+        let ticks = std::sync::Mutex::new(Vec::new());
+
+        let watch = async {
+            let mut state = state.clone();
+            let started = Instant::now();
+            tokio::time::timeout(Duration::from_secs(90), async {
+                while state.changed().await.is_ok() {
+                    ticks.lock().unwrap().push(started.elapsed());
+                }
+            })
+            .await
+            .ok();
+            shutdown.shutdown();
+        };
+        let (outcome, ()) = future::join(app.run(), watch).await;
+
+        assert!(outcome.is_clean());
+        assert_eq!(*state.borrow(), Total(3));
+        // The first tick waits for the next full 20 seconds of the wall clock
+        let ticks = ticks.lock().unwrap();
+        assert_eq!(ticks.len(), 3);
+        assert!(ticks[0] <= Duration::from_secs(20), "{ticks:?}");
+        for pair in ticks.windows(2) {
+            assert_eq!((pair[1] - pair[0]).as_millis(), 20_000, "{ticks:?}");
+        }
+    }
+
+    /// Keeps its thread busy before it adds 100
+    struct Busy;
+
+    impl<'f> ProducerWorker<'f, Add> for Busy {
+        fn work(
+            self: Box<Self>,
+            mut state_tx: mpsc::Sender<Add>,
+            _shutdown: Shutdown,
+        ) -> BoxFuture<'f, Result<(), WorkerError>> {
+            async move {
+                std::thread::sleep(Duration::from_millis(300));
+                state_tx.send(Add(100)).await?;
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    /// Adds 1 ten times, every 10ms
+    struct Ticker;
+
+    impl<'f> ProducerWorker<'f, Add> for Ticker {
+        fn work(
+            self: Box<Self>,
+            mut state_tx: mpsc::Sender<Add>,
+            _shutdown: Shutdown,
+        ) -> BoxFuture<'f, Result<(), WorkerError>> {
+            async move {
+                for _ in 0..10 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    state_tx.send(Add(1)).await?;
+                }
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    // A single thread for the app, the busy worker gets one of its own
+    #[tokio::test]
+    async fn compute_bound_worker_leaves_app_to_other_workers() {
+        let app = App::build(Total::default())
+            .add_producer(|| Busy, WorkerOptions::new().compute_bound())
+            .add_producer(|| Ticker, RestartPolicy::Never)
+            .build();
+        let state = app.state();
+
+        let watch = async {
+            let mut state = state.clone();
+            let mut seen = Vec::new();
+            while state.changed().await.is_ok() {
+                seen.push(state.borrow().0);
+            }
+            seen
+        };
+        let (outcome, seen) = future::join(app.run(), watch).await;
+
+        assert!(outcome.is_clean());
+        assert_eq!(*state.borrow(), Total(110));
+        // The ticker was done and its adds were stored while `Busy` blocked
+        assert!(seen.contains(&10), "seen {seen:?}");
+    }
+
+    #[test]
+    fn parses_cron_with_seconds() {
+        assert!(Schedule::cron("0 0 9 * * *").is_ok());
+        let error = Schedule::cron("every morning").unwrap_err();
+        assert!(error.to_string().contains("`every morning`"));
+    }
 }
@@ -1,10 +1,18 @@
-use std::{fs::File, time::Duration};
+use std::{
+    fs::File,
+    io::BufReader,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
 
-use app::{App, InjectedTo, Reduced};
+use app::{
+    journal::{Journal, JournalError, Replay},
+    registry::{Registry, WorkerSpec},
+    App, AppState,
+};
 use clap::Parser;
 use serde::Deserialize;
-use tg_reporter::{TelegramReporter, TelegramReporterConfig};
-use tracing::info;
+use tracing::{error, info, warn};
 use worker::Signal;
 
 use crate::worker::PriceCollector;
@@ -13,34 +21,42 @@ mod worker;
 
 #[derive(Deserialize)]
 struct Config {
-    threshold: f64,
-    #[serde(with = "humantime_serde")]
-    period: Duration,
-    tg: TelegramReporterConfig,
+    workers: Vec<WorkerSpec>,
+    journal: Option<PathBuf>,
+    metrics: Option<SocketAddr>,
 }
 
-#[derive(Default, Debug, PartialEq, Clone, Eq)]
+#[derive(AppState, Default, Debug, PartialEq, Clone, Eq)]
 struct Klaxo {
+    #[inject(push)]
+    #[reduce(take)]
     signals: Vec<Signal>,
 }
 
 #[derive(Parser)]
 struct Opts {
-    #[arg(short, long)]
-    config_file: String,
+    #[arg(short, long, required_unless_present = "replay")]
+    config_file: Option<String>,
+    /// Replay a journal instead of running the workers
+    #[arg(long)]
+    replay: Option<PathBuf>,
 }
 
-impl InjectedTo<Klaxo> for Signal {
-    fn inject_to(self, mut state: Klaxo) -> Klaxo {
-        state.signals.push(self);
-        state
+fn replay(path: &Path) -> Result<(), JournalError> {
+    let journal = BufReader::new(File::open(path)?);
+    let (_, output) = Replay::new(Klaxo::default())
+        .event::<Signal>()
+        .consumer::<Vec<Signal>>()
+        .run(journal)?;
+    for signal in output.consumed::<Vec<Signal>>().iter().flatten() {
+        println!("{signal}");
     }
+    Ok(())
 }
 
-impl Reduced<Vec<Signal>> for Klaxo {
-    fn reduce(&mut self) -> Vec<Signal> {
-        std::mem::take(&mut self.signals)
-    }
+fn registry() -> Registry<'static, Klaxo> {
+    let registry = Registry::new().producer("multi_price_feed", PriceCollector::new);
+    tg_reporter::register::<_, Signal>(registry)
 }
 
 async fn runner() {
@@ -48,18 +64,151 @@ async fn runner() {
     let cli_opts = Opts::parse();
     tracing_subscriber::fmt::init();
 
-    let config: Config =
-        serde_yaml::from_reader(File::open(cli_opts.config_file).unwrap()).unwrap();
-    let app = App::build(Klaxo::default())
-        .add_producer(PriceCollector::new(config.threshold, config.period))
-        .add_consumer(TelegramReporter::new(config.tg))
+    if let Some(path) = cli_opts.replay {
+        if let Err(e) = replay(&path) {
+            error!(?path, %e, "Cannot replay journal");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let config_file = cli_opts.config_file.expect("config file is required");
+    let config: Config = serde_yaml::from_reader(File::open(config_file).unwrap()).unwrap();
+    let Config {
+        workers,
+        journal,
+        metrics,
+    } = config;
+    let mut builder = App::build(Klaxo::default());
+    if let Some(path) = journal {
+        builder = builder.journal(Journal::create(path).unwrap());
+    }
+    if let Some(addr) = metrics {
+        builder = builder.metrics(addr);
+    }
+    let app = builder
+        .add_workers(&registry(), &workers)
+        .expect("Invalid workers config")
         .build();
 
     info!("run app");
-    app.run().await
+    let outcome = app.run().await;
+    if !outcome.is_clean() {
+        warn!(
+            not_stopped = ?outcome.not_stopped,
+            failures = ?outcome.failures,
+            "App did not stop cleanly"
+        );
+    }
 }
 
 #[tokio::main]
 async fn main() {
     runner().await;
 }
+
+#[cfg(test)]
+mod tests {
+    use app::{
+        supervisor::RestartPolicy,
+        testing::{Harness, Recording},
+        StreamExt,
+    };
+    use futures::stream;
+    use multi_price_feed::Price;
+    use sources_common::{
+        instrument::{Exchange, Instrument, InstrumentStatus, MarketType},
+        registry::SymbolRegistry,
+    };
+
+    use super::*;
+    use crate::worker::PriceTracker;
+
+    fn quote(exchange: Exchange, ticker: &str, base: &str, price: &str) -> Price {
+        let instrument = Instrument {
+            exchange,
+            market: MarketType::LinearPerpetual,
+            ticker: ticker.to_string(),
+            base_asset: base.to_string(),
+            quote_asset: "USDT".to_string(),
+            tick_size: None,
+            lot_size: None,
+            contract_multiplier: "1".parse().unwrap(),
+            status: InstrumentStatus::Trading,
+        };
+        let listing = SymbolRegistry::new().insert(&instrument).unwrap().clone();
+        Price {
+            instrument,
+            listing,
+            price: price.parse().unwrap(),
+        }
+    }
+
+    fn price(ticker: &str, price: &str) -> Price {
+        let base = ticker.trim_end_matches("USDT");
+        quote(Exchange::Binance, ticker, base, price)
+    }
+
+    #[test]
+    fn same_asset_is_compared_across_exchanges() {
+        let mut tracker = PriceTracker::new(0.01);
+        let signals: Vec<Signal> = [
+            quote(Exchange::Binance, "BTCUSDT", "BTC", "100"),
+            quote(Exchange::Kucoin, "XBTUSDTM", "XBT", "100.5"),
+            quote(Exchange::Bybit, "BTCUSDT", "BTC", "102.0"),
+            quote(Exchange::Binance, "1000PEPEUSDT", "1000PEPE", "0.0100"),
+            // 2% above the 0.00001 binance quotes per PEPE
+            quote(Exchange::Kucoin, "PEPEUSDTM", "PEPE", "0.0000102"),
+        ]
+        .into_iter()
+        .filter_map(|price| tracker.track(price))
+        .collect();
+
+        assert_eq!(signals.len(), 2, "{signals:?}");
+        assert!(signals[0]
+            .to_string()
+            .contains("bybit:*BTCUSDT* 100\\.5 📈  102\\.0 \\(*1\\.49%*\\)"));
+        assert!(signals[1].to_string().contains("kucoin:*PEPEUSDTM*"));
+        assert!(signals[1].to_string().contains("\\(*2\\.00%*\\)"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn price_moves_reach_the_reporter_once() {
+        let prices = vec![
+            price("BTCUSDT", "100"),
+            price("ETHUSDT", "10"),
+            price("BTCUSDT", "100.5"),
+            price("BTCUSDT", "102.0"),
+            price("ETHUSDT", "9.0"),
+        ];
+
+        let reported = Recording::new();
+        let finished = Harness::new(Klaxo::default())
+            .with(|app| {
+                app.add_producer(
+                    move || {
+                        PriceCollector::from_prices(
+                            0.01,
+                            stream::iter(prices.clone()).map(Ok).boxed(),
+                        )
+                    },
+                    RestartPolicy::Never,
+                )
+            })
+            .record(&reported)
+            .run()
+            .await;
+
+        assert!(finished.outcome.is_clean());
+        // Reporting takes the signals out of the state, those which arrive
+        // while the reporter is busy come in one batch
+        assert_eq!(finished.state, Klaxo::default());
+        let signals = reported.values().concat();
+        assert_eq!(signals.len(), 2);
+        assert!(signals[0].to_string().contains("BTCUSDT"));
+        assert!(signals[0]
+            .to_string()
+            .contains("100\\.5 📈  102\\.0 \\(*1\\.49%*\\)"));
+        assert!(signals[1].to_string().contains("ETHUSDT"));
+    }
+}
@@ -1,124 +1,209 @@
-use std::{collections::HashMap, fmt::Display, time::Duration};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    sync::Mutex,
+    time::Duration,
+};
 
 use app::{
-    worker::{ProducerWorker, Worker},
+    shutdown::Shutdown,
+    worker::{ProducerWorker, WorkerError},
     FutureExt, SinkExt, StreamExt,
 };
-use multi_price_feed::{GetMultiPriceFeedInput, Price, Symbol};
+use futures::{stream::BoxStream, TryFutureExt};
+use multi_price_feed::{GetMultiPriceFeedInput, Price};
+use serde::{Deserialize, Serialize};
+use sources_common::{
+    decimal,
+    instrument::{Exchange, MarketType},
+    registry::CanonicalSymbol,
+};
 use tracing::info;
-use url::Url;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Deserialize)]
+pub struct PriceCollectorConfig {
+    pub threshold: f64,
+    #[serde(with = "humantime_serde")]
+    pub period: Duration,
+}
+
+type Prices = BoxStream<'static, Result<Price, WorkerError>>;
+
 pub struct PriceCollector {
     threshold: f64,
-    period: Duration,
+    /// Behind a mutex only for the collector to be `Sync`
+    prices: Mutex<Prices>,
+}
+
+impl fmt::Debug for PriceCollector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PriceCollector")
+            .field("threshold", &self.threshold)
+            .finish_non_exhaustive()
+    }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// Prices of the USDT futures on every exchange, polled every `period`.
+async fn exchange_prices(period: Duration) -> Result<BoxStream<'static, Price>, WorkerError> {
+    let mut input = GetMultiPriceFeedInput::new(period);
+    input.add_filter(|instrument| {
+        instrument.is_trading() && instrument.quote_asset.to_uppercase() == "USDT"
+    });
+    input.add_url("binance", "https://fapi.binance.com");
+    input.add_url("kucoin", "https://api-futures.kucoin.com");
+    input.add_url("bybit", "https://api.bybit.com");
+    let price_feed = multi_price_feed::get_multi_price_feed(input)
+        .await
+        .map_err(WorkerError::other)?;
+    Ok(price_feed.boxed())
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum Direction {
     Long,
     Short,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct Signal {
     direction: Direction,
     price: Price,
-    prev_price: f64,
+    /// Per unit, like `Price::unit_price`, and maybe from another exchange
+    prev_price: decimal::Price,
 }
 
-fn tg_price(float: f64) -> String {
-    let price_display = format!("{:.2}", float);
-    price_display.replace('.', "\\.")
+/// Escapes the dots for MarkdownV2; prices keep the digits the exchange sent.
+fn tg_escape(text: impl Display) -> String {
+    text.to_string().replace('.', "\\.")
 }
 impl Signal {
     fn get_ticker_url(&self) -> String {
-        match self.price.symbol.source.as_str() {
-            "binance" => format!(
-                "https://www.binance.com/en/futures/{}",
-                self.price.symbol.ticker
-            ),
-            "bybit" => format!(
-                "https://www.bybit.com/trade/usdt/{}",
-                self.price.symbol.ticker
-            ),
-            "kucoin" => format!(
-                "https://www.kucoin.com/futures/trade/{}",
-                self.price.symbol.ticker
-            ),
-            _ => unimplemented!(),
+        let instrument = &self.price.instrument;
+        match (instrument.exchange, instrument.market) {
+            (Exchange::Binance, _) => {
+                format!("https://www.binance.com/en/futures/{}", instrument.ticker)
+            }
+            (Exchange::Bybit, MarketType::LinearPerpetual) => {
+                format!("https://www.bybit.com/trade/usdt/{}", instrument.ticker)
+            }
+            (Exchange::Bybit, _) => {
+                format!("https://www.bybit.com/trade/inverse/{}", instrument.ticker)
+            }
+            (Exchange::Kucoin, _) => {
+                format!("https://www.kucoin.com/futures/trade/{}", instrument.ticker)
+            }
         }
     }
 
-    fn up_or_down(&self)-> &str {
-        if self.price.price > self.prev_price {
+    fn up_or_down(&self) -> &str {
+        if self.price.unit_price() > self.prev_price {
             "📈 "
         } else {
             "📉"
         }
     }
-
 }
 
 impl Display for Signal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Обнаружено изменение цены: \n {}:*{}* {} {} {} \\(*{:.2}%*\\)\n [Посмотреть]({}) ",
-            self.price.symbol.source,
-            self.price.symbol.ticker,
-            tg_price(self.prev_price),
+            "Обнаружено изменение цены: \n {}:*{}* {} {} {} \\(*{}%*\\)\n [Посмотреть]({}) ",
+            self.price.instrument.exchange,
+            self.price.instrument.ticker,
+            tg_escape(self.prev_price),
             self.up_or_down(),
-            tg_price(self.price.price),
-            tg_price(self.price.percentage(self.prev_price) * 100.0),
+            tg_escape(self.price.unit_price()),
+            tg_escape(format_args!(
+                "{:.2}",
+                self.price.percentage(self.prev_price) * 100.0
+            )),
             self.get_ticker_url(),
         )
     }
 }
 
-impl Eq for Signal {}
-
 impl PriceCollector {
-    pub fn new(threshold: f64, period: Duration) -> Self {
-        PriceCollector { threshold, period }
+    pub fn new(config: PriceCollectorConfig) -> Self {
+        let PriceCollectorConfig { threshold, period } = config;
+        let prices = exchange_prices(period).map_ok(|prices| prices.map(Ok));
+        Self::from_prices(threshold, prices.try_flatten_stream().boxed())
+    }
+
+    /// Collects `prices`, e.g. from the exchanges, until one is an error.
+    pub fn from_prices(threshold: f64, prices: Prices) -> Self {
+        Self {
+            threshold,
+            prices: Mutex::new(prices),
+        }
+    }
+}
+
+/// Remembers the last unit price of every symbol, whichever exchange quoted
+/// it, and signals the moves above the threshold.
+pub(crate) struct PriceTracker {
+    threshold: f64,
+    prices: HashMap<CanonicalSymbol, decimal::Price>,
+}
+
+impl PriceTracker {
+    pub(crate) fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            prices: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn track(&mut self, item: Price) -> Option<Signal> {
+        let price = item.unit_price();
+        let Some(entry) = self.prices.get_mut(&item.listing.symbol) else {
+            info!(?item, "Add price item");
+            self.prices.insert(item.listing.symbol.clone(), price);
+            return None;
+        };
+        let prev_price = std::mem::replace(entry, price);
+        let diff = item.percentage(prev_price);
+        if diff <= self.threshold {
+            return None;
+        }
+        let direction = if prev_price > price {
+            Direction::Short
+        } else {
+            Direction::Long
+        };
+        let signal = Signal {
+            direction,
+            price: item,
+            prev_price,
+        };
+        info!(?signal, diff, "report");
+        Some(signal)
     }
 }
 
 impl<'f> ProducerWorker<'f, Signal> for PriceCollector {
-    fn work(self: Box<Self>, mut state_tx: app::mpsc::Sender<Signal>) -> app::BoxFuture<'f, ()> {
+    fn work(
+        self: Box<Self>,
+        mut state_tx: app::mpsc::Sender<Signal>,
+        mut shutdown: Shutdown,
+    ) -> app::BoxFuture<'f, Result<(), WorkerError>> {
         async move {
-            let mut price_storage: HashMap<Symbol, f64> = HashMap::new();
-            let mut input = GetMultiPriceFeedInput::new(self.period);
-            input.add_filter(|sym| sym.quote_asset.to_uppercase() == "USDT");
-            input.add_url("binance", "https://fapi.binance.com");
-            input.add_url("kucoin", "https://api-futures.kucoin.com");
-            input.add_url("bybit", "https://api.bybit.com");
-            let mut price_feed = multi_price_feed::get_multi_price_feed(input).await;
-
-            while let Some(item) = price_feed.next().await {
-                if let Some(entry) = price_storage.get_mut(&item.symbol) {
-                    let diff = item.percentage(*entry);
-
-                    if diff > self.threshold {
-                        let direction = if *entry > item.price {
-                            Direction::Short
-                        } else {
-                            Direction::Long
-                        };
-                        let signal = Signal {
-                            direction,
-                            price: item.clone(),
-                            prev_price: *entry,
-                        };
-                        info!(?signal, diff, "report");
-                        state_tx.send(signal).await.ok();
-                    }
-                    *entry = item.price
-                } else {
-                    info!(?item, "Add price item");
-                    price_storage.insert(item.symbol, item.price);
+            let mut tracker = PriceTracker::new(self.threshold);
+            let mut price_feed = self.prices.into_inner().unwrap();
+
+            loop {
+                let item = tokio::select! {
+                    item = price_feed.next() => item,
+                    _ = shutdown.wait() => None,
+                };
+                let Some(item) = item.transpose()? else {
+                    break;
+                };
+                if let Some(signal) = tracker.track(item) {
+                    state_tx.send(signal).await?;
                 }
             }
+            Ok(())
         }
         .boxed()
     }
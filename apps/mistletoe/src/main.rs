@@ -1,91 +1,349 @@
-use std::fs::File;
+use std::{
+    fs::File,
+    io::BufReader,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use app::{App, InjectedTo, Reduced};
+use app::{
+    checkpoint::Checkpoint,
+    control::Control,
+    journal::{Journal, JournalError, Replay},
+    registry::{Registry, WorkerSpec},
+    supervisor::{FailurePolicy, RestartPolicy},
+    worker::Schedule,
+    App, AppBuilder, AppState, Reduced,
+};
 use clap::Parser;
 use market_feed::{candles::Candles, order_book::OrderBook, trade::TradesAggregate};
 use predictor::{PredictorSignal, WorkerInput};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use stock_data_providers::market_feed::{config::PriceFeedConfig, PriceFeed, PriceFeedData};
-use tg_reporter::{TelegramReporter, TelegramReporterConfig};
-use tracing::info;
+use tg_reporter::{CommandHandler, TelegramReporter};
+use tracing::{error, info, warn};
 
-use crate::predictor::{Predictor, PredictorConfig};
+use crate::predictor::Predictor;
 
 mod histogram;
 mod predictor;
 
-#[derive(Debug, Clone, Default, Eq, PartialEq)]
+/// Bump whenever `Mistletoe` changes shape, so old checkpoints are rejected.
+/// 2 has decimal prices, 3 UTC timestamps.
+const STATE_VERSION: u32 = 3;
+
+#[derive(AppState, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 struct Mistletoe {
+    #[inject(from = PriceFeedData, merge)]
     candles: Candles,
+    #[inject(from = PriceFeedData, merge)]
     orderbook: OrderBook,
+    #[inject(from = PriceFeedData, merge)]
     trades_aggregate: TradesAggregate,
+    #[inject(push)]
+    #[reduce(take)]
     trade_signals: Vec<PredictorSignal>,
 }
 
 #[derive(Deserialize)]
 struct Config {
-    price_feed: PriceFeedConfig,
-    predictor: PredictorConfig,
-    tg: TelegramReporterConfig,
+    workers: Vec<WorkerSpec>,
+    journal: Option<PathBuf>,
+    metrics: Option<SocketAddr>,
+    checkpoint: Option<CheckpointConfig>,
+    /// How often to report `IAmOk` to telegram
+    #[serde(default, with = "humantime_serde")]
+    heartbeat: Option<Duration>,
+}
+
+#[derive(Deserialize)]
+struct CheckpointConfig {
+    path: PathBuf,
+    #[serde(with = "humantime_serde")]
+    interval: Duration,
 }
 
 #[derive(Parser)]
 struct Opts {
-    #[arg(short, long)]
-    config_file: String,
+    #[arg(short, long, required_unless_present = "replay")]
+    config_file: Option<String>,
+    /// Replay a journal instead of running the workers
+    #[arg(long)]
+    replay: Option<PathBuf>,
 }
 
-impl InjectedTo<Mistletoe> for PriceFeedData {
-    fn inject_to(self, mut state: Mistletoe) -> Mistletoe {
-        if let Some(c) = self.candles {
-            state.candles = c;
-        }
-        if let Some(c) = self.orderbook {
-            state.orderbook = c;
-        }
-        if let Some(t) = self.trades_aggregate {
-            state.trades_aggregate = t;
-        }
-        state
+impl Reduced<WorkerInput> for Mistletoe {
+    fn reduce(&mut self) -> WorkerInput {
+        (self.orderbook.clone(), self.trades_aggregate.clone())
     }
 }
 
-impl InjectedTo<Mistletoe> for PredictorSignal {
-    fn inject_to(self, mut state: Mistletoe) -> Mistletoe {
-        state.trade_signals.push(self);
-        state
+fn replay(path: &Path) -> Result<(), JournalError> {
+    let journal = BufReader::new(File::open(path)?);
+    let (state, output) = Replay::new(Mistletoe::default())
+        .event::<PriceFeedData>()
+        .event::<PredictorSignal>()
+        .consumer::<Vec<PredictorSignal>>()
+        .run(journal)?;
+    for signal in output.consumed::<Vec<PredictorSignal>>().iter().flatten() {
+        println!("{signal}");
     }
+    info!(?state, "replay finished");
+    Ok(())
 }
 
-impl Reduced<WorkerInput> for Mistletoe {
-    fn reduce(&mut self) -> WorkerInput {
-        (self.orderbook.clone(), self.trades_aggregate.clone())
-    }
+fn registry(watch: CommandHandler) -> Registry<'static, Mistletoe> {
+    stock_data_providers::market_feed::register(Registry::new())
+        .worker("predictor", Predictor::new)
+        .consumer::<_, _, Vec<PredictorSignal>, _>("telegram", move |config| {
+            TelegramReporter::new(config).command("watch", watch.clone())
+        })
 }
 
-impl Reduced<Vec<PredictorSignal>> for Mistletoe {
-    fn reduce(&mut self) -> Vec<PredictorSignal> {
-        std::mem::replace(&mut self.trade_signals, Vec::new())
-    }
+/// `/watch TICKER` starts one more price feed, configured like the first
+/// `price_feed` worker but for `TICKER`. Such a feed only fails on its own,
+/// since a mistyped ticker must not stop the app.
+fn watch_command(control: Control<'static, Mistletoe>, workers: &[WorkerSpec]) -> CommandHandler {
+    let template = workers
+        .iter()
+        .find(|spec| spec.kind == "price_feed")
+        .and_then(|spec| {
+            let options = spec.options().on_failure(FailurePolicy::Continue);
+            match PriceFeedConfig::deserialize(spec.config.clone()) {
+                Ok(config) => Some((config, options)),
+                // `add_workers` rejects the spec too
+                Err(e) => {
+                    warn!(%e, "Invalid price_feed config - /watch is disabled");
+                    None
+                }
+            }
+        });
+    Arc::new(move |ticker: &str| {
+        let Some((config, options)) = &template else {
+            return "There is no price feed to watch with".to_string();
+        };
+        if ticker.is_empty() {
+            return "Usage: /watch TICKER".to_string();
+        }
+        let ticker = ticker.to_uppercase();
+        let config = config.clone().with_ticker(&ticker);
+        let id = control.add_producer(move || PriceFeed::new(config.clone()), options.clone());
+        format!("Watching {ticker} as {id}")
+    })
+}
+
+/// Reports `IAmOk` every `period` along with the predictions.
+fn with_heartbeat(
+    builder: AppBuilder<'static, Mistletoe>,
+    period: Duration,
+) -> AppBuilder<'static, Mistletoe> {
+    builder.add_scheduled(
+        Schedule::every(period),
+        || |_: &Mistletoe| Some(PredictorSignal::IAmOk),
+        RestartPolicy::Always,
+    )
 }
 
 async fn runner() {
     let cli_opts = Opts::parse();
     tracing_subscriber::fmt::init();
 
-    let config: Config =
-        serde_yaml::from_reader(File::open(cli_opts.config_file).unwrap()).unwrap();
-    let app = App::build(Mistletoe::default())
-        .add_producer(PriceFeed::new(config.price_feed))
-        .add_worker(Predictor::new(config.predictor))
-        .add_consumer(TelegramReporter::new(config.tg))
+    if let Some(path) = cli_opts.replay {
+        if let Err(e) = replay(&path) {
+            error!(?path, %e, "Cannot replay journal");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let config_file = cli_opts.config_file.expect("config file is required");
+    let config: Config = serde_yaml::from_reader(File::open(config_file).unwrap()).unwrap();
+    let Config {
+        workers,
+        journal,
+        metrics,
+        checkpoint,
+        heartbeat,
+    } = config;
+    // Predictions make no sense without the price feed, so stop altogether
+    // once a worker keeps failing
+    let mut builder = App::build(Mistletoe::default()).on_failure(FailurePolicy::AbortAll);
+    if let Some(path) = journal {
+        builder = builder.journal(Journal::create(path).unwrap());
+    }
+    if let Some(addr) = metrics {
+        builder = builder.metrics(addr);
+    }
+    if let Some(CheckpointConfig { path, interval }) = checkpoint {
+        builder = builder.checkpoint(Checkpoint::new(path, STATE_VERSION).every(interval));
+    }
+    if let Some(period) = heartbeat {
+        builder = with_heartbeat(builder, period);
+    }
+    let watch = watch_command(builder.control(), &workers);
+    let app = builder
+        .add_workers(&registry(watch), &workers)
+        .expect("Invalid workers config")
         .build();
 
     info!("run app");
-    app.run().await
+    let outcome = app.run().await;
+    if !outcome.is_clean() {
+        warn!(
+            not_stopped = ?outcome.not_stopped,
+            failures = ?outcome.failures,
+            "App did not stop cleanly"
+        );
+    }
 }
 
 #[tokio::main]
 async fn main() {
     runner().await;
 }
+
+#[cfg(test)]
+mod tests {
+    use app::{
+        testing::{Harness, Recording, Script},
+        worker::{Backpressure, WorkerOptions},
+    };
+    use market_feed::candle::Candle;
+    use sources_common::{clock::ManualClock, timestamp::Timestamp};
+
+    use super::*;
+    use crate::predictor::PredictorConfig;
+
+    fn trades(current_price: f64) -> TradesAggregate {
+        TradesAggregate {
+            support_volume: 0.5,
+            min_price: 90.0,
+            max_price: 110.0,
+            current_price,
+            speed_factor: 1.0,
+        }
+    }
+
+    fn update(
+        orderbook: Option<OrderBook>,
+        trades_aggregate: Option<TradesAggregate>,
+    ) -> PriceFeedData {
+        PriceFeedData {
+            candles: None,
+            orderbook,
+            trades_aggregate,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn predictions_reach_the_reporter() {
+        let orderbook = OrderBook {
+            asks: vec![("101".parse().unwrap(), "2".parse().unwrap())],
+            bids: vec![("99".parse().unwrap(), "1".parse().unwrap())],
+        };
+        let script = Script::new()
+            .send(update(Some(orderbook.clone()), None))
+            .sleep(Duration::from_secs(60))
+            .send(update(None, Some(trades(100.0))))
+            .sleep(Duration::from_secs(1))
+            // Nothing the predictor looks at changes
+            .send(update(None, Some(trades(100.0))))
+            .sleep(Duration::from_secs(1))
+            .send(update(None, Some(trades(105.0))));
+        let config = PredictorConfig {
+            volume_weight_threshold: 0.5,
+            ticker: "ETHUSDT".to_string(),
+            clock: Arc::new(ManualClock::default()),
+        };
+        let inputs = Recording::<WorkerInput>::new();
+        let signals = Recording::<Vec<PredictorSignal>>::new();
+
+        let finished = Harness::new(Mistletoe::default())
+            .with(|app| {
+                let app = app.add_worker(
+                    move || Predictor::new(config.clone()),
+                    WorkerOptions::from(RestartPolicy::Never)
+                        .backpressure(Backpressure::CoalesceLatest),
+                );
+                with_heartbeat(app, Duration::from_secs(25))
+            })
+            .script(script)
+            .record(&inputs)
+            .record(&signals)
+            .run()
+            .await;
+
+        assert!(finished.outcome.is_clean(), "{:?}", finished.outcome);
+        assert_eq!(finished.state.orderbook, orderbook);
+        assert_eq!(finished.state.trades_aggregate, trades(105.0));
+        // The predictor keeps up with updates a second apart, and skips the
+        // one which changes nothing for it
+        assert_eq!(
+            inputs.values(),
+            vec![
+                (OrderBook::default(), TradesAggregate::default()),
+                (orderbook.clone(), TradesAggregate::default()),
+                (orderbook.clone(), trades(100.0)),
+                (orderbook, trades(105.0)),
+            ]
+        );
+        // The predictor does not signal on trades alone yet, so the reporter
+        // only gets the heartbeats of the first minute
+        assert_eq!(
+            signals.values(),
+            vec![vec![PredictorSignal::IAmOk], vec![PredictorSignal::IAmOk]]
+        );
+    }
+
+    /// Fails when `Mistletoe` changes shape; bump `STATE_VERSION` then and
+    /// update the expected checkpoint.
+    #[test]
+    fn checkpoint_shape_matches_state_version() {
+        let mut candles = Candles::default();
+        candles.join(Candle {
+            ts: Timestamp::from_millis(1_700_000_040_000),
+            time_unit: "1m".parse().unwrap(),
+            open: "1.5".parse().unwrap(),
+            high: "2".parse().unwrap(),
+            low: "1".parse().unwrap(),
+            close: "1.75".parse().unwrap(),
+            volume: "10".parse().unwrap(),
+            quote_volume: "17.5".parse().unwrap(),
+        });
+        let state = Mistletoe {
+            candles,
+            orderbook: OrderBook {
+                asks: vec![("101.5".parse().unwrap(), "2".parse().unwrap())],
+                bids: Vec::new(),
+            },
+            ..Mistletoe::default()
+        };
+
+        assert_eq!(STATE_VERSION, 3);
+        assert_eq!(
+            serde_json::to_value(state).unwrap(),
+            serde_json::json!({
+                "candles": [{
+                    "ts": 1_700_000_040_000_000_i64,
+                    "time_unit": "1m",
+                    "open": "1.5",
+                    "high": "2",
+                    "low": "1",
+                    "close": "1.75",
+                    "volume": "10",
+                    "quote_volume": "17.5",
+                }],
+                "orderbook": { "asks": [["101.5", "2"]], "bids": [] },
+                "trades_aggregate": {
+                    "support_volume": 0.0,
+                    "min_price": 0.0,
+                    "max_price": 0.0,
+                    "current_price": 0.0,
+                    "speed_factor": 0.0,
+                },
+                "trade_signals": [],
+            })
+        );
+    }
+}
@@ -1,34 +1,45 @@
 use std::fmt;
 use std::time::SystemTime;
 
-use app::{worker::Worker, FutureExt, SinkExt, mpsc, StreamExt};
+use app::{
+    mpsc,
+    shutdown::Shutdown,
+    worker::{Worker, WorkerError},
+    FutureExt, SinkExt, StreamExt,
+};
 use chrono::Utc;
-use market_feed::{candles::Candles, order_book::OrderBook, trade::TradesAggregate};
-use serde::Deserialize;
-use tokio::sync::watch;
-use tokio_stream::wrappers::WatchStream;
-use tracing::{error, info};
+use market_feed::{order_book::OrderBook, trade::TradesAggregate};
+use serde::{Deserialize, Serialize};
+use sources_common::{
+    clock::{SharedClock, SystemClock},
+    timestamp::Timestamp,
+};
+use tracing::info;
 
 pub struct Predictor {
     volume_weight_threshold: f64,
     ticker: String,
+    clock: SharedClock,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct PredictorConfig {
     pub volume_weight_threshold: f64,
     pub ticker: String,
+    /// Wall clock unless replaying
+    #[serde(skip, default = "SystemClock::shared")]
+    pub clock: SharedClock,
 }
 
 pub type WorkerInput = (OrderBook, TradesAggregate);
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Position {
     Short,
     Long,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PredictorSignal {
     TradeSignal {
         time: SystemTime,
@@ -67,13 +78,14 @@ impl Predictor {
         Self {
             ticker: config.ticker,
             volume_weight_threshold: config.volume_weight_threshold,
+            clock: config.clock,
         }
     }
 
     fn calculate_signal(
         &self,
         trades_aggregate: &TradesAggregate,
-        sent_staff: &mut SystemTime,
+        sent_staff: &mut Timestamp,
     ) -> Option<PredictorSignal> {
         /*
         let min = candles.min_low();
@@ -104,13 +116,13 @@ impl Predictor {
         /*
         if current < min && volume_weight > self.volume_weight_threshold {
             return Some(PredictorSignal::TradeSignal {
-                time: SystemTime::now(),
+                time: self.clock.now().into(),
                 position: Position::Short,
                 ticker,
             });
         } else if current > max && volume_weight > self.volume_weight_threshold {
             return Some(PredictorSignal::TradeSignal {
-                time: SystemTime::now(),
+                time: self.clock.now().into(),
                 position: Position::Long,
                 ticker,
             });
@@ -126,11 +138,11 @@ impl Predictor {
             );
             if let Some(time_unit) = candles.time_unit() {
                 let candle_dur = time_unit.calc_n(1);
-                let time_left = SystemTime::now().duration_since(*sent_staff).unwrap();
+                let time_left = self.clock.now() - *sent_staff;
                 info!("candle_dur {:?}, time_left {:?}", candle_dur, time_left);
                 if time_left > candle_dur {
                     info!("WTF");
-                    *sent_staff = SystemTime::now();
+                    *sent_staff = self.clock.now();
                     return Some(PredictorSignal::IAmOk);
                 }
             }
@@ -140,43 +152,36 @@ impl Predictor {
     }
 }
 
-impl<'f> Worker<'f, WorkerInput, Option<WorkerInput>, PredictorSignal, app::mpsc::SendError> for Predictor {
+impl<'f> Worker<'f, WorkerInput, WorkerInput, PredictorSignal, app::mpsc::SendError> for Predictor {
     type InputSink = mpsc::Sender<WorkerInput>;
-    type InputStream = WatchStream<Option<WorkerInput>>;
+    type InputStream = mpsc::Receiver<WorkerInput>;
 
     fn provide_input_stream(&self) -> (Self::InputSink, Self::InputStream) {
-        let (tx, rx) = watch::channel(None);
-        let (mp_tx, mut mp_rx) = mpsc::channel(1);
-        tokio::spawn(async move {
-            while let Some(item) = mp_rx.next().await {
-                tx.send(Some(item)).ok();
-            }
-        });
-
-        (mp_tx, WatchStream::new(rx))
+        mpsc::channel(1)
     }
 
     fn work(
         self: Box<Self>,
         mut state_rx: Self::InputStream,
         mut state_tx: app::mpsc::Sender<PredictorSignal>,
-    ) -> app::BoxFuture<'f, ()> {
+        mut shutdown: Shutdown,
+    ) -> app::BoxFuture<'f, Result<(), WorkerError>> {
         async move {
-            let mut sent_staff = SystemTime::now();
+            let mut sent_staff = self.clock.now();
             loop {
-                let mut signal = None;
-                if let Some(borrowed) = state_rx.next().await {
-                    // let borrowed = state_rx.borrow();
-                    if let Some((_, trades_aggregate)) = borrowed.as_ref() {
-                        signal = self.calculate_signal(trades_aggregate, &mut sent_staff);
-                    }
-                }
-                if let Some(signal) = signal.take() {
-                    if let Err(error) = state_tx.send(signal).await {
-                        error!(?error, "Error send trade signal");
-                    }
+                let next = tokio::select! {
+                    next = state_rx.next() => next,
+                    _ = shutdown.wait() => break,
+                };
+                let Some((_, trades_aggregate)) = next else {
+                    break;
+                };
+                let signal = self.calculate_signal(&trades_aggregate, &mut sent_staff);
+                if let Some(signal) = signal {
+                    state_tx.send(signal).await?;
                 }
             }
+            Ok(())
         }
         .boxed()
     }
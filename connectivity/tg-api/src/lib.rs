@@ -1,8 +1,7 @@
 use serde::{de::DeserializeOwned, Serialize};
 use telegram_bot_raw::{
-    GetUpdates, MessageOrChannelPost, ResponseType, ResponseWrapper, SendMessage, ResponseParameters,
+    GetUpdates, MessageOrChannelPost, ResponseWrapper, SendMessage, ResponseParameters,
 };
-use tracing::error;
 use update::Update;
 use url::Url;
 
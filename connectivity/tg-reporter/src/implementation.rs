@@ -1,9 +1,10 @@
 use core::fmt;
 use std::{fmt::Debug, path::PathBuf};
 
-use app::{BoxFuture, FutureExt, Sink, SinkExt, Stream, StreamExt};
+use app::{shutdown::Shutdown, BoxFuture, FutureExt, Sink, SinkExt, Stream, StreamExt};
 use telegram_bot_raw::{
-    ChatId, ChatMemberStatus, ChatRef, GetUpdates, ParseMode::MarkdownV2, SendMessage, UpdateKind,
+    ChatId, ChatMemberStatus, ChatRef, GetUpdates, MessageKind, ParseMode::MarkdownV2,
+    SendMessage,
 };
 use tg_api::{Api, update::Update};
 use tokio::{
@@ -25,6 +26,7 @@ impl TelegramReporter {
         &'f self,
         mut rx: St,
         mut state_rx: impl Stream<Item = Vec<T>> + Send + Sync + Unpin + 'f,
+        mut shutdown: Shutdown,
     ) -> BoxFuture<'f, ()>
     where
         St: Stream<Item = ChatMessage> + Unpin + Send + 'f,
@@ -34,8 +36,8 @@ impl TelegramReporter {
             let storage_path = self.storage_path.clone().into();
             let mut chats = match Self::load_chats(&storage_path).await {
                 Ok(chats) => chats,
-                Err(err) => {
-                    warn!(?storage_path, "Cannot load file - will create new one");
+                Err(error) => {
+                    warn!(?storage_path, ?error, "Cannot load file - will create new one");
                     Vec::new()
                 }
             };
@@ -69,6 +71,10 @@ impl TelegramReporter {
                         }
 
                     }
+                    _ = shutdown.wait() => {
+                        info!("Shutdown - stop sending");
+                        break;
+                    }
                 }
             }
         }
@@ -102,7 +108,23 @@ impl TelegramReporter {
         }
     }
 
-    async fn process_updates<S>(latest_update: &mut Option<i64>, updates: Vec<Update>, tx: &mut S) 
+    /// `None` unless `text` is a command this reporter has a handler for.
+    fn run_command(&self, text: &str) -> Option<String> {
+        let text = text.strip_prefix('/')?;
+        let (command, args) = text.split_once(' ').unwrap_or((text, ""));
+        // In groups commands come as `/command@bot_name`
+        let command = command.split('@').next().unwrap_or(command);
+        let handler = self.commands.get(command)?;
+        info!(command, args, "Run command");
+        Some(handler(args.trim()))
+    }
+
+    async fn process_updates<S>(
+        &self,
+        latest_update: &mut Option<i64>,
+        updates: Vec<Update>,
+        tx: &mut S,
+    )
 
     where
         S: Sink<ChatMessage> + fmt::Debug + Unpin + Send + Sync,
@@ -118,6 +140,16 @@ impl TelegramReporter {
                 tx.send(ChatMessage::Add(message.chat.id().into()))
                     .await
                     .unwrap();
+
+                if let MessageKind::Text { data, .. } = &message.kind {
+                    if let Some(reply) = self.run_command(data) {
+                        let chat = ChatRef::from_chat_id(message.chat.id());
+                        let reply = SendMessage::new(chat, reply);
+                        if let Err(error) = self.api.send_message(reply).await {
+                            error!(%error, "Cannot reply to command");
+                        }
+                    }
+                }
             }
 
             if let Some(chat_member) = update.my_chat_member {
@@ -141,7 +173,7 @@ impl TelegramReporter {
         }
     }
 
-    pub fn bot_loop<'f, S>(&'f self, mut tx: S) -> BoxFuture<'f, ()>
+    pub fn bot_loop<'f, S>(&'f self, mut tx: S, mut shutdown: Shutdown) -> BoxFuture<'f, ()>
     where
         S: Sink<ChatMessage> + fmt::Debug + Unpin + Send + Sync + 'f,
         <S as app::Sink<ChatMessage>>::Error: fmt::Debug,
@@ -158,8 +190,12 @@ impl TelegramReporter {
                         updates
                     })
                     .unwrap_or_else(GetUpdates::new);
-                match api.get_updates(request).await {
-                    Ok(updates) => Self::process_updates(&mut latest_update, updates, &mut tx).await,
+                let updates = select! {
+                    updates = api.get_updates(request) => updates,
+                    _ = shutdown.wait() => break,
+                };
+                match updates {
+                    Ok(updates) => self.process_updates(&mut latest_update, updates, &mut tx).await,
                     Err(some_error) => {
                         error!("{some_error}");
                     }
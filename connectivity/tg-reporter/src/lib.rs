@@ -1,13 +1,19 @@
 use core::fmt;
+use std::{collections::HashMap, sync::Arc};
 
-use app::{mpsc, worker::ConsumerWorker, FutureExt};
+use app::{
+    mpsc,
+    registry::Registry,
+    shutdown::Shutdown,
+    worker::{ConsumerWorker, WorkerError},
+    FutureExt, Reduced,
+};
 use serde::Deserialize;
 use tg_api::Api;
-use tokio::sync::watch;
 
 pub mod implementation;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct TelegramReporterConfig {
     #[serde(rename = "token", default = "bot_token_from_env")]
     pub bot_token: String,
@@ -18,9 +24,13 @@ fn bot_token_from_env() -> String {
     std::env::var("BOT_KEY").expect("Provide bot key via config or via env var 'BOT_KEY'")
 }
 
+/// Gets the arguments of a bot command and returns the reply.
+pub type CommandHandler = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
 pub struct TelegramReporter {
     api: Api,
     storage_path: String,
+    commands: HashMap<String, CommandHandler>,
 }
 
 impl TelegramReporter {
@@ -28,29 +38,52 @@ impl TelegramReporter {
         Self {
             api: Api::new(config.bot_token),
             storage_path: config.storage_path,
+            commands: HashMap::new(),
         }
     }
+
+    /// Answers `/<name> args` in any chat with `handler(args)`.
+    pub fn command(mut self, name: &str, handler: CommandHandler) -> Self {
+        self.commands.insert(name.to_string(), handler);
+        self
+    }
+}
+
+/// Registers `TelegramReporter` as `telegram`, reporting the `Vec<T>` the
+/// app state is reduced to.
+pub fn register<'f, S, T>(registry: Registry<'f, S>) -> Registry<'f, S>
+where
+    'f: 'static,
+    S: Reduced<Vec<T>> + Clone + Send + Sync + 'static,
+    T: fmt::Display + fmt::Debug + PartialEq + Clone + Send + Sync + 'f,
+{
+    registry.consumer::<_, _, Vec<T>, _>("telegram", TelegramReporter::new)
 }
 
 impl<'f, T> ConsumerWorker<'f, Vec<T>, app::mpsc::SendError> for TelegramReporter
 where
     T: fmt::Display + Sync + Send + 'f,
 {
-    type Sink = mpsc::UnboundedSender<Vec<T>>;
-    type Stream = mpsc::UnboundedReceiver<Vec<T>>;
+    type Sink = mpsc::Sender<Vec<T>>;
+    type Stream = mpsc::Receiver<Vec<T>>;
 
     fn provide_input_stream(&self) -> (Self::Sink, Self::Stream) {
-        mpsc::unbounded()
+        mpsc::channel(1)
     }
 
-    fn work(self: Box<Self>, state_rx: Self::Stream) -> app::BoxFuture<'f, ()> {
+    fn work(
+        self: Box<Self>,
+        state_rx: Self::Stream,
+        shutdown: Shutdown,
+    ) -> app::BoxFuture<'f, Result<(), WorkerError>> {
         async move {
             let mut futures = Vec::new();
             let (tx, rx) = mpsc::channel(10);
-            futures.push(self.bot_loop(tx).boxed());
-            futures.push(self.send_loop(rx, state_rx).boxed());
+            futures.push(self.bot_loop(tx, shutdown.clone()).boxed());
+            futures.push(self.send_loop(rx, state_rx, shutdown).boxed());
 
             futures::future::join_all(futures).await;
+            Ok(())
         }
         .boxed()
     }
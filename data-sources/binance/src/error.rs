@@ -1,3 +1,6 @@
+use std::time::Duration;
+
+use sources_common::time_unit::UnsupportedInterval;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -6,14 +9,159 @@ pub enum Error {
     SerdeError(serde_json::Error, String),
 
     #[error("Cannot get message: {0}")]
-    TungsteniteError(tokio_tungstenite::tungstenite::Error),
+    TungsteniteError(Box<tokio_tungstenite::tungstenite::Error>),
 
     #[error("Cannot convert message to market feed message")]
     NotAMarketMessage,
+
+    /// -1121
+    #[error("Invalid symbol: {msg}")]
+    InvalidSymbol { msg: String },
+
+    /// -1003, or 429 and 418 (IP banned) without a code
+    #[error("Rate limited: {msg}")]
+    RateLimited {
+        msg: String,
+        retry_after: Option<Duration>,
+    },
+
+    #[error("Binance error {code}: {msg}")]
+    Api { code: i64, msg: String },
+
+    #[error("{url} answered {status}: {body}")]
+    Status {
+        url: String,
+        status: u16,
+        body: String,
+    },
+
+    #[error("{url} timed out")]
+    Timeout { url: String },
+
+    #[error("Cannot decode response of {url} <{body}>: {source}")]
+    Decode {
+        url: String,
+        source: serde_json::Error,
+        body: String,
+    },
+
+    #[error("API key is not a valid header value")]
+    InvalidApiKey,
+
+    #[error(transparent)]
+    UnsupportedInterval(#[from] UnsupportedInterval),
+
+    #[error(transparent)]
+    Http(exchange_http::Error),
+}
+
+impl Error {
+    /// Names the codes we act upon, see
+    /// https://binance-docs.github.io/apidocs/spot/en/#error-codes
+    pub fn api(code: i64, msg: String) -> Self {
+        match code {
+            -1121 => Error::InvalidSymbol { msg },
+            -1003 => Error::RateLimited {
+                msg,
+                retry_after: None,
+            },
+            _ => Error::Api { code, msg },
+        }
+    }
 }
 
 impl From<tokio_tungstenite::tungstenite::Error> for Error {
     fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
-        Error::TungsteniteError(e)
+        Error::TungsteniteError(Box::new(e))
+    }
+}
+
+/// Binance explains failures in the body as `{"code": -1121, "msg": "..."}`,
+/// which wins over the bare status.
+impl From<exchange_http::Error> for Error {
+    fn from(e: exchange_http::Error) -> Self {
+        match e {
+            exchange_http::Error::Status {
+                url,
+                status,
+                body,
+                retry_after,
+            } => match serde_json::from_str::<ApiError>(&body) {
+                Ok(ApiError { code, msg }) => match Error::api(code, msg) {
+                    Error::RateLimited { msg, .. } => Error::RateLimited { msg, retry_after },
+                    error => error,
+                },
+                Err(_) if status.as_u16() == 429 || status.as_u16() == 418 => {
+                    Error::RateLimited {
+                        msg: body,
+                        retry_after,
+                    }
+                }
+                Err(_) => Error::Status {
+                    url,
+                    status: status.as_u16(),
+                    body,
+                },
+            },
+            exchange_http::Error::Timeout { url } => Error::Timeout { url },
+            exchange_http::Error::Decode { url, source, body } => {
+                Error::Decode { url, source, body }
+            }
+            e => Error::Http(e),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ApiError {
+    code: i64,
+    msg: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_known_api_codes() {
+        assert!(matches!(
+            Error::api(-1121, "Invalid symbol.".to_string()),
+            Error::InvalidSymbol { .. }
+        ));
+        assert!(matches!(
+            Error::api(-1003, "Too many requests.".to_string()),
+            Error::RateLimited { .. }
+        ));
+        assert!(matches!(
+            Error::api(-1100, "Illegal characters.".to_string()),
+            Error::Api { code: -1100, .. }
+        ));
+    }
+
+    fn status(status: u16, body: &str) -> exchange_http::Error {
+        exchange_http::Error::Status {
+            url: "https://api.binance.com/api/v3/depth".to_string(),
+            status: exchange_http::StatusCode::from_u16(status).unwrap(),
+            body: body.to_string(),
+            retry_after: Some(Duration::from_secs(3)),
+        }
+    }
+
+    #[test]
+    fn codes_in_the_body_win_over_status() {
+        let error = Error::from(status(400, r#"{"code":-1121,"msg":"Invalid symbol."}"#));
+        assert!(matches!(error, Error::InvalidSymbol { msg } if msg == "Invalid symbol."));
+
+        let error = Error::from(status(429, r#"{"code":-1003,"msg":"Too many requests."}"#));
+        assert!(matches!(
+            error,
+            Error::RateLimited { retry_after: Some(after), .. } if after == Duration::from_secs(3)
+        ));
+
+        let error = Error::from(status(418, "banned"));
+        assert!(matches!(error, Error::RateLimited { msg, .. } if msg == "banned"));
+
+        let error = Error::from(status(502, "<html>Bad Gateway</html>"));
+        assert!(matches!(error, Error::Status { status: 502, .. }));
     }
 }
@@ -1,9 +1,13 @@
-use std::time::Duration;
-
-use toolset::deser_duration_from_integer;
+use sources_common::{
+    decimal::Qty,
+    instrument::{Exchange, Instrument, MarketType},
+    timestamp::{self, Timestamp},
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::spot::exchange_info::{instrument_status, lot_size, tick_size, Filter};
+
 #[derive(Debug, Default, Serialize)]
 pub struct ExchangeInfoRequest {
     pub symbol: Option<String>,
@@ -15,8 +19,8 @@ pub struct ExchangeInfoRequest {
 #[serde(rename_all = "camelCase")]
 pub struct ExchangeInfo {
     pub timezone: String,
-    #[serde(deserialize_with = "deser_duration_from_integer")]
-    pub server_time: Duration,
+    #[serde(with = "timestamp::millis")]
+    pub server_time: Timestamp,
     pub symbols: Vec<Value>,
 }
 
@@ -47,4 +51,27 @@ pub struct Symbol {
     pub quote_asset: String,
     pub order_types: Vec<OrderType>,
     pub contract_type: String,
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+}
+
+impl From<Symbol> for Instrument {
+    fn from(value: Symbol) -> Self {
+        // USDⓈ-M futures: perpetuals and quarterlies, all margined in the quote asset
+        let market = match value.contract_type.as_str() {
+            "PERPETUAL" => MarketType::LinearPerpetual,
+            _ => MarketType::DatedFuture,
+        };
+        Self {
+            exchange: Exchange::Binance,
+            market,
+            tick_size: tick_size(&value.filters),
+            lot_size: lot_size(&value.filters),
+            contract_multiplier: Qty::new(1, 0),
+            status: instrument_status(&value.status),
+            ticker: value.symbol,
+            base_asset: value.base_asset,
+            quote_asset: value.quote_asset,
+        }
+    }
 }
@@ -1,13 +1,13 @@
-use std::time::Duration;
-
-use toolset::{deser_duration_from_integer, deser_float_from_string};
+use sources_common::{
+    decimal::Price,
+    timestamp::{self, Timestamp},
+};
 use serde::Deserialize;
 
 #[derive(Deserialize)]
 pub struct SymbolPrice {
-    #[serde(deserialize_with = "deser_float_from_string")]
-    pub price: f64,
-    #[serde(deserialize_with = "deser_duration_from_integer")]
-    pub time: Duration,
+    pub price: Price,
+    #[serde(with = "timestamp::millis")]
+    pub time: Timestamp,
     pub symbol: String,
 }
@@ -17,8 +17,12 @@ pub struct SubscribeMessage {
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 pub enum StreamData {
+    /// Ack of a command, `result` is `null` on success
     SubscribeResponse {
-        response: Option<serde_json::Value>,
+        #[serde(default)]
+        result: Option<serde_json::Value>,
+        #[serde(default)]
+        error: Option<serde_json::Value>,
         id: u32,
     },
     Package(StreamPackage),
@@ -1,23 +1,31 @@
-use std::time::Duration;
-
 use serde::{Deserialize, Serialize};
-use sources_common::time_unit::{ser_time_unit, TimeUnit};
-use toolset::{deser_duration_from_integer, deser_float_from_string};
+use sources_common::{
+    decimal::{Price, Qty},
+    time_unit::{TimeUnit, UnsupportedInterval},
+    timestamp::{self, Timestamp},
+};
 
 use crate::ToChannel;
 
 pub struct CandleStream {
-    pub ticker: String,
-    pub time_unit: TimeUnit,
+    ticker: String,
+    interval: String,
+}
+
+impl CandleStream {
+    /// Binance rejects the subscription if it has no such interval, so this
+    /// fails early instead.
+    pub fn new(ticker: &str, time_unit: &TimeUnit) -> Result<Self, UnsupportedInterval> {
+        Ok(Self {
+            ticker: ticker.to_lowercase(),
+            interval: time_unit.to_binance()?,
+        })
+    }
 }
 
 impl ToChannel for CandleStream {
     fn to_channel(&self) -> String {
-        format!(
-            "{}@kline_{}",
-            self.ticker.to_lowercase(),
-            self.time_unit.fmt()
-        )
+        format!("{}@kline_{}", self.ticker, self.interval)
     }
 }
 
@@ -25,37 +33,41 @@ impl ToChannel for CandleStream {
 #[serde(rename_all = "camelCase")]
 pub struct CandlesQuery {
     pub symbol: String,
-    #[serde(serialize_with = "ser_time_unit")]
-    pub interval: TimeUnit,
+    interval: String,
     pub start_time: Option<u64>,
     pub end_time: Option<u64>,
     pub limit: Option<u32>,
     pub offset: Option<u32>,
 }
 
+impl CandlesQuery {
+    pub fn new(symbol: String, interval: &TimeUnit) -> Result<Self, UnsupportedInterval> {
+        Ok(Self {
+            symbol,
+            interval: interval.to_binance()?,
+            start_time: None,
+            end_time: None,
+            limit: None,
+            offset: None,
+        })
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Candle {
-    #[serde(deserialize_with = "deser_duration_from_integer")]
-    pub ts: Duration,
-    #[serde(deserialize_with = "deser_float_from_string")]
-    pub open: f64,
-    #[serde(deserialize_with = "deser_float_from_string")]
-    pub high: f64,
-    #[serde(deserialize_with = "deser_float_from_string")]
-    pub low: f64,
-    #[serde(deserialize_with = "deser_float_from_string")]
-    pub close: f64,
-    #[serde(deserialize_with = "deser_float_from_string")]
-    pub volume: f64,
-    #[serde(deserialize_with = "deser_duration_from_integer")]
-    pub close_ts: Duration,
-    #[serde(deserialize_with = "deser_float_from_string")]
-    pub quote_volume: f64,
+    #[serde(with = "timestamp::millis")]
+    pub ts: Timestamp,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: Qty,
+    #[serde(with = "timestamp::millis")]
+    pub close_ts: Timestamp,
+    pub quote_volume: Qty,
     pub number_of_trades: u32,
-    #[serde(deserialize_with = "deser_float_from_string")]
-    pub taker_buy_base_asset_volume: f64,
-    #[serde(deserialize_with = "deser_float_from_string")]
-    pub taker_buy_quote_asset_volume: f64,
+    pub taker_buy_base_asset_volume: Qty,
+    pub taker_buy_quote_asset_volume: Qty,
 
     #[serde(rename = "unused")]
     _unused: String,
@@ -63,10 +75,10 @@ pub struct Candle {
 
 #[derive(Deserialize, Debug)]
 pub struct WsCandleData {
-    #[serde(rename = "t", deserialize_with = "deser_duration_from_integer")]
-    pub ts: Duration,
-    #[serde(rename = "T", deserialize_with = "deser_duration_from_integer")]
-    pub close_ts: Duration,
+    #[serde(rename = "t", with = "timestamp::millis")]
+    pub ts: Timestamp,
+    #[serde(rename = "T", with = "timestamp::millis")]
+    pub close_ts: Timestamp,
     #[serde(rename = "s")]
     pub ticker: String,
     #[serde(rename = "i")]
@@ -77,28 +89,28 @@ pub struct WsCandleData {
     #[serde(rename = "L")]
     pub last_trade_id: u64,
 
-    #[serde(rename = "o", deserialize_with = "deser_float_from_string")]
-    pub open: f64,
-    #[serde(rename = "h", deserialize_with = "deser_float_from_string")]
-    pub high: f64,
-    #[serde(rename = "l", deserialize_with = "deser_float_from_string")]
-    pub low: f64,
-    #[serde(rename = "c", deserialize_with = "deser_float_from_string")]
-    pub close: f64,
-
-    #[serde(rename = "v", deserialize_with = "deser_float_from_string")]
-    pub volume: f64,
+    #[serde(rename = "o")]
+    pub open: Price,
+    #[serde(rename = "h")]
+    pub high: Price,
+    #[serde(rename = "l")]
+    pub low: Price,
+    #[serde(rename = "c")]
+    pub close: Price,
+
+    #[serde(rename = "v")]
+    pub volume: Qty,
     #[serde(rename = "n")]
     pub number_of_trades: u32,
     #[serde(rename = "x")]
     pub is_closed: bool,
-    #[serde(rename = "q", deserialize_with = "deser_float_from_string")]
-    pub quote_volume: f64,
+    #[serde(rename = "q")]
+    pub quote_volume: Qty,
 
-    #[serde(rename = "V", deserialize_with = "deser_float_from_string")]
-    pub taker_buy_base_asset_volume: f64,
-    #[serde(rename = "Q", deserialize_with = "deser_float_from_string")]
-    pub taker_buy_quote_asset_volume: f64,
+    #[serde(rename = "V")]
+    pub taker_buy_base_asset_volume: Qty,
+    #[serde(rename = "Q")]
+    pub taker_buy_quote_asset_volume: Qty,
 }
 
 #[derive(Deserialize, Debug)]
@@ -112,6 +124,16 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn rejects_intervals_binance_does_not_have() {
+        let stream = CandleStream::new("BTCUSDT", &"15m".parse().unwrap()).unwrap();
+        assert_eq!(stream.to_channel(), "btcusdt@kline_15m");
+
+        let seven_minutes: TimeUnit = "7m".parse().unwrap();
+        assert!(CandleStream::new("BTCUSDT", &seven_minutes).is_err());
+        assert!(CandlesQuery::new("BTCUSDT".to_string(), &seven_minutes).is_err());
+    }
+
     #[test]
     fn parsing_candle() {
         let input = r#"
@@ -134,7 +156,8 @@ mod tests {
         "#;
         let candle = serde_json::from_str::<Vec<Candle>>(input).unwrap();
 
-        assert_eq!(candle[0].open, 0.01634790);
-        assert_eq!(candle[0].ts.as_secs(), 1499040000);
+        assert_eq!(candle[0].open, Price::new(1634790, 8));
+        assert_eq!(candle[0].ts.as_millis(), 1499040000000);
+        assert_eq!(candle[0].close_ts.to_string(), "2017-07-09T23:59:59.999Z");
     }
 }
@@ -1,6 +1,8 @@
-use std::time::Duration;
-
-use toolset::deser_duration_from_integer;
+use sources_common::{
+    decimal::{Price, Qty},
+    instrument::{Exchange, Instrument, InstrumentStatus, MarketType},
+    timestamp::{self, Timestamp},
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -15,8 +17,8 @@ pub struct ExchangeInfoRequest {
 #[serde(rename_all = "camelCase")]
 pub struct ExchangeInfo {
     pub timezone: String,
-    #[serde(deserialize_with = "deser_duration_from_integer")]
-    pub server_time: Duration,
+    #[serde(with = "timestamp::millis")]
+    pub server_time: Timestamp,
     pub symbols: Vec<Value>,
 }
 
@@ -51,18 +53,126 @@ pub struct Symbol {
     pub is_spot_trading_allowed: bool,
     pub is_margin_trading_allowed: bool,
     pub permissions: Vec<String>,
+    #[serde(default, with = "toolset::empty_as_none")]
     pub default_self_trade_prevention_mode: Option<String>,
     #[serde(default)]
     pub allowed_self_trade_prevention_modes: Vec<String>,
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+}
+
+/// Symbol filters shared by spot and futures; only the ones the instrument
+/// model needs are parsed.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "filterType", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Filter {
+    #[serde(rename_all = "camelCase")]
+    PriceFilter { tick_size: Price },
+    #[serde(rename_all = "camelCase")]
+    LotSize { step_size: Qty },
+    #[serde(other)]
+    Other,
+}
+
+pub(crate) fn tick_size(filters: &[Filter]) -> Option<Price> {
+    filters.iter().find_map(|filter| match filter {
+        Filter::PriceFilter { tick_size } => Some(*tick_size),
+        _ => None,
+    })
+}
+
+pub(crate) fn lot_size(filters: &[Filter]) -> Option<Qty> {
+    filters.iter().find_map(|filter| match filter {
+        Filter::LotSize { step_size } => Some(*step_size),
+        _ => None,
+    })
 }
 
-impl From<Symbol> for sources_common::symbol::Symbol {
+/// Spot and futures statuses, both spelled in SCREAMING_SNAKE_CASE.
+pub(crate) fn instrument_status(status: &str) -> InstrumentStatus {
+    match status {
+        "TRADING" => InstrumentStatus::Trading,
+        "PRE_TRADING" | "PENDING_TRADING" => InstrumentStatus::PreTrading,
+        "PRE_DELIVERING" | "DELIVERING" | "PRE_SETTLE" | "SETTLING" => InstrumentStatus::Settling,
+        "DELIVERED" | "CLOSE" => InstrumentStatus::Closed,
+        _ => InstrumentStatus::Halted,
+    }
+}
+
+impl From<Symbol> for Instrument {
     fn from(value: Symbol) -> Self {
         Self {
-            source: "binance".into(),
+            exchange: Exchange::Binance,
+            market: MarketType::Spot,
+            tick_size: tick_size(&value.filters),
+            lot_size: lot_size(&value.filters),
+            contract_multiplier: Qty::new(1, 0),
+            status: instrument_status(&value.status),
             ticker: value.symbol,
             base_asset: value.base_asset,
             quote_asset: value.quote_asset,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbol_becomes_instrument() {
+        let input = r#"
+        {
+            "symbol": "ETHBTC",
+            "status": "TRADING",
+            "baseAsset": "ETH",
+            "baseAssetPrecision": 8,
+            "quoteAsset": "BTC",
+            "quotePrecision": 8,
+            "quoteAssetPrecision": 8,
+            "orderTypes": ["LIMIT", "MARKET"],
+            "icebergAllowed": true,
+            "ocoAllowed": true,
+            "quoteOrderQtyMarketAllowed": true,
+            "allowTrailingStop": false,
+            "cancelReplaceAllowed": false,
+            "isSpotTradingAllowed": true,
+            "isMarginTradingAllowed": true,
+            "permissions": ["SPOT", "MARGIN"],
+            "filters": [
+                {
+                    "filterType": "PRICE_FILTER",
+                    "minPrice": "0.00001000",
+                    "maxPrice": "922327.00000000",
+                    "tickSize": "0.00001000"
+                },
+                {
+                    "filterType": "PERCENT_PRICE",
+                    "multiplierUp": "5",
+                    "multiplierDown": "0.2",
+                    "avgPriceMins": 5
+                },
+                {
+                    "filterType": "LOT_SIZE",
+                    "minQty": "0.00010000",
+                    "maxQty": "100000.00000000",
+                    "stepSize": "0.00010000"
+                }
+            ]
+        }
+        "#;
+        let symbol = serde_json::from_str::<Symbol>(input).unwrap();
+        let instrument = Instrument::from(symbol);
+
+        assert_eq!(instrument.exchange, Exchange::Binance);
+        assert_eq!(instrument.market, MarketType::Spot);
+        assert_eq!(instrument.ticker, "ETHBTC");
+        assert_eq!(instrument.tick_size, Some(Price::new(1, 5)));
+        assert_eq!(instrument.lot_size, Some(Qty::new(1, 4)));
+        assert_eq!(
+            instrument.round_price("0.062346".parse().unwrap()),
+            "0.06235".parse().unwrap()
+        );
+        assert!(instrument.is_trading());
+    }
+}
@@ -1,6 +1,9 @@
 use crate::ToChannel;
 use std::time::Duration;
-use toolset::{deser_duration_from_integer, deser_float_from_string};
+use sources_common::{
+    decimal::{Price, Qty},
+    timestamp::{self, Timestamp},
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -43,14 +46,11 @@ impl ToChannel for HistoricalTradesChannel {
 #[serde(rename_all = "camelCase")]
 pub struct ApiHistoricalTrade {
     pub id: u64,
-    #[serde(deserialize_with = "deser_float_from_string")]
-    pub price: f64,
-    #[serde(deserialize_with = "deser_float_from_string")]
-    pub qty: f64,
-    #[serde(deserialize_with = "deser_float_from_string")]
-    pub quote_qty: f64,
-    #[serde(deserialize_with = "deser_duration_from_integer")]
-    pub time: Duration, // Trade executed timestamp, as same as `T` in the stream
+    pub price: Price,
+    pub qty: Qty,
+    pub quote_qty: Qty,
+    #[serde(with = "timestamp::millis")]
+    pub time: Timestamp, // Trade executed timestamp, as same as `T` in the stream
     pub is_buyer_maker: bool,
     pub is_best_match: bool,
 }
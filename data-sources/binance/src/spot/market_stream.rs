@@ -0,0 +1,407 @@
+//! Combined market stream which survives disconnects: it reconnects with
+//! backoff, subscribes again to every channel and tells the consumer about
+//! the gap, so snapshots can be fetched again.
+
+use std::{collections::HashMap, time::Duration};
+
+use exchange_http::RetryPolicy;
+use futures::{
+    channel::mpsc::{self, Sender},
+    SinkExt, Stream, StreamExt,
+};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, error, info, warn};
+use url::Url;
+
+use crate::{
+    error::Error,
+    protocol::{StreamData, StreamPackage, SubscribeMessage},
+    ToChannel,
+};
+
+use super::conversion;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Events buffered for a slow consumer. Once they are full the socket is no
+/// longer read; if binance drops us for that, we reconnect as usual.
+const STREAM_CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+pub enum StreamEvent {
+    /// A socket is open, sent on every (re)connect
+    Connected,
+    /// Packets are lost until `Resubscribed`
+    Disconnected {
+        reason: String,
+    },
+    /// Binance acked the subscription renewed after a reconnect
+    Resubscribed,
+    Package(StreamPackage),
+}
+
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Delays between attempts to reconnect, retried forever
+    pub backoff: RetryPolicy,
+    /// Binance drops connections after 24h, we reconnect a bit earlier
+    pub max_connection_age: Duration,
+    /// Binance pings every few minutes, silence longer than that means the
+    /// connection is dead
+    pub idle_timeout: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            backoff: RetryPolicy {
+                max_retries: u32::MAX,
+                initial: Duration::from_secs(1),
+                max: Duration::from_secs(60),
+            },
+            max_connection_age: Duration::from_secs(23 * 60 * 60),
+            idle_timeout: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// Only the first connect fails, later disconnects come as
+/// `StreamEvent::Disconnected` and are healed in the background.
+pub async fn get_market_stream(
+    ws_host: Url,
+    subscribe_streams: Vec<Box<dyn ToChannel + Send>>,
+) -> Result<impl Stream<Item = StreamEvent>, Error> {
+    get_market_stream_with(ws_host, subscribe_streams, ReconnectPolicy::default()).await
+}
+
+pub async fn get_market_stream_with(
+    mut ws_host: Url,
+    subscribe_streams: Vec<Box<dyn ToChannel + Send>>,
+    policy: ReconnectPolicy,
+) -> Result<impl Stream<Item = StreamEvent>, Error> {
+    ws_host.set_path("/stream");
+
+    let (socket, _response) = connect_async(ws_host.clone()).await?;
+    let (tx, rx) = mpsc::channel(STREAM_CAPACITY);
+
+    let connection = Connection {
+        url: ws_host,
+        channels: subscribe_streams.iter().map(|c| c.to_channel()).collect(),
+        policy,
+        tx,
+        next_id: 1,
+        pending: HashMap::new(),
+    };
+    tokio::spawn(connection.run(socket));
+
+    Ok(rx)
+}
+
+enum Disconnect {
+    Lost(String),
+    /// Nobody listens anymore, the task is done
+    ReceiverGone,
+}
+
+struct Connection {
+    url: Url,
+    channels: Vec<String>,
+    policy: ReconnectPolicy,
+    tx: Sender<StreamEvent>,
+    next_id: u32,
+    /// Sent `SUBSCRIBE` ids, mapped to whether it renews a lost subscription
+    pending: HashMap<u32, bool>,
+}
+
+impl Connection {
+    async fn run(mut self, socket: Socket) {
+        let mut socket = Some(socket);
+        let mut resubscribe = false;
+        let mut retry = 0;
+        loop {
+            if self.tx.is_closed() {
+                return;
+            }
+            let current = match socket.take() {
+                Some(socket) => socket,
+                None => {
+                    let delay = self
+                        .policy
+                        .backoff
+                        .delay(retry, None)
+                        .unwrap_or(self.policy.backoff.max);
+                    retry = retry.saturating_add(1);
+                    info!(?delay, retry, "Reconnect to binance web socket");
+                    tokio::time::sleep(delay).await;
+                    match connect_async(self.url.clone()).await {
+                        Ok((socket, _response)) => socket,
+                        Err(e) => {
+                            warn!(%e, "Cannot reconnect to binance web socket");
+                            continue;
+                        }
+                    }
+                }
+            };
+            if self.emit(StreamEvent::Connected).await.is_err() {
+                return;
+            }
+
+            match self.serve(current, resubscribe, &mut retry).await {
+                Disconnect::ReceiverGone => return,
+                Disconnect::Lost(reason) => {
+                    warn!(%reason, "Binance web socket disconnected");
+                    self.pending.clear();
+                    if self
+                        .emit(StreamEvent::Disconnected { reason })
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                    resubscribe = true;
+                }
+            }
+        }
+    }
+
+    /// Subscribes and forwards packets until the connection is lost. `retry`
+    /// is reset once binance confirms the subscription, the connection is
+    /// healthy then.
+    async fn serve(&mut self, socket: Socket, resubscribe: bool, retry: &mut u32) -> Disconnect {
+        let (mut ws_tx, mut ws_rx) = socket.split();
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let command = SubscribeMessage {
+            method: "SUBSCRIBE".to_string(),
+            params: self.channels.clone(),
+            id,
+        };
+        debug!(?command, "Send command to binance web socket");
+        let command_str = serde_json::to_string_pretty(&command).unwrap();
+        if let Err(e) = ws_tx.send(Message::Text(command_str)).await {
+            return Disconnect::Lost(format!("cannot subscribe: {e}"));
+        }
+        self.pending.insert(id, resubscribe);
+
+        let max_age = tokio::time::sleep(self.policy.max_connection_age);
+        tokio::pin!(max_age);
+        loop {
+            let msg = tokio::select! {
+                _ = &mut max_age => {
+                    let _ = ws_tx.close().await;
+                    return Disconnect::Lost("connection is about to expire".to_string());
+                }
+                msg = tokio::time::timeout(self.policy.idle_timeout, ws_rx.next()) => msg,
+            };
+            let msg = match msg {
+                Ok(Some(Ok(msg))) => msg,
+                Ok(Some(Err(e))) => return Disconnect::Lost(e.to_string()),
+                Ok(None) => return Disconnect::Lost("connection closed".to_string()),
+                Err(_) => {
+                    return Disconnect::Lost(format!(
+                        "no messages for {:?}",
+                        self.policy.idle_timeout
+                    ))
+                }
+            };
+            match msg {
+                Message::Ping(v) => {
+                    if let Err(e) = ws_tx.send(Message::Pong(v)).await {
+                        return Disconnect::Lost(format!("cannot answer ping: {e}"));
+                    }
+                }
+                Message::Text(txt) => match conversion(txt) {
+                    Ok(StreamData::Package(p)) => {
+                        if self.emit(StreamEvent::Package(p)).await.is_err() {
+                            let _ = ws_tx.close().await;
+                            return Disconnect::ReceiverGone;
+                        }
+                    }
+                    Ok(StreamData::SubscribeResponse { result, error, id }) => {
+                        info!(?result, ?error, id, "SubscribeResponse");
+                        match self.ack(id, error).await {
+                            Ok(true) => *retry = 0,
+                            Ok(false) => {}
+                            Err(disconnect) => {
+                                let _ = ws_tx.close().await;
+                                return disconnect;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!(?e, "Error occured");
+                    }
+                },
+                Message::Close(frame) => {
+                    return Disconnect::Lost(format!("closed by binance: {frame:?}"));
+                }
+                Message::Binary(data) => {
+                    warn!(len = data.len(), "Unexpected binary message");
+                }
+                Message::Pong(_) | Message::Frame(_) => {}
+            }
+        }
+    }
+
+    /// `true` once binance confirmed a subscription. A rejected one is
+    /// treated like a lost connection, so it is retried on a new one.
+    async fn ack(&mut self, id: u32, error: Option<serde_json::Value>) -> Result<bool, Disconnect> {
+        match (self.pending.remove(&id), error) {
+            (None, _) => {
+                warn!(id, "Ack of unknown command");
+                Ok(false)
+            }
+            (Some(_), Some(error)) => Err(Disconnect::Lost(format!(
+                "binance rejected subscription {id}: {error}"
+            ))),
+            (Some(resubscribe), None) => {
+                if resubscribe && self.emit(StreamEvent::Resubscribed).await.is_err() {
+                    return Err(Disconnect::ReceiverGone);
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    async fn emit(&mut self, event: StreamEvent) -> Result<(), ()> {
+        self.tx.send(event).await.map_err(|_| {
+            debug!("Stream receiver is gone");
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+
+    use super::*;
+
+    struct Channel(&'static str);
+
+    impl ToChannel for Channel {
+        fn to_channel(&self) -> String {
+            self.0.to_string()
+        }
+    }
+
+    /// Acks the subscription of each connection and sends one trade, then
+    /// drops the connection. The first `rejected` subscriptions are rejected
+    /// instead. Hands back the subscribe commands it got.
+    async fn serve(
+        connections: usize,
+        rejected: usize,
+    ) -> (Url, tokio::task::JoinHandle<Vec<serde_json::Value>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("ws://{}", listener.local_addr().unwrap())).unwrap();
+        let server = tokio::spawn(async move {
+            let mut commands = Vec::new();
+            for connection in 0..connections {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut socket = accept_async(stream).await.unwrap();
+                let Some(Ok(Message::Text(command))) = socket.next().await else {
+                    panic!("no subscribe command");
+                };
+                let command: serde_json::Value = serde_json::from_str(&command).unwrap();
+                let id = command["id"].clone();
+                commands.push(command);
+                if connection < rejected {
+                    let nack =
+                        format!(r#"{{"error":{{"code":2,"msg":"Invalid request"}},"id":{id}}}"#);
+                    socket.send(Message::Text(nack)).await.unwrap();
+                    // Until the client hangs up
+                    while let Some(Ok(_)) = socket.next().await {}
+                    continue;
+                }
+                let ack = format!(r#"{{"result":null,"id":{id}}}"#);
+                socket.send(Message::Text(ack)).await.unwrap();
+                socket.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+                let trade = r#"{"stream":"btcusdt@trade","data":{"e":"trade","s":"BTCUSDT"}}"#;
+                socket.send(Message::Text(trade.to_string())).await.unwrap();
+                socket.close(None).await.unwrap();
+            }
+            commands
+        });
+        (url, server)
+    }
+
+    fn fast_reconnects() -> ReconnectPolicy {
+        ReconnectPolicy {
+            backoff: RetryPolicy {
+                max_retries: u32::MAX,
+                initial: Duration::from_millis(1),
+                max: Duration::from_millis(5),
+            },
+            ..ReconnectPolicy::default()
+        }
+    }
+
+    fn kind(event: &StreamEvent) -> &'static str {
+        match event {
+            StreamEvent::Connected => "connected",
+            StreamEvent::Disconnected { .. } => "disconnected",
+            StreamEvent::Resubscribed => "resubscribed",
+            StreamEvent::Package(_) => "package",
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnects_and_resubscribes() {
+        let (url, server) = serve(2, 0).await;
+        let channels: Vec<Box<dyn ToChannel + Send>> = vec![Box::new(Channel("btcusdt@trade"))];
+        let stream = get_market_stream_with(url, channels, fast_reconnects())
+            .await
+            .unwrap();
+
+        let events = stream.take(7).collect::<Vec<_>>().await;
+        let kinds = events.iter().map(kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                "connected",
+                "package",
+                "disconnected",
+                "connected",
+                "resubscribed",
+                "package",
+                "disconnected"
+            ]
+        );
+
+        let commands = server.await.unwrap();
+        assert_eq!(commands[0]["params"], serde_json::json!(["btcusdt@trade"]));
+        assert_eq!(commands[1]["params"], commands[0]["params"]);
+        assert_ne!(commands[1]["id"], commands[0]["id"]);
+    }
+
+    #[tokio::test]
+    async fn rejected_subscription_is_retried_on_a_new_connection() {
+        let (url, server) = serve(2, 1).await;
+        let channels: Vec<Box<dyn ToChannel + Send>> = vec![Box::new(Channel("btcusdt@trade"))];
+        let stream = get_market_stream_with(url, channels, fast_reconnects())
+            .await
+            .unwrap();
+
+        let events = stream.take(5).collect::<Vec<_>>().await;
+        let kinds = events.iter().map(kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                "connected",
+                "disconnected",
+                "connected",
+                "resubscribed",
+                "package"
+            ]
+        );
+        let StreamEvent::Disconnected { reason } = &events[1] else {
+            unreachable!()
+        };
+        assert!(reason.contains("rejected"), "{reason}");
+
+        let commands = server.await.unwrap();
+        assert_eq!(commands[1]["params"], commands[0]["params"]);
+    }
+}
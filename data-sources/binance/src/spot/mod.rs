@@ -1,16 +1,16 @@
 use core::fmt;
-use futures::{channel::mpsc, SinkExt, Stream, StreamExt};
-use reqwest::header::{HeaderMap, HeaderValue};
+use exchange_http::{
+    header::{HeaderMap, HeaderValue},
+    HttpClient,
+};
 use serde::{de::DeserializeOwned, Serialize};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio_tungstenite::{connect_async, tungstenite::Message};
-use tracing::{debug, error, info};
-use url::Url;
+use sources_common::clock::Clock;
+use std::time::Duration;
+use tracing::info;
 
 use crate::{
     error::Error,
-    protocol::{Response, StreamData, StreamPackage, SubscribeMessage},
-    ToChannel,
+    protocol::{Response, StreamData},
 };
 
 use self::{
@@ -25,9 +25,12 @@ use self::{
 pub mod candle;
 pub mod exchange_info;
 pub mod historical_trades;
+pub mod market_stream;
 pub mod orderbook;
 pub mod trade;
 
+pub use market_stream::get_market_stream;
+
 fn conversion(text: String) -> Result<StreamData, Error> {
     let stream_data = serde_json::from_str::<StreamData>(&text)
         .map_err(|e| Error::SerdeError(e, text.to_string()))?;
@@ -35,151 +38,86 @@ fn conversion(text: String) -> Result<StreamData, Error> {
     Ok(stream_data)
 }
 
-pub async fn get_market_stream(
-    mut ws_host: Url,
-    subscribe_streams: Vec<Box<dyn ToChannel + Send>>,
-) -> impl Stream<Item = StreamPackage> {
-    ws_host.set_path("/stream");
-
-    let (stream, _response) = connect_async(ws_host).await.unwrap();
-    let (mut ws_tx, mut ws_rx) = stream.split();
-    let (mut tx, rx) = mpsc::unbounded();
-
-    let channels = subscribe_streams
-        .into_iter()
-        .map(|c| c.to_channel())
-        .collect();
-    let command = SubscribeMessage {
-        method: "SUBSCRIBE".to_string(),
-        params: channels,
-        id: 100,
-    };
-
-    debug!(?command, "Send command to binance web socket");
-    let command_str = serde_json::to_string_pretty(&command).unwrap();
-    let command = Message::Text(command_str);
-    ws_tx.send(command).await.unwrap();
-
-    tokio::spawn(async move {
-        while let Some(Ok(msg)) = ws_rx.next().await {
-            match msg {
-                Message::Ping(v) => ws_tx.send(Message::Pong(v)).await.unwrap(),
-                Message::Text(txt) => match conversion(txt) {
-                    Ok(StreamData::Package(p)) => tx.send(p).await.unwrap(),
-                    Ok(StreamData::SubscribeResponse { response, id }) => {
-                        info!(?response, ?id, "SubscribeResponse");
-                    }
-                    Err(e) => {
-                        error!(?e, "Error occured");
-                    }
-                },
-                Message::Close(_) => {
-                    tx.close().await.unwrap();
-                    ws_tx.close().await.unwrap();
-                    break;
-                }
-                _ => unreachable!("Something unexpected"),
-            }
-        }
-    });
-
-    rx
-}
-
-pub async fn fetch<Q, R>(api_host: Url, path: &str, query: Q, headers: Option<HeaderMap>) -> R
+pub async fn fetch<Q, R>(
+    client: &HttpClient,
+    path: &str,
+    query: Q,
+    headers: Option<HeaderMap>,
+) -> Result<R, Error>
 where
     Q: Serialize + fmt::Debug,
     R: DeserializeOwned,
 {
-    let mut url = api_host.join(path).unwrap();
-    let qs = serde_qs::to_string(&query).unwrap();
+    info!(?query, "Run query");
 
-    info!(?query, ?qs, "Run query");
-
-    url.set_query(Some(&qs));
-    let mut req = reqwest::Client::new().get(url);
+    let mut request = client.get(path).query(&query);
     if let Some(headers) = headers {
-        req = req.headers(headers);
+        request = request.headers(headers);
     }
 
-    let result = req.send().await.unwrap().text().await.unwrap();
+    let result = request.text().await?;
 
     match serde_json::from_str::<Response<R>>(&result) {
-        Ok(Response::Success(t)) => t,
-        Ok(Response::Error { code, msg }) => {
-            panic!("Bam! {path} {code} ({msg})");
-        }
-
-        Err(e) => {
-            panic!("Serde! {e} {}", result);
-        }
+        Ok(Response::Success(t)) => Ok(t),
+        Ok(Response::Error { code, msg }) => Err(Error::api(code, msg)),
+        Err(source) => Err(Error::Decode {
+            url: path.to_string(),
+            source,
+            body: result,
+        }),
     }
 }
-pub async fn fetch_type<Q, R>(api_host: Url, path: &str, query: Q, headers: Option<HeaderMap>) -> R
+pub async fn fetch_type<Q, R>(
+    client: &HttpClient,
+    path: &str,
+    query: Q,
+    headers: Option<HeaderMap>,
+) -> Result<R, Error>
 where
     Q: Serialize + fmt::Debug,
     R: DeserializeOwned,
 {
-    let mut url = api_host.join(path).unwrap();
-    let qs = serde_qs::to_string(&query).unwrap();
+    info!(?query, "Run query");
 
-    info!(?query, ?qs, "Run query");
-
-    url.set_query(Some(&qs));
-    let mut req = reqwest::Client::new().get(url);
+    let mut request = client.get(path).query(&query);
     if let Some(headers) = headers {
-        req = req.headers(headers);
+        request = request.headers(headers);
     }
 
-    let result = req.send().await.unwrap().text().await.unwrap();
-
-    match serde_json::from_str::<R>(&result) {
-        Ok(t) => t,
-        Err(e) => {
-            panic!("Serde! {e} {}", result);
-        }
-    }
+    Ok(request.json().await?)
 }
 
 pub async fn fetch_exchange_info(
-    api_host: Url,
+    client: &HttpClient,
     exchange_info: ExchangeInfoRequest,
-) -> ExchangeInfo {
-    let mut url = api_host.join("/api/v3/exchangeInfo").unwrap();
-    let qs = serde_qs::to_string(&exchange_info).unwrap();
-    url.set_query(Some(&qs));
-    let result = reqwest::Client::new()
-        .get(url)
-        .send()
-        .await
-        .unwrap()
-        .text()
-        .await
-        .unwrap();
-    match serde_json::from_str(&result) {
-        Ok(r) => r,
-        Err(e) => {
-            panic!("Serde! {e}");
-        }
-    }
+) -> Result<ExchangeInfo, Error> {
+    fetch_type(client, "/api/v3/exchangeInfo", exchange_info, None).await
 }
 
-pub async fn fetch_candles(api_host: Url, candles_query: CandlesQuery) -> Vec<Candle> {
-    fetch(api_host, "/api/v3/klines", candles_query, None).await
+pub async fn fetch_candles(
+    client: &HttpClient,
+    candles_query: CandlesQuery,
+) -> Result<Vec<Candle>, Error> {
+    fetch(client, "/api/v3/klines", candles_query, None).await
 }
 
-pub async fn fetch_orderbook(api_host: Url, orderbook_query: OrderBookQuery) -> ApiOrderBook {
-    fetch(api_host, "/api/v3/depth", orderbook_query, None).await
+pub async fn fetch_orderbook(
+    client: &HttpClient,
+    orderbook_query: OrderBookQuery,
+) -> Result<ApiOrderBook, Error> {
+    fetch(client, "/api/v3/depth", orderbook_query, None).await
 }
 
 pub async fn fetch_historical_trades(
-    api_host: Url,
+    client: &HttpClient,
     historical_trades_query: HistoricalTradesQuery,
-) -> Vec<ApiHistoricalTrade> {
+) -> Result<Vec<ApiHistoricalTrade>, Error> {
+    let api_key = HeaderValue::from_str(&historical_trades_query.api_key)
+        .map_err(|_| Error::InvalidApiKey)?;
     let mut headers = HeaderMap::new();
-    headers.insert("X-MBX-APIKEY", HeaderValue::from_str(&historical_trades_query.api_key).unwrap());
+    headers.insert("X-MBX-APIKEY", api_key);
     fetch_type(
-        api_host,
+        client,
         "/api/v3/historicalTrades",
         historical_trades_query.query,
         Some(headers)
@@ -188,26 +126,26 @@ pub async fn fetch_historical_trades(
 }
 
 pub async fn fetch_all_historical_trades(
-    api_host: Url,
+    client: &HttpClient,
     all_historical_trades_query: AllHistoricalTradesQuery,
-) -> Vec<ApiHistoricalTrade> {
+    clock: &dyn Clock,
+) -> Result<Vec<ApiHistoricalTrade>, Error> {
     let mut trades: Vec<Vec<ApiHistoricalTrade>> = Vec::new();
     info!(?all_historical_trades_query, "Do query");
     let window = all_historical_trades_query.query.window;
-    let now = || SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
     loop {
         tokio::time::sleep(Duration::from_millis(500)).await;
         if let Some(true) = trades
             .last()
             .and_then(|r| r.first())
-            .map(|first_trade| (now() - first_trade.time) > window)
+            .map(|first_trade| (clock.now() - first_trade.time) > window)
         {
             break;
         }
 
         let from_id = trades.last().and_then(|t| t.first()).map(|t| t.id - 500);
         let fetched_trades = fetch_historical_trades(
-            api_host.clone(),
+            client,
             HistoricalTradesQuery {
                 query: Query {
                     symbol: all_historical_trades_query.query.ticker.clone(),
@@ -215,10 +153,10 @@ pub async fn fetch_all_historical_trades(
                 },
                 api_key: all_historical_trades_query.api_key.clone(),
             },
-        ).await;
+        ).await?;
 
         trades.push(fetched_trades);
     }
 
-    trades.into_iter().rev().flatten().collect()
+    Ok(trades.into_iter().rev().flatten().collect())
 }
@@ -1,6 +1,5 @@
-use std::ops::Deref;
-
-use serde::{de, Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Serialize};
+use sources_common::decimal::{Price, Qty};
 
 use crate::ToChannel;
 
@@ -20,47 +19,50 @@ impl ToChannel for OrderBookChannel {
     }
 }
 
-#[derive(Deserialize)]
-pub struct PriceNode(#[serde(deserialize_with = "deser_floats_array_from_string_array")] [f64; 2]);
-
-impl Deref for PriceNode {
-    type Target = [f64; 2];
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl From<PriceNode> for [f64; 2] {
-    fn from(pn: PriceNode) -> Self {
-        pn.0
-    }
-}
-
 #[derive(Deserialize)]
 pub struct WsOrderBook {
     #[serde(rename = "u")]
     pub last_update_id: u64,
     #[serde(rename = "U")]
     pub first_update_id: u64,
-    #[serde(rename = "b")]
-    pub bids: Vec<PriceNode>,
-    #[serde(rename = "a")]
-    pub asks: Vec<PriceNode>,
+    /// Price and quantity at it, zero quantity meaning the level is gone
+    #[serde(rename = "b", with = "toolset::levels")]
+    pub bids: Vec<(Price, Qty)>,
+    #[serde(rename = "a", with = "toolset::levels")]
+    pub asks: Vec<(Price, Qty)>,
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ApiOrderBook {
     pub last_update_id: u64,
-    pub bids: Vec<PriceNode>,
-    pub asks: Vec<PriceNode>,
+    #[serde(with = "toolset::levels")]
+    pub bids: Vec<(Price, Qty)>,
+    #[serde(with = "toolset::levels")]
+    pub asks: Vec<(Price, Qty)>,
 }
 
-pub fn deser_floats_array_from_string_array<'de, D: Deserializer<'de>>(
-    deserializer: D,
-) -> Result<[f64; 2], D::Error> {
-    let str_arr = <[String; 2]>::deserialize(deserializer)?;
-    let price = str_arr[0].parse().map_err(de::Error::custom)?;
-    let quant = str_arr[1].parse().map_err(de::Error::custom)?;
-    Ok([price, quant])
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_depth_update() {
+        let input = r#"
+        {
+            "e": "depthUpdate",
+            "E": 123456789,
+            "s": "BNBBTC",
+            "U": 157,
+            "u": 160,
+            "b": [["0.0024", "10"]],
+            "a": [["0.0026", "100"], ["0.0027", "0.00000000"]]
+        }
+        "#;
+        let update = serde_json::from_str::<WsOrderBook>(input).unwrap();
+
+        assert_eq!(update.bids, vec![(Price::new(24, 4), Qty::new(10, 0))]);
+        assert_eq!(update.asks[0], (Price::new(26, 4), Qty::new(100, 0)));
+        assert!(update.asks[1].1.is_zero());
+    }
 }
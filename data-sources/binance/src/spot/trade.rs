@@ -1,7 +1,8 @@
-use std::time::Duration;
-
 use serde::Deserialize;
-use toolset::{deser_duration_from_integer, deser_float_from_string};
+use sources_common::{
+    decimal::{Price, Qty},
+    timestamp::{self, Timestamp},
+};
 
 #[derive(Deserialize)]
 pub struct WsTrade{
@@ -9,12 +10,12 @@ pub struct WsTrade{
     pub id: u64,
     #[serde(rename="s")]
     pub symbol: String,
-    #[serde(rename="p", deserialize_with = "deser_float_from_string")]
-    pub price: f64,
-    #[serde(rename="q", deserialize_with = "deser_float_from_string")]
-    pub qty: f64,
-    #[serde(rename="T", deserialize_with = "deser_duration_from_integer")]
-    pub time: Duration, // Trade executed timestamp, as same as `T` in the stream
+    #[serde(rename="p")]
+    pub price: Price,
+    #[serde(rename="q")]
+    pub qty: Qty,
+    #[serde(rename="T", with = "timestamp::millis")]
+    pub time: Timestamp, // Trade executed timestamp, as same as `T` in the stream
 
     #[serde(rename="m")]
     pub is_buyer_maker: bool,
@@ -23,7 +24,7 @@ pub struct WsTrade{
 }
 
 impl WsTrade {
-    pub fn quote_qty(&self) -> f64 {
+    pub fn quote_qty(&self) -> Qty {
         self.price * self.qty
     }
 }
@@ -1,11 +1,13 @@
-use reqwest::Client;
+use exchange_http::HttpClient;
 use serde::Deserialize;
-use toolset::deser_float_from_string;
-use url::Url;
+use sources_common::{
+    decimal::{Price, Qty},
+    instrument::{Exchange, Instrument, InstrumentStatus, MarketType},
+};
 
 #[derive(Deserialize)]
 pub struct Response<T> {
-    #[serde(rename = "ret_code")]
+    #[serde(rename = "ret_code", with = "toolset::string_or_int")]
     _code: u16,
     #[serde(rename = "ret_msg")]
     _ret_msg: String,
@@ -15,49 +17,65 @@ pub struct Response<T> {
 #[derive(Deserialize)]
 pub struct Symbol {
     pub name: String,
+    pub status: String,
     pub base_currency: String,
     pub quote_currency: String,
+    pub price_filter: Option<PriceFilter>,
+    pub lot_size_filter: Option<LotSizeFilter>,
 }
 
 #[derive(Deserialize)]
-pub struct TickerInfo {
-    pub symbol: String,
-    #[serde(deserialize_with = "deser_float_from_string")]
-    pub last_price: f64,
-}
-
-pub async fn fetch_symbols(api_host: Url) -> Vec<Symbol> {
-    let url = api_host.join("/v2/public/symbols").unwrap();
-    let result = Client::new()
-        .get(url.clone())
-        .send()
-        .await
-        .unwrap()
-        .text()
-        .await
-        .unwrap();
-    match serde_json::from_str::<Response<Vec<Symbol>>>(&result) {
-        Ok(r) => r.result,
-        Err(e) => {
-            panic!("Serde! {e}\n{} {}", url, result);
-        }
-    }
+pub struct PriceFilter {
+    pub tick_size: Price,
 }
 
-pub async fn fetch_tickers(api_host: Url) -> Vec<TickerInfo> {
-    let url = api_host.join("/v2/public/tickers").unwrap();
-    let result = Client::new()
-        .get(url)
-        .send()
-        .await
-        .unwrap()
-        .text()
-        .await
-        .unwrap();
-    match serde_json::from_str::<Response<Vec<TickerInfo>>>(&result) {
-        Ok(r) => r.result,
-        Err(e) => {
-            panic!("Serde! {e}");
+#[derive(Deserialize)]
+pub struct LotSizeFilter {
+    pub qty_step: Qty,
+}
+
+impl From<Symbol> for Instrument {
+    fn from(value: Symbol) -> Self {
+        // Quarterlies are named after their expiry, e.g. BTCUSDH23
+        let dated = value.name.ends_with(|c: char| c.is_ascii_digit());
+        let market = match value.quote_currency.as_str() {
+            _ if dated => MarketType::DatedFuture,
+            "USDT" | "USDC" => MarketType::LinearPerpetual,
+            _ => MarketType::InversePerpetual,
+        };
+        let status = match value.status.as_str() {
+            "Trading" => InstrumentStatus::Trading,
+            "PreLaunch" => InstrumentStatus::PreTrading,
+            "Settling" => InstrumentStatus::Settling,
+            "Closed" => InstrumentStatus::Closed,
+            _ => InstrumentStatus::Halted,
+        };
+        Self {
+            exchange: Exchange::Bybit,
+            market,
+            tick_size: value.price_filter.map(|filter| filter.tick_size),
+            lot_size: value.lot_size_filter.map(|filter| filter.qty_step),
+            contract_multiplier: Qty::new(1, 0),
+            status,
+            ticker: value.name,
+            base_asset: value.base_currency,
+            quote_asset: value.quote_currency,
         }
     }
 }
+
+#[derive(Deserialize)]
+pub struct TickerInfo {
+    pub symbol: String,
+    pub last_price: Price,
+}
+
+pub async fn fetch_symbols(client: &HttpClient) -> Result<Vec<Symbol>, exchange_http::Error> {
+    let response: Response<Vec<Symbol>> = client.get("/v2/public/symbols").json().await?;
+    Ok(response.result)
+}
+
+pub async fn fetch_tickers(client: &HttpClient) -> Result<Vec<TickerInfo>, exchange_http::Error> {
+    let response: Response<Vec<TickerInfo>> = client.get("/v2/public/tickers").json().await?;
+    Ok(response.result)
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use thiserror::Error;
+use url::Url;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid path {path}: {source}")]
+    Url {
+        path: String,
+        source: url::ParseError,
+    },
+
+    #[error("Cannot encode query: {0}")]
+    Query(serde_qs::Error),
+
+    #[error("Cannot build HTTP client: {0}")]
+    Build(reqwest::Error),
+
+    #[error("{url} timed out")]
+    Timeout { url: String },
+
+    #[error("Request to {url} failed: {source}")]
+    Transport { url: String, source: reqwest::Error },
+
+    #[error("{url} answered {status}: {body}")]
+    Status {
+        url: String,
+        status: StatusCode,
+        body: String,
+        /// What the server asked to wait with `Retry-After`
+        retry_after: Option<Duration>,
+    },
+
+    #[error("Cannot decode response of {url} <{body}>: {source}")]
+    Decode {
+        url: String,
+        source: serde_json::Error,
+        body: String,
+    },
+}
+
+impl Error {
+    pub(crate) fn from_reqwest(error: reqwest::Error, url: &Url) -> Self {
+        let url = url.to_string();
+        if error.is_timeout() {
+            Error::Timeout { url }
+        } else {
+            Error::Transport { url, source: error }
+        }
+    }
+
+    /// Worth trying again: the exchange is overloaded, rate limits or
+    /// could not be reached.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Timeout { .. } | Error::Transport { .. } => true,
+            Error::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! HTTP client shared by the exchange crates: one connection pool per
+//! client, a timeout on every request, and retries with jittered backoff
+//! while the exchange is overloaded, rate limits or cannot be reached.
+
+use std::time::{Duration, Instant};
+
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    Proxy, Response,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{debug, warn};
+use url::Url;
+
+pub use error::Error;
+pub use reqwest::{header, StatusCode};
+pub use retry::RetryPolicy;
+
+mod error;
+mod retry;
+
+/// Cheap to clone; clones share the connection pool.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    base_url: Url,
+    timeout: Duration,
+    retry: RetryPolicy,
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpClientBuilder {
+    base_url: Url,
+    timeout: Duration,
+    connect_timeout: Duration,
+    retry: RetryPolicy,
+    proxy: Option<Url>,
+}
+
+impl HttpClientBuilder {
+    /// For one attempt of a request, 10s by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sends every request through `proxy`, e.g. `http://proxy:3128`.
+    pub fn proxy(mut self, proxy: Url) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn build(self) -> Result<HttpClient, Error> {
+        let mut builder = reqwest::Client::builder().connect_timeout(self.connect_timeout);
+        if let Some(proxy) = self.proxy {
+            builder = builder.proxy(Proxy::all(proxy).map_err(Error::Build)?);
+        }
+        Ok(HttpClient {
+            client: builder.build().map_err(Error::Build)?,
+            base_url: self.base_url,
+            timeout: self.timeout,
+            retry: self.retry,
+        })
+    }
+}
+
+impl HttpClient {
+    /// Default timeouts and retries, no proxy.
+    pub fn new(base_url: Url) -> Self {
+        Self::builder(base_url)
+            .build()
+            .expect("HTTP client without a proxy")
+    }
+
+    pub fn builder(base_url: Url) -> HttpClientBuilder {
+        HttpClientBuilder {
+            base_url,
+            timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(5),
+            retry: RetryPolicy::default(),
+            proxy: None,
+        }
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// The same pool and settings for another host.
+    pub fn with_base_url(&self, base_url: Url) -> Self {
+        Self {
+            base_url,
+            ..self.clone()
+        }
+    }
+
+    /// `path` is relative to the base URL.
+    pub fn get(&self, path: &str) -> Request<'_> {
+        let url = self.base_url.join(path).map_err(|source| Error::Url {
+            path: path.to_string(),
+            source,
+        });
+        Request {
+            client: self,
+            url,
+            headers: HeaderMap::new(),
+        }
+    }
+}
+
+/// A GET request; failures to build it surface once it is sent.
+pub struct Request<'c> {
+    client: &'c HttpClient,
+    url: Result<Url, Error>,
+    headers: HeaderMap,
+}
+
+impl Request<'_> {
+    /// Parameters as a query string, usually from a struct.
+    pub fn query(mut self, query: &impl Serialize) -> Self {
+        self.url = self.url.and_then(|mut url| {
+            let qs = serde_qs::to_string(query).map_err(Error::Query)?;
+            if !qs.is_empty() {
+                url.set_query(Some(&qs));
+            }
+            Ok(url)
+        });
+        self
+    }
+
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    /// Body of the first successful response. Any status but 2xx is an
+    /// error, carrying the body the exchange explains itself in.
+    pub async fn text(self) -> Result<String, Error> {
+        self.send().await.map(|(_, body)| body)
+    }
+
+    pub async fn json<R: DeserializeOwned>(self) -> Result<R, Error> {
+        let (url, body) = self.send().await?;
+        serde_json::from_str(&body).map_err(|source| Error::Decode {
+            url: url.to_string(),
+            source,
+            body,
+        })
+    }
+
+    async fn send(self) -> Result<(Url, String), Error> {
+        let url = self.url?;
+        let mut retry = 0;
+        loop {
+            let started = Instant::now();
+            let result = match self
+                .client
+                .client
+                .get(url.clone())
+                .headers(self.headers.clone())
+                .timeout(self.client.timeout)
+                .send()
+                .await
+            {
+                Ok(response) => read(response, &url).await,
+                Err(error) => Err(Error::from_reqwest(error, &url)),
+            };
+            let latency = started.elapsed();
+            let error = match result {
+                Ok(body) => {
+                    debug!(%url, ?latency, retry, "GET");
+                    return Ok((url, body));
+                }
+                Err(error) => error,
+            };
+            let delay = error
+                .is_retryable()
+                .then(|| self.client.retry.delay(retry, error.retry_after()))
+                .flatten();
+            match delay {
+                Some(delay) => {
+                    warn!(%url, ?latency, retry, ?delay, %error, "GET failed, retry");
+                    tokio::time::sleep(delay).await;
+                    retry += 1;
+                }
+                None => {
+                    warn!(%url, ?latency, retry, %error, "GET failed");
+                    return Err(error);
+                }
+            }
+        }
+    }
+}
+
+async fn read(response: Response, url: &Url) -> Result<String, Error> {
+    let status = response.status();
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok()?.parse().ok())
+        .map(Duration::from_secs);
+    let body = response
+        .text()
+        .await
+        .map_err(|error| Error::from_reqwest(error, url))?;
+    if status.is_success() {
+        Ok(body)
+    } else {
+        Err(Error::Status {
+            url: url.to_string(),
+            status,
+            body,
+            retry_after,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Answers one connection per response, in order, and hands back the
+    /// request lines it got.
+    async fn serve(responses: Vec<String>) -> (Url, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                }
+                let request = String::from_utf8(request).unwrap();
+                requests.push(request.lines().next().unwrap().to_string());
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    fn quick_retries() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(5),
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Query {
+        symbol: String,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Ticker {
+        price: String,
+    }
+
+    #[tokio::test]
+    async fn retries_overload_and_rate_limits() {
+        let (url, server) = serve([
+            "HTTP/1.1 503 Service Unavailable\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+            "HTTP/1.1 429 Too Many Requests\r\nretry-after: 0\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 17\r\n\r\n{\"price\": \"1.50\"}",
+        ]
+        .map(String::from)
+        .to_vec())
+        .await;
+        let client = HttpClient::builder(url)
+            .retry(quick_retries())
+            .build()
+            .unwrap();
+
+        let ticker: Ticker = client
+            .get("/api/v3/ticker")
+            .query(&Query {
+                symbol: "BTCUSDT".to_string(),
+            })
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(ticker.price, "1.50");
+        let requests = server.await.unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[2].starts_with("GET /api/v3/ticker?symbol=BTCUSDT "));
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let body = r#"{"code":-1121,"msg":"Invalid symbol."}"#;
+        let response = format!(
+            "HTTP/1.1 400 Bad Request\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let (url, server) = serve(vec![response]).await;
+        let client = HttpClient::builder(url)
+            .retry(quick_retries())
+            .build()
+            .unwrap();
+
+        let error = client.get("/api/v3/depth").text().await.unwrap_err();
+
+        assert!(!error.is_retryable());
+        match error {
+            Error::Status {
+                status, body: got, ..
+            } => {
+                assert_eq!(status, 400);
+                assert_eq!(got, body);
+            }
+            error => panic!("unexpected {error}"),
+        }
+        assert_eq!(server.await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn gives_up_once_retries_are_used_up() {
+        let unavailable =
+            "HTTP/1.1 502 Bad Gateway\r\nconnection: close\r\ncontent-length: 0\r\n\r\n";
+        let (url, server) = serve(vec![unavailable.to_string(); 3]).await;
+        let client = HttpClient::builder(url)
+            .retry(quick_retries())
+            .build()
+            .unwrap();
+
+        let error = client.get("/").text().await.unwrap_err();
+
+        assert!(matches!(error, Error::Status { status, .. } if status == 502));
+        assert_eq!(server.await.unwrap().len(), 3);
+    }
+}
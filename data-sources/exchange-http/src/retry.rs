@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+/// Exponential backoff with jitter: retry `n`, counting from 0, waits
+/// between half and all of `initial * 2^n`, capped at `max`. A `Retry-After`
+/// from the exchange is waited out, unless it is longer than `max`; the error
+/// is then left to the caller, who knows how long it may wait.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial: Duration::from_millis(250),
+            max: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn never() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// `None` once the retries are used up or `retry_after` exceeds `max`.
+    pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        let retry_after = retry_after.unwrap_or_default();
+        if retry >= self.max_retries || retry_after > self.max {
+            return None;
+        }
+        let ceiling = self
+            .initial
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max);
+        let half = ceiling / 2;
+        let jitter = fastrand::u64(0..=(ceiling - half).as_nanos() as u64);
+        let delay = half + Duration::from_nanos(jitter);
+        Some(delay.max(retry_after))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_grow_with_jitter_up_to_max() {
+        let policy = RetryPolicy {
+            max_retries: 4,
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(300),
+        };
+        for _ in 0..100 {
+            let first = policy.delay(0, None).unwrap();
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let second = policy.delay(1, None).unwrap();
+            assert!(second >= Duration::from_millis(100) && second <= Duration::from_millis(200));
+            let capped = policy.delay(3, None).unwrap();
+            assert!(capped >= Duration::from_millis(150) && capped <= Duration::from_millis(300));
+        }
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_millis(250))),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(policy.delay(0, Some(Duration::from_secs(2))), None);
+        assert_eq!(policy.delay(4, None), None);
+        assert_eq!(RetryPolicy::never().delay(0, None), None);
+    }
+}
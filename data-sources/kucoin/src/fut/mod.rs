@@ -1,9 +1,15 @@
 use serde::Deserialize;
-use url::Url;
+use sources_common::{
+    decimal::{Price, Qty},
+    instrument::{Exchange, Instrument, InstrumentStatus, MarketType},
+};
+use exchange_http::HttpClient;
 
 #[derive(Deserialize)]
 pub struct Response<T> {
-    code: String,
+    /// `"200000"` on success
+    #[serde(with = "toolset::string_or_int")]
+    code: u32,
     data: T,
 }
 
@@ -13,38 +19,97 @@ pub struct ActiveContract {
     pub symbol: String,
     pub base_currency: String,
     pub quote_currency: String,
-    pub last_trade_price: f64,
+    pub last_trade_price: Price,
+    /// `FFWCSX` for perpetuals, `FFICSX` for futures with an expiry
+    #[serde(rename = "type")]
+    pub contract_type: String,
+    pub is_inverse: bool,
+    pub tick_size: Price,
+    pub lot_size: Qty,
+    /// Negative for inverse contracts
+    pub multiplier: Qty,
+    pub status: String,
 }
 
-pub async fn fetch_active_contracts(api_host: Url) -> Vec<ActiveContract> {
-
-    let url = api_host.join("/api/v1/contracts/active").unwrap();
-
-    let result = reqwest::Client::new()
-        .get(url.clone())
-        .send()
-        .await
-        .unwrap()
-        .text()
-        .await
-        .unwrap();
-
-    match serde_json::from_str::<Response<Vec<ActiveContract>>>(&result) {
-        Ok(r) => r.data,
-        Err(e) => {
-            panic!("Serde! {e} wtf: {} \n {:?}", result, url);
+impl From<ActiveContract> for Instrument {
+    fn from(value: ActiveContract) -> Self {
+        let market = match (value.contract_type.as_str(), value.is_inverse) {
+            ("FFICSX", _) => MarketType::DatedFuture,
+            (_, true) => MarketType::InversePerpetual,
+            (_, false) => MarketType::LinearPerpetual,
+        };
+        let status = match value.status.as_str() {
+            "Open" => InstrumentStatus::Trading,
+            "BeingSettled" => InstrumentStatus::Settling,
+            "Closed" => InstrumentStatus::Closed,
+            _ => InstrumentStatus::Halted,
+        };
+        Self {
+            exchange: Exchange::Kucoin,
+            market,
+            tick_size: Some(value.tick_size),
+            lot_size: Some(value.lot_size),
+            contract_multiplier: value.multiplier.abs(),
+            status,
+            ticker: value.symbol,
+            base_asset: value.base_currency,
+            quote_asset: value.quote_currency,
         }
     }
 }
+
+pub async fn fetch_active_contracts(
+    client: &HttpClient,
+) -> Result<Vec<ActiveContract>, exchange_http::Error> {
+    let response: Response<Vec<ActiveContract>> =
+        client.get("/api/v1/contracts/active").json().await?;
+    Ok(response.data)
+}
 #[cfg(test)]
 mod tests {
 
     use super::*;
 
+    #[test]
+    fn response_code_is_a_string() {
+        let input = r#"{"code": "200000", "data": []}"#;
+        let response = serde_json::from_str::<Response<Vec<ActiveContract>>>(input).unwrap();
+
+        assert_eq!(response.code, 200000);
+        assert!(response.data.is_empty());
+    }
+
+    #[test]
+    fn inverse_contract_becomes_instrument() {
+        let input = r#"
+        {
+            "symbol": "XBTUSDM",
+            "rootSymbol": "XBT",
+            "type": "FFWCSX",
+            "baseCurrency": "XBT",
+            "quoteCurrency": "USD",
+            "settleCurrency": "XBT",
+            "isInverse": true,
+            "tickSize": 0.1,
+            "lotSize": 1,
+            "multiplier": -1,
+            "status": "Open",
+            "lastTradePrice": 16800.5
+        }
+        "#;
+        let contract = serde_json::from_str::<ActiveContract>(input).unwrap();
+        let instrument = Instrument::from(contract);
+
+        assert_eq!(instrument.market, MarketType::InversePerpetual);
+        assert_eq!(instrument.contract_multiplier, Qty::new(1, 0));
+        assert_eq!(instrument.tick_size, Some(Price::new(1, 1)));
+        assert!(instrument.is_trading());
+    }
+
     #[tokio::test]
     async fn fetch_prices() {
-        let url = Url::parse("https://api-futures.kucoin.com").unwrap();
-        let active_contracts = fetch_active_contracts(url).await;
+        let url = url::Url::parse("https://api-futures.kucoin.com").unwrap();
+        let active_contracts = fetch_active_contracts(&HttpClient::new(url)).await.unwrap();
         println!(
             "active_contracts: {:#?}",
             active_contracts
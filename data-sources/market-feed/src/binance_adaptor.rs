@@ -1,16 +1,16 @@
-use std::time::{SystemTime, UNIX_EPOCH};
-
 use binance::{
+    error::Error,
     protocol::{StreamData, StreamPackage},
     spot::{
         candle::{CandleStream, CandlesQuery, WsCandle},
         historical_trades::{AllHistoricalTradesQuery, HistoricalTradesChannel},
+        market_stream::StreamEvent,
         orderbook::{OrderBookChannel, OrderBookQuery}, trade::WsTrade,
     },
     ToChannel,
 };
 use futures::{Stream, StreamExt};
-use sources_common::{time_unit::TimeUnit, symbol::{Symbol, self}};
+use sources_common::time_unit::TimeUnit;
 use tracing::{debug, error, info};
 
 use crate::{
@@ -22,59 +22,41 @@ use crate::{
     MarketFeedMessage, MarketFeedSettings, FetchSymbolInput,
 };
 
-pub async fn fetch_candles(input: FetchCandlesInput) -> Candles {
-    let from = SystemTime::now() - input.time_unit.calc_n(50);
-    let to = SystemTime::now() + input.time_unit.calc_n(1);
-    let from = from.duration_since(UNIX_EPOCH).unwrap().as_secs();
-    let to = to.duration_since(UNIX_EPOCH).unwrap().as_secs();
+pub async fn fetch_candles(input: FetchCandlesInput) -> Result<Candles, Error> {
+    let now = input.clock.now();
+    let from = now - input.time_unit.calc_n(50);
 
-    info!(
-        "from {} now: {}",
-        from,
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-    );
-    let bin_candles = binance::spot::fetch_candles(
-        input.api_host,
-        CandlesQuery {
-            symbol: input.ticker,
-            interval: input.time_unit.clone(),
-            start_time: Some(from * 1000),
-            end_time: None,
-            limit: None,
-            offset: None,
-        },
-    )
-    .await;
+    info!("from {} now: {}", from, now);
+    let mut query = CandlesQuery::new(input.ticker, &input.time_unit)?;
+    query.start_time = Some(from.as_millis() as u64);
+    let bin_candles = binance::spot::fetch_candles(&input.client, query).await?;
 
     info!("Fetched {} candles", bin_candles.len());
 
-    bin_candles
+    Ok(bin_candles
         .into_iter()
         .map(|c| (c, input.time_unit.clone()))
         .collect::<Vec<_>>()
-        .into()
+        .into())
 }
 
-pub async fn fetch_orderbook(input: FetchOrderbookInput) -> OrderBook {
+pub async fn fetch_orderbook(input: FetchOrderbookInput) -> Result<OrderBook, Error> {
     let orderbook = binance::spot::fetch_orderbook(
-        input.api_host,
+        &input.client,
         OrderBookQuery {
             limit: input.depth,
             symbol: input.ticker,
         },
     )
-    .await;
+    .await?;
 
-    orderbook.into()
+    Ok(orderbook.into())
 }
 
 /*
-pub async fn fetch_symbol(input: FetchSymbolInput) -> Symbol {
+pub async fn fetch_symbol(input: FetchSymbolInput) -> Instrument {
     let symbol = binance::spot::fetch_exchange_info(
-        input.api_host,
+        &input.client,
         binance::spot::exchange_info::ExchangeInfoRequest {
             symbol: Some(input.ticker), 
             symbols: None,
@@ -89,9 +71,9 @@ pub async fn fetch_symbol(input: FetchSymbolInput) -> Symbol {
 }
 */
 
-pub async fn fetch_historical_trades(input: FetchHistoricalTradesInput) -> Trades {
+pub async fn fetch_historical_trades(input: FetchHistoricalTradesInput) -> Result<Trades, Error> {
     let trades = binance::spot::fetch_all_historical_trades(
-        input.api_host,
+        &input.client,
         AllHistoricalTradesQuery {
             api_key: input.api_key,
             query: binance::spot::historical_trades::AllQuery {
@@ -99,26 +81,24 @@ pub async fn fetch_historical_trades(input: FetchHistoricalTradesInput) -> Trade
                 window: input.from,
             },
         },
+        input.clock.as_ref(),
     )
-    .await;
+    .await?;
 
-    Trades::new(trades.into_iter().map(Into::into).collect())
+    Ok(Trades::new(trades.into_iter().map(Into::into).collect(), input.clock))
 }
 
 pub async fn create_market_feed(
     input: MarketFeedInput,
-) -> Option<impl Stream<Item = MarketFeedMessage> + Send + Sync> {
-    let channels = input.get_channels();
-    let stream = binance::spot::get_market_stream(input.ws_url, channels).await;
-
-    Some(stream.filter_map(|item| async move {
-        match item.try_into() {
-            Ok(item) => Some(item),
-            Err(e) => {
-                info!("non-data message {}", e);
-                None
-            }
-        }
+) -> Result<impl Stream<Item = MarketFeedMessage> + Send + Sync, Error> {
+    let channels = input.get_channels()?;
+    let stream = binance::spot::get_market_stream(input.ws_url, channels).await?;
+
+    Ok(stream.map(|event| match event {
+        StreamEvent::Package(package) => package.into(),
+        StreamEvent::Connected => MarketFeedMessage::Connected,
+        StreamEvent::Disconnected { reason } => MarketFeedMessage::Disconnected { reason },
+        StreamEvent::Resubscribed => MarketFeedMessage::Resubscribed,
     }))
 }
 
@@ -135,16 +115,16 @@ impl From<binance::spot::historical_trades::ApiHistoricalTrade> for Trade {
 impl From<binance::spot::orderbook::WsOrderBook> for OrderBook {
     fn from(ob: binance::spot::orderbook::WsOrderBook) -> Self {
         Self {
-            asks: ob.asks.into_iter().map(Into::into).collect(),
-            bids: ob.bids.into_iter().map(Into::into).collect(),
+            asks: ob.asks,
+            bids: ob.bids,
         }
     }
 }
 impl From<binance::spot::orderbook::ApiOrderBook> for OrderBook {
     fn from(ob: binance::spot::orderbook::ApiOrderBook) -> Self {
         Self {
-            asks: ob.asks.into_iter().map(Into::into).collect(),
-            bids: ob.bids.into_iter().map(Into::into).collect(),
+            asks: ob.asks,
+            bids: ob.bids,
         }
     }
 }
@@ -240,24 +220,22 @@ impl TryFrom<StreamData> for MarketFeedMessage {
     fn try_from(value: StreamData) -> Result<Self, Self::Error> {
         match value {
             StreamData::Package(p) => Ok(p.into()),
-            StreamData::SubscribeResponse { response, id } => Err(format!(
-                "wtf: {} ({id})",
-                serde_json::to_string_pretty(&response).unwrap()
+            StreamData::SubscribeResponse { result, error, id } => Err(format!(
+                "wtf: {} {} ({id})",
+                serde_json::to_string_pretty(&result).unwrap(),
+                serde_json::to_string_pretty(&error).unwrap()
             )),
         }
     }
 }
 
 impl MarketFeedInput {
-    fn get_channels(&self) -> Vec<Box<dyn ToChannel + Send>> {
+    fn get_channels(&self) -> Result<Vec<Box<dyn ToChannel + Send>>, Error> {
         self.settings
             .iter()
             .map(|settings| {
                 let b: Box<dyn ToChannel + Send> = match settings {
-                    MarketFeedSettings::Candle(tu) => Box::new(CandleStream {
-                        ticker: self.ticker.clone(),
-                        time_unit: tu.clone(),
-                    }),
+                    MarketFeedSettings::Candle(tu) => Box::new(CandleStream::new(&self.ticker, tu)?),
                     MarketFeedSettings::OrderBook => Box::new(OrderBookChannel {
                         ticker: self.ticker.clone(),
                     }),
@@ -265,7 +243,7 @@ impl MarketFeedInput {
                         ticker: self.ticker.clone(),
                     }),
                 };
-                b
+                Ok(b)
             })
             .collect()
     }
@@ -1,17 +1,18 @@
-use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use sources_common::{
+    decimal::{Price, Qty},
+    time_unit::TimeUnit,
+    timestamp::Timestamp,
+};
 
-use sources_common::time_unit::TimeUnit;
-
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Candle {
-    pub ts: Duration,
+    pub ts: Timestamp,
     pub time_unit: TimeUnit,
-    pub open: f64,
-    pub high: f64,
-    pub low: f64,
-    pub close: f64,
-    pub volume: f64,
-    pub quote_volume: f64,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: Qty,
+    pub quote_volume: Qty,
 }
-
-impl Eq for Candle {}
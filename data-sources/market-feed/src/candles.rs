@@ -1,10 +1,14 @@
 use std::{cmp::Ordering, slice::Iter};
 
 use crate::candle::Candle;
-use sources_common::time_unit::TimeUnit;
+use serde::{Deserialize, Serialize};
+use sources_common::{
+    decimal::{Price, Qty},
+    time_unit::TimeUnit,
+};
 use tracing::info;
 
-#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Candles(Vec<Candle>);
 
 impl IntoIterator for Candles {
@@ -20,17 +24,15 @@ impl Candles {
         self.0.iter()
     }
 
-    pub fn min_low(&self) -> f64 {
-        let min = self.iter().map(|c| c.low).min_by(|a, b| a.total_cmp(b));
-        min.unwrap_or(f64::NEG_INFINITY)
+    pub fn min_low(&self) -> Option<Price> {
+        self.iter().map(|c| c.low).min()
     }
 
-    pub fn max_high(&self) -> f64 {
-        let min = self.iter().map(|c| c.high).max_by(|a, b| a.total_cmp(b));
-        min.unwrap_or(f64::NEG_INFINITY)
+    pub fn max_high(&self) -> Option<Price> {
+        self.iter().map(|c| c.high).max()
     }
 
-    pub fn total_volume(&self) -> f64 {
+    pub fn total_volume(&self) -> Qty {
         self.0.iter().map(|candle| candle.volume).sum()
     }
     pub fn len(&self) -> usize {
@@ -50,14 +52,12 @@ impl Candles {
         let last_volume = self
             .latest_candle()
             .map(|candle| candle.volume)
-            .unwrap_or(0.0);
-        last_volume / total
+            .unwrap_or_default();
+        last_volume.to_f64() / total.to_f64()
     }
 
-    pub fn current(&self) -> f64 {
-        self.latest_candle()
-            .map(|last_candle| last_candle.close)
-            .unwrap_or(0.0)
+    pub fn current(&self) -> Option<Price> {
+        self.latest_candle().map(|last_candle| last_candle.close)
     }
 
     pub fn new(candles: Vec<Candle>) -> Self {
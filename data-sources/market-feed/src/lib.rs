@@ -1,9 +1,10 @@
 use std::time::Duration;
 
 use candle::Candle;
+use exchange_http::HttpClient;
 use order_book::OrderBook;
 use trade::Trade;
-use sources_common::time_unit::TimeUnit;
+use sources_common::{clock::SharedClock, time_unit::TimeUnit};
 use url::Url;
 
 pub mod candle;
@@ -16,6 +17,11 @@ pub enum MarketFeedMessage {
     Candle(Candle),
     OrderBook(OrderBook),
     Trade(Trade),
+    Connected,
+    /// Updates are missed until `Resubscribed`
+    Disconnected { reason: String },
+    /// The feed is back after a gap, snapshots are stale
+    Resubscribed,
 }
 
 pub enum MarketFeedSettings {
@@ -31,28 +37,30 @@ pub struct MarketFeedInput {
 }
 
 pub struct FetchCandlesInput {
-    pub api_host: Url,
+    pub client: HttpClient,
     pub ticker: String,
     pub time_unit: TimeUnit,
     pub countback: usize,
+    pub clock: SharedClock,
 }
 
 pub struct FetchOrderbookInput {
-    pub api_host: Url,
+    pub client: HttpClient,
     pub ticker: String,
     pub depth: u32,
 }
 
 pub struct FetchSymbolInput {
-    pub api_host: Url,
+    pub client: HttpClient,
     pub ticker: String,
 }
 
 pub struct FetchHistoricalTradesInput {
-    pub api_host: Url,
+    pub client: HttpClient,
     pub api_key: String,
     pub ticker: String,
     pub from: Duration,
+    pub clock: SharedClock,
 }
 
 #[cfg(feature = "binance")]
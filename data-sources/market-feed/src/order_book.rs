@@ -1,29 +1,58 @@
-#[derive(Clone, Debug, Default, PartialEq)]
+use serde::{Deserialize, Serialize};
+use sources_common::decimal::{Price, Qty};
+
+/// Price levels, each with the quantity at it, sorted by price.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OrderBook {
-    pub asks: Vec<[f64; 2]>,
-    pub bids: Vec<[f64; 2]>,
+    pub asks: Vec<(Price, Qty)>,
+    pub bids: Vec<(Price, Qty)>,
 }
 
-impl Eq for OrderBook {}
-
-fn join_arr(state: &mut Vec<[f64; 2]>, update: &[[f64; 2]]) {
-    for a in update {
-        if a[1] == 0.0 {
-            if let Some(ix) = state.iter().position(|ask| ask[0] == a[0]) {
+fn join_levels(state: &mut Vec<(Price, Qty)>, update: &[(Price, Qty)]) {
+    for &(price, qty) in update {
+        let level = state.iter().position(|(level, _)| *level == price);
+        match level {
+            Some(ix) if qty.is_zero() => {
                 state.remove(ix);
             }
-        } else if let Some(pos) = state.iter_mut().find(|ask| ask[0] == a[0]) {
-            pos[1] = a[1];
-        } else {
-            state.push(*a);
-            state.sort_by(|a, b| a[0].partial_cmp(&b[0]).expect("wrong float in order book"));
+            Some(ix) => state[ix].1 = qty,
+            None if qty.is_zero() => {}
+            None => {
+                state.push((price, qty));
+                state.sort_by_key(|(price, _)| *price);
+            }
         }
     }
 }
 
 impl OrderBook {
     pub fn join(&mut self, ob: Self) {
-        join_arr(&mut self.asks, &ob.asks);
-        join_arr(&mut self.bids, &ob.bids);
+        join_levels(&mut self.asks, &ob.asks);
+        join_levels(&mut self.bids, &ob.bids);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(price: &str, qty: &str) -> (Price, Qty) {
+        (price.parse().unwrap(), qty.parse().unwrap())
+    }
+
+    #[test]
+    fn updates_match_levels_exactly() {
+        let mut book = OrderBook {
+            asks: vec![level("0.3", "1"), level("0.5", "2")],
+            bids: vec![level("0.1", "4")],
+        };
+        book.join(OrderBook {
+            // 0.1 + 0.2 is not 0.3 in floats
+            asks: vec![level("0.30", "0"), level("0.4", "3"), level("0.5000", "5")],
+            bids: vec![level("0.2", "0")],
+        });
+
+        assert_eq!(book.asks, vec![level("0.4", "3"), level("0.5", "5")]);
+        assert_eq!(book.bids, vec![level("0.1", "4")]);
     }
 }
@@ -1,16 +1,22 @@
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
+use sources_common::{
+    clock::SharedClock,
+    decimal::{Price, Qty},
+    timestamp::Timestamp,
+};
 use tracing::warn;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Trade {
-    pub price: f64,
-    pub quantity: f64,
-    pub quote_quantity: f64,
-    pub time: Duration, // Trade executed timestamp, as same as `T` in the stream
+    pub price: Price,
+    pub quantity: Qty,
+    pub quote_quantity: Qty,
+    pub time: Timestamp, // Trade executed timestamp, as same as `T` in the stream
 }
 
-#[derive(Default, Debug, Clone, PartialEq)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TradesAggregate {
     pub support_volume: f64,
     pub min_price: f64,
@@ -25,41 +31,49 @@ pub struct AggregateOptions {
     speed_factor_window: Duration,
 }
 
-pub struct Trades(Vec<Trade>);
+/// Trades in time order, aged by `clock`.
+pub struct Trades {
+    trades: Vec<Trade>,
+    clock: SharedClock,
+}
 
 impl Trades {
-    pub fn new(trades: Vec<Trade>) -> Self {
-        Trades(trades)
+    pub fn new(trades: Vec<Trade>, clock: SharedClock) -> Self {
+        if let Some(last) = trades.last() {
+            clock.observe(last.time);
+        }
+        Trades { trades, clock }
     }
 
     pub fn add(&mut self, trade: Trade) {
-        self.0.push(trade);
+        self.clock.observe(trade.time);
+        self.trades.push(trade);
     }
 
     pub fn remove_old(&mut self, window: Duration) {
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-        let was = self.0.len();
-        self.0.retain(|t| now - t.time < window);
-        let deleted = was - self.0.len();
-        warn!("deleted {}/ {}", deleted, self.0.len());
+        let now = self.clock.now();
+        let was = self.trades.len();
+        self.trades.retain(|t| now - t.time < window);
+        let deleted = was - self.trades.len();
+        warn!("deleted {}/ {}", deleted, self.trades.len());
     }
 
     pub fn calculate_speed_factor(&self, window: Duration) -> Option<f64> {
-        self.0
+        self.trades
             .first()
-            .and_then(|f| self.0.last().map(|l| (f, l)))
+            .and_then(|f| self.trades.last().map(|l| (f, l)))
             .and_then(|(first_trade, last_trade)| {
                 let time_between = last_trade.time - first_trade.time;
-                let average_trades_per_second = self.0.len() as f64 / time_between.as_secs_f64();
-                let time_mark = SystemTime::now().duration_since(UNIX_EPOCH).unwrap() - window;
-                self.0
+                let average_trades_per_second = self.trades.len() as f64 / time_between.as_secs_f64();
+                let time_mark = self.clock.now() - window;
+                self.trades
                     .iter()
                     .position(|trade| trade.time > time_mark)
-                    .map(|pos| (pos, self.0.get(pos).unwrap()))
-                    .and_then(|f| self.0.last().map(|l| (f, l)))
+                    .map(|pos| (pos, self.trades.get(pos).unwrap()))
+                    .and_then(|f| self.trades.last().map(|l| (f, l)))
                     .map(|((position, first_trade), last_trade)| {
                         let time_between = last_trade.time - first_trade.time;
-                        let amount_in_interval = self.0.len() - position;
+                        let amount_in_interval = self.trades.len() - position;
                         amount_in_interval as f64 / time_between.as_secs_f64()
                     })
                     .map(|window_speed| window_speed / average_trades_per_second)
@@ -68,13 +82,9 @@ impl Trades {
 
     pub fn calculate_aggregate(&self, options: &AggregateOptions) -> TradesAggregate {
         // TODO: Implement trades aggregate
-        let current_price = self.0.last().unwrap().price;
-        let mut min_price = f64::INFINITY;
-        let mut max_price = f64::NEG_INFINITY;
-        for t in &self.0 {
-            min_price = min_price.min(t.price);
-            max_price = max_price.max(t.price);
-        }
+        let current_price = self.trades.last().unwrap().price.to_f64();
+        let min_price = self.trades.iter().map(|t| t.price).min().unwrap().to_f64();
+        let max_price = self.trades.iter().map(|t| t.price).max().unwrap().to_f64();
         let tolerance = options.tolerance.max(options.tick_size / 2.0);
         let range = max_price - min_price;
         let tolerance = tolerance * range;
@@ -85,17 +95,18 @@ impl Trades {
             .calculate_speed_factor(options.speed_factor_window)
             .unwrap_or(1.0);
 
-        let mut total_volume = 0.0;
-        let mut price_volume = 0.0;
-        for t in &self.0 {
+        let mut total_volume = Qty::ZERO;
+        let mut price_volume = Qty::ZERO;
+        for t in &self.trades {
             total_volume += t.quantity;
-            if t.price > lower_price && t.price < higher_price {
+            let price = t.price.to_f64();
+            if price > lower_price && price < higher_price {
                 price_volume += t.quantity;
             }
         }
 
         TradesAggregate {
-            support_volume: price_volume / total_volume,
+            support_volume: price_volume.to_f64() / total_volume.to_f64(),
             min_price,
             max_price,
             current_price,
@@ -113,4 +124,32 @@ impl AggregateOptions {
         }
     }
 }
-impl Eq for TradesAggregate {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use sources_common::clock::{Clock, SimulatedClock};
+
+    use super::*;
+
+    fn trade(millis: i64) -> Trade {
+        Trade {
+            price: Price::new(100, 0),
+            quantity: Qty::new(1, 0),
+            quote_quantity: Qty::new(100, 0),
+            time: Timestamp::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn replayed_trades_age_by_event_time() {
+        let clock = SimulatedClock::default();
+        let mut trades = Trades::new(vec![trade(1_000), trade(2_000)], Arc::new(clock.clone()));
+        trades.add(trade(4_000));
+        trades.remove_old(Duration::from_millis(2_500));
+
+        assert_eq!(clock.now(), Timestamp::from_millis(4_000));
+        assert_eq!(trades.trades, vec![trade(2_000), trade(4_000)]);
+    }
+}
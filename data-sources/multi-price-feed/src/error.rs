@@ -0,0 +1,23 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("No client for {0}, add one with `add_url` or `add_client`")]
+    MissingClient(&'static str),
+
+    #[cfg(feature = "binance")]
+    #[error("Cannot fetch binance symbols: {0}")]
+    Binance(#[from] binance::error::Error),
+
+    #[cfg(feature = "binance")]
+    #[error("Cannot decode binance symbol: {0}")]
+    BinanceSymbol(serde_json::Error),
+
+    #[cfg(feature = "kucoin")]
+    #[error("Cannot fetch kucoin contracts: {0}")]
+    Kucoin(exchange_http::Error),
+
+    #[cfg(feature = "bybit")]
+    #[error("Cannot fetch bybit symbols: {0}")]
+    Bybit(exchange_http::Error),
+}
@@ -1,27 +1,37 @@
 use std::{collections::HashMap, sync::Arc, time::Duration};
 
+use exchange_http::HttpClient;
 use futures::{channel::mpsc, FutureExt, SinkExt, Stream};
+use serde::{Deserialize, Serialize};
+use sources_common::{
+    decimal,
+    instrument::Instrument,
+    registry::{Listing, SymbolRegistry},
+};
 use tracing::{info, warn};
 use url::Url;
 
-#[derive(Debug, Clone, PartialEq, Hash, Eq)]
-pub struct Symbol {
-    pub ticker: String,
-    pub base_asset: String,
-    pub quote_asset: String,
-    pub source: String,
-}
+use crate::error::Error;
+
+pub mod error;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Price {
-    pub symbol: Symbol,
-    pub price: f64,
+    pub instrument: Instrument,
+    /// What the instrument is called across exchanges
+    pub listing: Listing,
+    pub price: decimal::Price,
 }
 
 impl Price {
-    pub fn percentage(&self, prev: f64) -> f64 {
-        let diff = (prev - self.price).abs();
-        diff / prev
+    /// Comparable across exchanges, whatever units they quote for.
+    pub fn unit_price(&self) -> decimal::Price {
+        self.price.per_unit(self.listing.units)
+    }
+
+    pub fn percentage(&self, prev: decimal::Price) -> f64 {
+        let diff = (prev - self.unit_price()).abs();
+        diff.to_f64() / prev.to_f64()
     }
 }
 
@@ -29,60 +39,104 @@ type FilterClosure<T> = Arc<dyn Fn(&T) -> bool + Send + Sync>;
 
 #[derive(Clone)]
 pub struct GetMultiPriceFeedInput {
-    urls: HashMap<String, Url>,
-    binance_filters: Vec<FilterClosure<Symbol>>,
+    clients: HashMap<String, HttpClient>,
+    binance_filters: Vec<FilterClosure<Instrument>>,
     waiting_period: Duration,
 }
 
 impl GetMultiPriceFeedInput {
     pub fn new(period: Duration) -> Self {
         GetMultiPriceFeedInput {
-            urls: Default::default(),
+            clients: Default::default(),
             binance_filters: Default::default(),
             waiting_period: period,
         }
     }
-    pub fn add_filter(&mut self, filter: impl Fn(&Symbol) -> bool + Send + Sync + 'static) {
+    pub fn add_filter(&mut self, filter: impl Fn(&Instrument) -> bool + Send + Sync + 'static) {
         self.binance_filters.push(Arc::new(filter));
     }
 
     pub fn add_url(&mut self, source: &str, url: &str) {
-        self.urls.insert(source.into(), Url::parse(url).unwrap());
+        self.add_client(source, HttpClient::new(Url::parse(url).unwrap()));
+    }
+
+    /// For a source that needs its own timeouts, retries or proxy
+    pub fn add_client(&mut self, source: &str, client: HttpClient) {
+        self.clients.insert(source.into(), client);
+    }
+
+    fn client(&self, source: &'static str) -> Result<HttpClient, Error> {
+        self.clients
+            .get(source)
+            .cloned()
+            .ok_or(Error::MissingClient(source))
     }
 }
 
+/// Fails when a client of an enabled source is missing or its symbols
+/// cannot be fetched; later fetches only log their errors. Prices only come
+/// for instruments the `SymbolRegistry` knows, so dated futures are left out.
 pub async fn get_multi_price_feed(
     input: GetMultiPriceFeedInput,
-) -> impl Stream<Item = Price> + Send + Sync {
+) -> Result<impl Stream<Item = Price> + Send + Sync, Error> {
     let (tx, rx) = mpsc::unbounded();
     let mut futures = Vec::new();
+    let mut registry = SymbolRegistry::new();
+
+    #[cfg(feature = "kucoin")]
+    let kucoin_contracts = collect_kucoin_contracts(input.clone()).await?;
+    #[cfg(feature = "kucoin")]
+    registry.extend(&kucoin_contracts);
+    #[cfg(feature = "binance")]
+    let binance_symbols = collect_binance_symbols(input.clone())
+        .await?
+        .into_iter()
+        .map(|symbol| (symbol.ticker.clone(), symbol))
+        .collect::<HashMap<_, _>>();
+    #[cfg(feature = "binance")]
+    registry.extend(binance_symbols.values());
+    #[cfg(feature = "bybit")]
+    let bybit_symbols = collect_bybit_symbols(input.clone())
+        .await?
+        .into_iter()
+        .map(|symbol| (symbol.ticker.clone(), symbol))
+        .collect::<HashMap<_, _>>();
+    #[cfg(feature = "bybit")]
+    registry.extend(bybit_symbols.values());
+    let registry = Arc::new(registry);
 
     #[cfg(feature = "kucoin")]
     {
         use kucoin::fut::fetch_active_contracts;
         let mut tx = tx.clone();
+        let registry = registry.clone();
         let waiting_period = input.waiting_period;
         let input = input.clone();
+        let client = input.client("kucoin")?;
+        info!(symbols = ?kucoin_contracts, "Collected kucoin symbols for monitoring");
         futures.push(
             async move {
-                let url = input
-                    .urls
-                    .get("kucoin")
-                    .map(Clone::clone)
-                    .unwrap();
                 loop {
                     info!("query prices kucoin");
-                    let mut prices: Box<dyn Iterator<Item = Price> + Send> = Box::new(
-                        fetch_active_contracts(url.clone())
-                            .await
-                            .into_iter()
-                            .map(Into::into),
-                    );
+                    let contracts = match fetch_active_contracts(&client).await {
+                        Ok(contracts) => contracts,
+                        Err(e) => {
+                            warn!(%e, "Cannot fetch prices from kucoin");
+                            Vec::new()
+                        }
+                    };
+                    let mut instruments: Box<dyn Iterator<Item = (Instrument, decimal::Price)> + Send> =
+                        Box::new(contracts.into_iter().map(|contract| {
+                            let price = contract.last_trade_price;
+                            (contract.into(), price)
+                        }));
                     for filter in input.binance_filters.clone() {
-                        let iter = prices.filter(move |p| (filter.clone())(&p.symbol));
-                        prices = Box::new(iter);
+                        let iter = instruments.filter(move |(instrument, _)| (filter.clone())(instrument));
+                        instruments = Box::new(iter);
                     }
-                    let prices = prices.collect::<Vec<_>>();
+                    let prices = instruments
+                        .filter_map(|(instrument, price)| listed(&registry, instrument, price))
+                        .collect::<Vec<_>>();
 
                     for price in prices.into_iter() {
                         if let Err(e) = tx.send(price).await {
@@ -100,36 +154,24 @@ pub async fn get_multi_price_feed(
     {
         use binance::fut::fetch_ticker_price;
         let mut tx = tx.clone();
-        let symbols = collect_binance_symbols(input.clone())
-            .await
-            .into_iter()
-            .map(|symbol| (symbol.ticker.clone(), symbol))
-            .collect::<HashMap<_, _>>();
+        let registry = registry.clone();
+        let symbols = binance_symbols;
         info!(?symbols, "Collected symbols for monitoring");
-        let input = input.clone();
+        let client = input.client("binance")?;
         let waiting_period = input.waiting_period;
         futures.push(
             async move {
-                let url = input
-                    .urls
-                    .get("binance")
-                    .map(Clone::clone)
-                    .unwrap();
                 loop {
-                    let prices = fetch_ticker_price(url.clone()).await;
+                    let prices = fetch_ticker_price(&client).await.unwrap_or_else(|e| {
+                        warn!(%e, "Cannot fetch prices from binance");
+                        Vec::new()
+                    });
                     info!("query prices");
                     for p in prices {
-                        if let Some(symbol) = symbols.get(&p.symbol) {
-                            let symbol = Symbol {
-                                ticker: symbol.ticker.clone(),
-                                base_asset: symbol.base_asset.clone(),
-                                quote_asset: symbol.quote_asset.clone(),
-                                source: "binance".into(),
-                            };
-                            let price = Price {
-                                symbol,
-                                price: p.price,
-                            };
+                        let Some(instrument) = symbols.get(&p.symbol) else {
+                            continue;
+                        };
+                        if let Some(price) = listed(&registry, instrument.clone(), p.price) {
                             if let Err(e) = tx.send(price).await {
                                 warn!(?e, "Cannot pass price from binance");
                             }
@@ -146,33 +188,26 @@ pub async fn get_multi_price_feed(
     {
         use bybit::fut::fetch_tickers;
         let mut tx = tx.clone();
-        let symbols = collect_bybit_symbols(input.clone())
-            .await
-            .into_iter()
-            .map(|symbol| (symbol.ticker.clone(), symbol))
-            .collect::<HashMap<_, _>>();
+        let registry = registry.clone();
+        let symbols = bybit_symbols;
         info!(?symbols, "Collected symbols for monitoring");
+        let client = input.client("bybit")?;
         let waiting_period = input.waiting_period;
         futures.push(
             async move {
-                let url = input.urls.get("bybit").map(Clone::clone).unwrap();
                 loop {
-                    let prices = fetch_tickers(url.clone()).await;
+                    let prices = fetch_tickers(&client).await.unwrap_or_else(|e| {
+                        warn!(%e, "Cannot fetch prices from bybit");
+                        Vec::new()
+                    });
                     info!("query prices bybit");
                     for p in prices {
-                        if let Some(symbol) = symbols.get(&p.symbol) {
-                            let symbol = Symbol {
-                                ticker: symbol.ticker.clone(),
-                                base_asset: symbol.base_asset.clone(),
-                                quote_asset: symbol.quote_asset.clone(),
-                                source: "bybit".into(),
-                            };
-                            let price = Price {
-                                symbol,
-                                price: p.last_price,
-                            };
+                        let Some(instrument) = symbols.get(&p.symbol) else {
+                            continue;
+                        };
+                        if let Some(price) = listed(&registry, instrument.clone(), p.last_price) {
                             if let Err(e) = tx.send(price).await {
-                                warn!(?e, "Cannot pass price from binance");
+                                warn!(?e, "Cannot pass price from bybit");
                             }
                         }
                     }
@@ -187,78 +222,52 @@ pub async fn get_multi_price_feed(
         futures::future::join_all(futures).await;
     });
 
-    rx
+    Ok(rx)
 }
 
-#[cfg(feature = "binance")]
-impl From<binance::fut::exchange_info::Symbol> for Symbol {
-    fn from(bs: binance::fut::exchange_info::Symbol) -> Self {
-        Self {
-            source: "binance".into(),
-            ticker: bs.symbol,
-            quote_asset: bs.quote_asset,
-            base_asset: bs.base_asset,
-        }
-    }
-}
-
-#[cfg(feature = "bybit")]
-impl From<bybit::fut::Symbol> for Symbol {
-    fn from(bs: bybit::fut::Symbol) -> Self {
-        Self {
-            source: "bybit".into(),
-            ticker: bs.name,
-            quote_asset: bs.quote_currency,
-            base_asset: bs.base_currency,
-        }
-    }
+/// `None` for instruments the registry leaves out.
+fn listed(registry: &SymbolRegistry, instrument: Instrument, price: decimal::Price) -> Option<Price> {
+    let listing = registry
+        .canonical(instrument.exchange, &instrument.ticker)?
+        .clone();
+    Some(Price {
+        instrument,
+        listing,
+        price,
+    })
 }
 
 #[cfg(feature = "kucoin")]
-impl From<kucoin::fut::ActiveContract> for Price {
-    fn from(active_contract: kucoin::fut::ActiveContract) -> Self {
-        let symbol = Symbol {
-            source: "kucoin".into(),
-            ticker: active_contract.symbol,
-            quote_asset: active_contract.quote_currency,
-            base_asset: active_contract.base_currency,
-        };
-        Self {
-            symbol,
-            price: active_contract.last_trade_price,
-        }
-    }
-}
+async fn collect_kucoin_contracts(input: GetMultiPriceFeedInput) -> Result<Vec<Instrument>, Error> {
+    let client = input.client("kucoin")?;
+    let contracts = kucoin::fut::fetch_active_contracts(&client)
+        .await
+        .map_err(Error::Kucoin)?;
 
-#[cfg(feature = "kucoin")]
-impl From<kucoin::fut::ActiveContract> for Symbol {
-    fn from(active_contract: kucoin::fut::ActiveContract) -> Self {
-        Self {
-            source: "kucoin".into(),
-            ticker: active_contract.symbol,
-            quote_asset: active_contract.quote_currency,
-            base_asset: active_contract.base_currency,
-        }
+    let mut iter: Box<dyn Iterator<Item = Instrument>> =
+        Box::new(contracts.into_iter().map(Instrument::from));
+    for f in &input.binance_filters {
+        let i = iter.filter(f.as_ref());
+        iter = Box::new(i);
     }
+
+    Ok(iter.collect())
 }
 
 #[cfg(feature = "binance")]
-async fn collect_binance_symbols(input: GetMultiPriceFeedInput) -> Vec<Symbol> {
+async fn collect_binance_symbols(input: GetMultiPriceFeedInput) -> Result<Vec<Instrument>, Error> {
     let mut symbols = Vec::new();
     use binance::fut::exchange_info::{ExchangeInfoRequest, Symbol as BinanceSymbol};
-    let url = input
-        .urls
-        .get("binance")
-        .map(Clone::clone)
-        .unwrap();
-    let info = binance::fut::fetch_exchange_info(url, ExchangeInfoRequest::default()).await;
-
-    let mut iter: Box<dyn Iterator<Item = Symbol>> = Box::new(
-        info.symbols
-            .into_iter()
-            .map(|v| serde_json::from_value::<BinanceSymbol>(v).unwrap())
-            .map(|v| v.into()),
-    );
+    let client = input.client("binance")?;
+    let info = binance::fut::fetch_exchange_info(&client, ExchangeInfoRequest::default()).await?;
+
+    let instruments = info
+        .symbols
+        .into_iter()
+        .map(|v| serde_json::from_value::<BinanceSymbol>(v).map(Instrument::from))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::BinanceSymbol)?;
+    let mut iter: Box<dyn Iterator<Item = Instrument>> = Box::new(instruments.into_iter());
 
     for f in &input.binance_filters {
         let i = iter.filter(f.as_ref());
@@ -267,20 +276,18 @@ async fn collect_binance_symbols(input: GetMultiPriceFeedInput) -> Vec<Symbol> {
 
     symbols.extend(iter);
 
-    symbols
+    Ok(symbols)
 }
 
 #[cfg(feature = "bybit")]
-async fn collect_bybit_symbols(input: GetMultiPriceFeedInput) -> Vec<Symbol> {
+async fn collect_bybit_symbols(input: GetMultiPriceFeedInput) -> Result<Vec<Instrument>, Error> {
     let mut symbols = Vec::new();
-    let url = input
-        .urls
-        .get("bybit")
-        .map(Clone::clone)
-        .unwrap();
-    let info = bybit::fut::fetch_symbols(url).await;
+    let client = input.client("bybit")?;
+    let info = bybit::fut::fetch_symbols(&client)
+        .await
+        .map_err(Error::Bybit)?;
 
-    let mut iter: Box<dyn Iterator<Item = Symbol>> = Box::new(info.into_iter().map(|v| v.into()));
+    let mut iter: Box<dyn Iterator<Item = Instrument>> = Box::new(info.into_iter().map(|v| v.into()));
 
     for f in &input.binance_filters {
         let i = iter.filter(f.as_ref());
@@ -288,7 +295,7 @@ async fn collect_bybit_symbols(input: GetMultiPriceFeedInput) -> Vec<Symbol> {
     }
     symbols.extend(iter);
 
-    symbols
+    Ok(symbols)
 }
 
 #[cfg(test)]
@@ -301,10 +308,11 @@ mod test {
         use std::{sync::Arc, time::Duration};
 
         let mut input = crate::GetMultiPriceFeedInput::new(Duration::from_secs(15));
+        input.add_url("binance", "https://fapi.binance.com");
         input
             .binance_filters
             .push(Arc::new(|s| s.quote_asset == "USDT"));
-        let symbols = collect_binance_symbols(input).await;
+        let symbols = collect_binance_symbols(input).await.unwrap();
         assert!(symbols.is_empty())
     }
 }
@@ -0,0 +1,106 @@
+//! Where "now" comes from. Live feeds use the wall clock, replays and
+//! backtests the time of the events they replay, tests whatever they set.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::timestamp::Timestamp;
+
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> Timestamp;
+
+    /// Tells the clock an event happened at `time`. Only clocks following
+    /// event time care.
+    fn observe(&self, _time: Timestamp) {}
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// Wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl SystemClock {
+    pub fn shared() -> SharedClock {
+        Arc::new(Self)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::now()
+    }
+}
+
+/// Stands still until set or advanced. Clones share the time.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock(Arc<AtomicI64>);
+
+impl ManualClock {
+    pub fn new(start: Timestamp) -> Self {
+        Self(Arc::new(AtomicI64::new(start.as_micros())))
+    }
+
+    pub fn set(&self, time: Timestamp) {
+        self.0.store(time.as_micros(), Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let now = self.now() + by;
+        self.set(now);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::from_micros(self.0.load(Ordering::SeqCst))
+    }
+}
+
+/// Event time of replayed data: the latest event observed so far. Never
+/// goes back, so late events do not rewind it. Clones share the time.
+#[derive(Debug, Clone, Default)]
+pub struct SimulatedClock(Arc<AtomicI64>);
+
+impl SimulatedClock {
+    pub fn new(start: Timestamp) -> Self {
+        Self(Arc::new(AtomicI64::new(start.as_micros())))
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::from_micros(self.0.load(Ordering::SeqCst))
+    }
+
+    fn observe(&self, time: Timestamp) {
+        self.0.fetch_max(time.as_micros(), Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulated_clock_follows_events_forward_only() {
+        let clock = SimulatedClock::new(Timestamp::from_millis(1_000));
+        let shared: SharedClock = Arc::new(clock.clone());
+        shared.observe(Timestamp::from_millis(5_000));
+        shared.observe(Timestamp::from_millis(3_000));
+        assert_eq!(clock.now(), Timestamp::from_millis(5_000));
+
+        let manual = ManualClock::new(Timestamp::from_millis(1_000));
+        manual.observe(Timestamp::from_millis(5_000));
+        manual.advance(Duration::from_secs(1));
+        assert_eq!(manual.now(), Timestamp::from_millis(2_000));
+        manual.set(Timestamp::UNIX_EPOCH);
+        assert_eq!(manual.now(), Timestamp::UNIX_EPOCH);
+    }
+}
@@ -0,0 +1,246 @@
+//! Exact prices and quantities. Exchanges send numbers as strings to keep
+//! them exact; parsing them into `f64` loses that, and makes equal levels
+//! compare unequal.
+
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Mul, Sub, SubAssign},
+    str::FromStr,
+};
+
+use rust_decimal::{prelude::ToPrimitive, Decimal, RoundingStrategy};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+macro_rules! decimal {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(Decimal);
+
+        impl $name {
+            pub const ZERO: Self = Self(Decimal::ZERO);
+
+            /// `mantissa * 10^-scale`, e.g. `new(1005, 1)` is 100.5.
+            pub fn new(mantissa: i64, scale: u32) -> Self {
+                Self(Decimal::new(mantissa, scale))
+            }
+
+            pub fn is_zero(&self) -> bool {
+                self.0.is_zero()
+            }
+
+            /// Digits after the decimal point, as the exchange sent them.
+            pub fn scale(&self) -> u32 {
+                self.0.scale()
+            }
+
+            pub fn abs(&self) -> Self {
+                Self(self.0.abs())
+            }
+
+            /// For statistics only; the result is no longer exact.
+            pub fn to_f64(&self) -> f64 {
+                self.0.to_f64().unwrap_or(f64::NAN)
+            }
+
+            /// Nearest multiple of `step`, halves away from zero. `step` of
+            /// zero leaves the value as it is.
+            pub fn round_to(&self, step: Self) -> Self {
+                self.multiple_of(step, RoundingStrategy::MidpointAwayFromZero)
+            }
+
+            /// Multiple of `step` towards zero, e.g. the quantity which can
+            /// actually be ordered.
+            pub fn trunc_to(&self, step: Self) -> Self {
+                self.multiple_of(step, RoundingStrategy::ToZero)
+            }
+
+            fn multiple_of(&self, step: Self, strategy: RoundingStrategy) -> Self {
+                if step.is_zero() {
+                    return *self;
+                }
+                let steps = (self.0 / step.0).round_dp_with_strategy(0, strategy);
+                Self((steps * step.0).normalize())
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = rust_decimal::Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                parse(s).map(Self)
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+
+            fn add(self, rhs: Self) -> Self {
+                Self(self.0 + rhs.0)
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+
+            fn sub(self, rhs: Self) -> Self {
+                Self(self.0 - rhs.0)
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, rhs: Self) {
+                self.0 += rhs.0;
+            }
+        }
+
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, rhs: Self) {
+                self.0 -= rhs.0;
+            }
+        }
+
+        impl Sum for $name {
+            fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+                iter.fold(Self::ZERO, Add::add)
+            }
+        }
+
+        /// As a string, so that no precision is lost on the way.
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(&self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserializer.deserialize_any(DecimalVisitor).map(Self)
+            }
+        }
+    };
+}
+
+decimal!(
+    /// Price of one unit, or of one contract.
+    Price
+);
+
+decimal!(
+    /// Amount of an asset, or number of contracts.
+    Qty
+);
+
+/// Price times quantity is the amount of the quote asset.
+impl Mul<Qty> for Price {
+    type Output = Qty;
+
+    fn mul(self, rhs: Qty) -> Qty {
+        Qty(self.0 * rhs.0)
+    }
+}
+
+impl Price {
+    /// Price of a single unit when `self` is quoted for `units` of them, e.g.
+    /// for `1000PEPEUSDT`.
+    pub fn per_unit(&self, units: u32) -> Price {
+        match units {
+            0 | 1 => *self,
+            units => Price(self.0 / Decimal::from(units)),
+        }
+    }
+}
+
+/// Without rounding; fails on digits a `Decimal` cannot hold.
+fn parse(s: &str) -> Result<Decimal, rust_decimal::Error> {
+    Decimal::from_str_exact(s).or_else(|_| Decimal::from_scientific(s))
+}
+
+/// Accepts both the strings exchanges usually send and plain JSON numbers.
+struct DecimalVisitor;
+
+impl<'de> de::Visitor<'de> for DecimalVisitor {
+    type Value = Decimal;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a decimal number or a string with one")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        parse(v).map_err(E::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(Decimal::from(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(Decimal::from(v))
+    }
+
+    /// The shortest string which reads back as `v`, e.g. 0.1 rather than
+    /// 0.1000000000000000055511151231257827
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        self.visit_str(&v.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_exchange_numbers_exactly() {
+        let price: Price = serde_json::from_str("\"0.01634790\"").unwrap();
+        assert_eq!(price, Price::new(163479, 7));
+        assert_eq!(price.scale(), 8);
+        assert_eq!(price.to_string(), "0.01634790");
+        assert_eq!(serde_json::to_string(&price).unwrap(), "\"0.01634790\"");
+
+        let qty: Qty = serde_json::from_str("0.1").unwrap();
+        assert_eq!(qty, Qty::new(1, 1));
+        let qty: Qty = serde_json::from_str("-1").unwrap();
+        assert_eq!(qty.abs(), Qty::new(1, 0));
+        assert!(serde_json::from_str::<Price>("\"1.2.3\"").is_err());
+        assert_eq!(Price::new(125, 4).per_unit(1000), Price::new(125, 7));
+    }
+
+    #[test]
+    fn equal_values_are_equal_whatever_the_scale() {
+        let a: Price = "100.10".parse().unwrap();
+        let b: Price = "100.1".parse().unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a.cmp(&b), std::cmp::Ordering::Equal);
+        assert_eq!(
+            "0.1".parse::<Qty>().unwrap() + "0.2".parse().unwrap(),
+            "0.3".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn rounds_to_steps() {
+        let tick = Price::new(5, 1);
+        assert_eq!(
+            "100.26".parse::<Price>().unwrap().round_to(tick),
+            Price::new(1005, 1)
+        );
+        assert_eq!(
+            "100.24".parse::<Price>().unwrap().round_to(tick),
+            Price::new(100, 0)
+        );
+        let lot = Qty::new(1, 3);
+        assert_eq!(
+            "0.12345".parse::<Qty>().unwrap().trunc_to(lot),
+            Qty::new(123, 3)
+        );
+        assert_eq!(Qty::new(7, 0).trunc_to(Qty::ZERO), Qty::new(7, 0));
+        assert_eq!(Price::new(2, 0) * Qty::new(15, 1), Qty::new(3, 0));
+    }
+}
@@ -0,0 +1,132 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::decimal::{Price, Qty};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Exchange {
+    Binance,
+    Bybit,
+    Kucoin,
+}
+
+impl Exchange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Exchange::Binance => "binance",
+            Exchange::Bybit => "bybit",
+            Exchange::Kucoin => "kucoin",
+        }
+    }
+}
+
+impl fmt::Display for Exchange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Exchange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "binance" => Ok(Exchange::Binance),
+            "bybit" => Ok(Exchange::Bybit),
+            "kucoin" => Ok(Exchange::Kucoin),
+            _ => Err(format!("unknown exchange {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarketType {
+    Spot,
+    /// Perpetual swap margined and settled in the quote asset
+    LinearPerpetual,
+    /// Perpetual swap margined and settled in the base asset
+    InversePerpetual,
+    DatedFuture,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstrumentStatus {
+    Trading,
+    /// Listed, but trading has not started yet
+    PreTrading,
+    Halted,
+    Settling,
+    Closed,
+}
+
+/// What identifies an instrument: the same ticker can be listed on several
+/// exchanges and on several markets of one exchange.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct InstrumentId {
+    pub exchange: Exchange,
+    pub market: MarketType,
+    pub ticker: String,
+}
+
+/// A tradable instrument as the exchange lists it, `ticker` being the
+/// exchange's own name for it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Instrument {
+    pub exchange: Exchange,
+    pub market: MarketType,
+    pub ticker: String,
+    pub base_asset: String,
+    pub quote_asset: String,
+    /// Minimal price change, `None` if the exchange does not tell
+    pub tick_size: Option<Price>,
+    /// Minimal quantity change, in contracts for derivatives
+    pub lot_size: Option<Qty>,
+    /// Contract size: in the base asset for linear contracts, in the quote
+    /// asset for inverse ones, 1 for spot
+    pub contract_multiplier: Qty,
+    pub status: InstrumentStatus,
+}
+
+impl Instrument {
+    pub fn id(&self) -> InstrumentId {
+        InstrumentId {
+            exchange: self.exchange,
+            market: self.market,
+            ticker: self.ticker.clone(),
+        }
+    }
+
+    pub fn is_trading(&self) -> bool {
+        self.status == InstrumentStatus::Trading
+    }
+
+    /// Nearest price the exchange accepts.
+    pub fn round_price(&self, price: Price) -> Price {
+        self.tick_size.map_or(price, |tick| price.round_to(tick))
+    }
+
+    /// Largest quantity up to `qty` the exchange accepts.
+    pub fn round_qty(&self, qty: Qty) -> Qty {
+        self.lot_size.map_or(qty, |lot| qty.trunc_to(lot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exchange_names_round_trip() {
+        for exchange in [Exchange::Binance, Exchange::Bybit, Exchange::Kucoin] {
+            assert_eq!(exchange.to_string().parse(), Ok(exchange));
+            let json = serde_json::to_string(&exchange).unwrap();
+            assert_eq!(json, format!("\"{exchange}\""));
+        }
+        assert_eq!("KuCoin".parse(), Ok(Exchange::Kucoin));
+        assert!("ftx".parse::<Exchange>().is_err());
+    }
+}
@@ -1,2 +1,6 @@
+pub mod clock;
+pub mod decimal;
+pub mod instrument;
+pub mod registry;
 pub mod time_unit;
-pub mod symbol;
+pub mod timestamp;
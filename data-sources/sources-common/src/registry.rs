@@ -0,0 +1,291 @@
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::instrument::{Exchange, Instrument, MarketType};
+
+/// Exchange independent name of an instrument, spelled `BASE/QUOTE:TYPE`,
+/// e.g. `BTC/USDT:PERP`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CanonicalSymbol {
+    pub base: String,
+    pub quote: String,
+    pub market: MarketType,
+}
+
+impl CanonicalSymbol {
+    pub fn new(base: &str, quote: &str, market: MarketType) -> Self {
+        Self {
+            base: base.to_uppercase(),
+            quote: quote.to_uppercase(),
+            market,
+        }
+    }
+}
+
+fn market_code(market: MarketType) -> &'static str {
+    match market {
+        MarketType::Spot => "SPOT",
+        MarketType::LinearPerpetual => "PERP",
+        MarketType::InversePerpetual => "INVERSE",
+        MarketType::DatedFuture => "FUTURE",
+    }
+}
+
+impl fmt::Display for CanonicalSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let market = market_code(self.market);
+        write!(f, "{}/{}:{market}", self.base, self.quote)
+    }
+}
+
+impl FromStr for CanonicalSymbol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{s} is not BASE/QUOTE:TYPE");
+        let (pair, market) = s.split_once(':').ok_or_else(invalid)?;
+        let (base, quote) = pair.split_once('/').ok_or_else(invalid)?;
+        let market = [
+            MarketType::Spot,
+            MarketType::LinearPerpetual,
+            MarketType::InversePerpetual,
+            MarketType::DatedFuture,
+        ]
+        .into_iter()
+        .find(|candidate| market_code(*candidate) == market.to_uppercase())
+        .ok_or_else(invalid)?;
+        if base.is_empty() || quote.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self::new(base, quote, market))
+    }
+}
+
+impl Serialize for CanonicalSymbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for CanonicalSymbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// One exchange's ticker for a canonical symbol.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Listing {
+    pub exchange: Exchange,
+    pub ticker: String,
+    pub symbol: CanonicalSymbol,
+    /// Base units the quoted price is for, e.g. 1000 for `1000PEPEUSDT`
+    pub units: u32,
+}
+
+/// Maps exchange tickers to canonical symbols and back, filled from the
+/// exchanges' symbol listings.
+///
+/// Dated futures are left out, as nothing but the expiry, which instruments
+/// do not carry, tells them apart.
+#[derive(Debug, Clone)]
+pub struct SymbolRegistry {
+    aliases: HashMap<String, String>,
+    by_ticker: HashMap<(Exchange, String), Listing>,
+    by_symbol: HashMap<CanonicalSymbol, Vec<(Exchange, String)>>,
+}
+
+impl Default for SymbolRegistry {
+    fn default() -> Self {
+        Self {
+            aliases: HashMap::from([("XBT".to_string(), "BTC".to_string())]),
+            by_ticker: HashMap::new(),
+            by_symbol: HashMap::new(),
+        }
+    }
+}
+
+impl SymbolRegistry {
+    /// Knows `XBT` as `BTC`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spells `alias` as `asset` in canonical symbols. Only applies to
+    /// instruments added afterwards.
+    pub fn with_alias(mut self, alias: &str, asset: &str) -> Self {
+        self.aliases
+            .insert(alias.to_uppercase(), asset.to_uppercase());
+        self
+    }
+
+    /// `None` if the instrument is a dated future, which is not registered.
+    /// An instrument with a ticker the exchange already has replaces it.
+    pub fn insert(&mut self, instrument: &Instrument) -> Option<&Listing> {
+        if instrument.market == MarketType::DatedFuture {
+            return None;
+        }
+        let (base, units) = self.asset(&instrument.base_asset);
+        let (quote, _) = self.asset(&instrument.quote_asset);
+        let listing = Listing {
+            exchange: instrument.exchange,
+            ticker: instrument.ticker.clone(),
+            symbol: CanonicalSymbol::new(&base, &quote, instrument.market),
+            units,
+        };
+        let key = (instrument.exchange, instrument.ticker.clone());
+        if let Some(replaced) = self.by_ticker.remove(&key) {
+            if let Some(tickers) = self.by_symbol.get_mut(&replaced.symbol) {
+                tickers.retain(|ticker| *ticker != key);
+            }
+        }
+        self.by_symbol
+            .entry(listing.symbol.clone())
+            .or_default()
+            .push(key.clone());
+        Some(self.by_ticker.entry(key).or_insert(listing))
+    }
+
+    pub fn extend<'i>(&mut self, instruments: impl IntoIterator<Item = &'i Instrument>) {
+        for instrument in instruments {
+            self.insert(instrument);
+        }
+    }
+
+    pub fn canonical(&self, exchange: Exchange, ticker: &str) -> Option<&Listing> {
+        self.by_ticker.get(&(exchange, ticker.to_string()))
+    }
+
+    /// The exchange's ticker for `symbol`; the first one registered if the
+    /// exchange lists several, e.g. `PEPEUSDT` and `1000PEPEUSDT`.
+    pub fn listing(&self, exchange: Exchange, symbol: &CanonicalSymbol) -> Option<&Listing> {
+        self.listings(symbol)
+            .find(|listing| listing.exchange == exchange)
+    }
+
+    /// Every exchange's tickers for `symbol`, in the order they were added.
+    pub fn listings<'r>(
+        &'r self,
+        symbol: &CanonicalSymbol,
+    ) -> impl Iterator<Item = &'r Listing> + 'r {
+        self.by_symbol
+            .get(symbol)
+            .into_iter()
+            .flatten()
+            .filter_map(|key| self.by_ticker.get(key))
+    }
+
+    /// Canonical spelling of `asset` and the units a `1000PEPE`-like
+    /// prefix stands for.
+    fn asset(&self, asset: &str) -> (String, u32) {
+        let asset = asset.to_uppercase();
+        let digits = asset.len() - asset.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let (prefix, name) = asset.split_at(digits);
+        // 1000, 10000 and so on
+        let multiplier = prefix.len() >= 4
+            && prefix.starts_with('1')
+            && prefix[1..].bytes().all(|digit| digit == b'0');
+        let units = prefix
+            .parse::<u32>()
+            .ok()
+            .filter(|_| multiplier && !name.is_empty());
+        let (name, units) = match units {
+            Some(units) => (name, units),
+            // e.g. 1INCH
+            None => (asset.as_str(), 1),
+        };
+        let name = self.aliases.get(name).map_or(name, String::as_str);
+        (name.to_string(), units)
+    }
+}
+
+impl<'i> FromIterator<&'i Instrument> for SymbolRegistry {
+    fn from_iter<I: IntoIterator<Item = &'i Instrument>>(instruments: I) -> Self {
+        let mut registry = Self::new();
+        registry.extend(instruments);
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decimal::Qty, instrument::InstrumentStatus};
+
+    fn instrument(exchange: Exchange, ticker: &str, base: &str, quote: &str) -> Instrument {
+        Instrument {
+            exchange,
+            market: MarketType::LinearPerpetual,
+            ticker: ticker.to_string(),
+            base_asset: base.to_string(),
+            quote_asset: quote.to_string(),
+            tick_size: None,
+            lot_size: None,
+            contract_multiplier: Qty::new(1, 0),
+            status: InstrumentStatus::Trading,
+        }
+    }
+
+    #[test]
+    fn same_asset_on_every_exchange_gets_one_symbol() {
+        let instruments = [
+            instrument(Exchange::Binance, "BTCUSDT", "BTC", "USDT"),
+            instrument(Exchange::Bybit, "BTCUSDT", "BTC", "USDT"),
+            instrument(Exchange::Kucoin, "XBTUSDTM", "XBT", "USDT"),
+            instrument(Exchange::Binance, "1000PEPEUSDT", "1000PEPE", "USDT"),
+            instrument(Exchange::Kucoin, "1INCHUSDTM", "1INCH", "USDT"),
+        ];
+        let registry: SymbolRegistry = instruments.iter().collect();
+
+        let btc: CanonicalSymbol = "BTC/USDT:PERP".parse().unwrap();
+        let kucoin = registry.canonical(Exchange::Kucoin, "XBTUSDTM").unwrap();
+        assert_eq!(kucoin.symbol, btc);
+        assert_eq!(
+            registry.listing(Exchange::Kucoin, &btc).unwrap().ticker,
+            "XBTUSDTM"
+        );
+        let exchanges: Vec<_> = registry.listings(&btc).map(|l| l.exchange).collect();
+        assert_eq!(
+            exchanges,
+            vec![Exchange::Binance, Exchange::Bybit, Exchange::Kucoin]
+        );
+
+        let pepe = registry
+            .canonical(Exchange::Binance, "1000PEPEUSDT")
+            .unwrap();
+        assert_eq!(pepe.symbol.to_string(), "PEPE/USDT:PERP");
+        assert_eq!(pepe.units, 1000);
+        let inch = registry.canonical(Exchange::Kucoin, "1INCHUSDTM").unwrap();
+        assert_eq!(inch.symbol.to_string(), "1INCH/USDT:PERP");
+        assert_eq!(inch.units, 1);
+    }
+
+    #[test]
+    fn relisted_ticker_replaces_the_old_listing() {
+        let mut registry = SymbolRegistry::new().with_alias("WETH", "ETH");
+        registry.insert(&instrument(Exchange::Bybit, "ETHUSDT", "WETH", "USDT"));
+        registry.insert(&instrument(Exchange::Bybit, "ETHUSDT", "ETH", "USDC"));
+
+        let old = "ETH/USDT:PERP".parse().unwrap();
+        assert_eq!(registry.listings(&old).count(), 0);
+        let new = "ETH/USDC:PERP".parse().unwrap();
+        assert_eq!(
+            registry.listing(Exchange::Bybit, &new).unwrap().ticker,
+            "ETHUSDT"
+        );
+    }
+
+    #[test]
+    fn parses_canonical_symbols() {
+        let symbol: CanonicalSymbol = "btc/usd:inverse".parse().unwrap();
+        assert_eq!(
+            symbol,
+            CanonicalSymbol::new("BTC", "USD", MarketType::InversePerpetual)
+        );
+        assert_eq!(symbol.to_string(), "BTC/USD:INVERSE");
+        assert!("BTCUSDT".parse::<CanonicalSymbol>().is_err());
+        assert!("BTC/USDT:SWAP".parse::<CanonicalSymbol>().is_err());
+    }
+}
@@ -1,7 +0,0 @@
-#[derive(Debug, Clone, PartialEq, Hash, Eq)]
-pub struct Symbol {
-    pub ticker: String,
-    pub base_asset: String,
-    pub quote_asset: String,
-    pub source: String,
-}
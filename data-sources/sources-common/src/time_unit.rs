@@ -1,9 +1,28 @@
-use std::{borrow::Cow, str::FromStr, time::Duration};
+use std::{borrow::Cow, fmt, str::FromStr, time::Duration, time::SystemTime};
 
-use serde::{de, Deserialize, Deserializer, Serializer};
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
-pub struct TimeUnit(#[serde(deserialize_with = "deser_time_unit_u32_from_string")] u32);
+use crate::instrument::Exchange;
+
+/// Candle interval: any number of seconds, minutes, hours or days, counted
+/// from the unix epoch, or of weeks starting on Monday and calendar months,
+/// both in UTC.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct TimeUnit {
+    count: u32,
+    unit: Unit,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Unit {
+    Second,
+    Minute,
+    Hour,
+    Day,
+    Week,
+    Month,
+}
 
 pub static SECOND: u32 = 1;
 pub static MINUTE: u32 = 60 * SECOND;
@@ -11,105 +30,348 @@ pub static HOUR: u32 = 60 * MINUTE;
 pub static DAY: u32 = 24 * HOUR;
 pub static WEEK: u32 = 7 * DAY;
 
+/// 1970-01-05, the first Monday after the epoch
+const FIRST_MONDAY: i64 = 4 * DAY as i64;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{exchange} has no {time_unit} interval")]
+pub struct UnsupportedInterval {
+    pub exchange: Exchange,
+    pub time_unit: TimeUnit,
+}
+
 impl TimeUnit {
-    pub fn secs(v: u32) -> Self {
-        TimeUnit(v)
+    /// `count` is at least 1.
+    pub fn new(count: u32, unit: Unit) -> Self {
+        TimeUnit {
+            count: count.max(1),
+            unit,
+        }
     }
 
-    pub fn calc_n(&self, v: u32) -> Duration {
-        let secs = (self.0 as u64) * (v as u64);
-        Duration::from_secs(secs)
+    pub fn secs(v: u32) -> Self {
+        Self::new(v, Unit::Second)
     }
 
     pub fn mins(v: u32) -> Self {
-        TimeUnit(v * 60)
+        Self::new(v, Unit::Minute)
     }
 
     pub fn hours(v: u32) -> Self {
-        TimeUnit(v * 60 * 60)
+        Self::new(v, Unit::Hour)
     }
 
     pub fn days(v: u32) -> Self {
-        TimeUnit(v * 60 * 60 * 24)
+        Self::new(v, Unit::Day)
     }
 
     pub fn weeks(v: u32) -> Self {
-        TimeUnit(v * 60 * 60 * 24 * 7)
+        Self::new(v, Unit::Week)
     }
 
     pub fn months(v: u32) -> Self {
-        TimeUnit(v * 60 * 60 * 24 * 7 * 4) // maybe
+        Self::new(v, Unit::Month)
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn unit(&self) -> Unit {
+        self.unit
+    }
+
+    /// Length of one interval, `None` for months, which differ in length.
+    pub fn duration(&self) -> Option<Duration> {
+        let unit = match self.unit {
+            Unit::Second => SECOND,
+            Unit::Minute => MINUTE,
+            Unit::Hour => HOUR,
+            Unit::Day => DAY,
+            Unit::Week => WEEK,
+            Unit::Month => return None,
+        };
+        Some(Duration::from_secs(unit as u64 * self.count as u64))
+    }
+
+    /// Length of `v` intervals, taking a month as 30 days.
+    pub fn calc_n(&self, v: u32) -> Duration {
+        let one = self
+            .duration()
+            .unwrap_or(Duration::from_secs(30 * DAY as u64 * self.count as u64));
+        one * v
+    }
+
+    /// Start of the interval `time` falls into.
+    pub fn floor(&self, time: SystemTime) -> SystemTime {
+        let time = DateTime::<Utc>::from(time);
+        match self.unit {
+            Unit::Month => {
+                let months = month_index(&time);
+                month_start(months - months.rem_euclid(self.count as i64))
+            }
+            Unit::Week => {
+                let secs = time.timestamp() - FIRST_MONDAY;
+                let length = self.count as i64 * WEEK as i64;
+                at(secs - secs.rem_euclid(length) + FIRST_MONDAY)
+            }
+            _ => {
+                let secs = time.timestamp();
+                let length = self.duration().unwrap().as_secs() as i64;
+                at(secs - secs.rem_euclid(length))
+            }
+        }
+    }
+
+    /// `time` itself when it is a boundary, the next boundary otherwise.
+    pub fn ceil(&self, time: SystemTime) -> SystemTime {
+        let floor = self.floor(time);
+        if floor == time {
+            time
+        } else {
+            self.after(floor)
+        }
+    }
+
+    /// The first boundary strictly after `time`.
+    pub fn next_boundary(&self, time: SystemTime) -> SystemTime {
+        self.after(self.floor(time))
+    }
+
+    fn after(&self, boundary: SystemTime) -> SystemTime {
+        match self.unit {
+            Unit::Month => {
+                let months = month_index(&DateTime::<Utc>::from(boundary));
+                month_start(months + self.count as i64)
+            }
+            _ => boundary + self.duration().unwrap(),
+        }
     }
 
     pub fn fmt(&self) -> String {
-        match self.0 {
-            v if SECOND == v => "1s".to_string(),
-            v if 5 * SECOND == v => "5s".to_string(),
-            v if MINUTE == v => "1m".to_string(),
-            v if 3 * MINUTE == v => "3m".to_string(),
-            v if 5 * MINUTE == v => "5m".to_string(),
-            v if 15 * MINUTE == v => "15m".to_string(),
-            v if 30 * MINUTE == v => "30m".to_string(),
-            v if 1 * HOUR == v => "1h".to_string(),
-            v if 2 * HOUR == v => "2h".to_string(),
-            v if 4 * HOUR == v => "4h".to_string(),
-            v if 6 * HOUR == v => "6h".to_string(),
-            v if 8 * HOUR == v => "8h".to_string(),
-            v if 12 * HOUR == v => "12h".to_string(),
-            v if 1 * DAY == v => "1d".to_string(),
-            v if 3 * DAY == v => "3d".to_string(),
-            v if 1 * WEEK == v => "1w".to_string(),
-            v if 4 * WEEK == v => "1M".to_string(),
-            _ => unreachable!("incorrect time_unit: {}", self.0),
+        self.to_string()
+    }
+
+    /// How `exchange` spells this interval.
+    pub fn to_exchange(&self, exchange: Exchange) -> Result<String, UnsupportedInterval> {
+        match exchange {
+            Exchange::Binance => self.to_binance(),
+            Exchange::Bybit => self.to_bybit(),
+            Exchange::Kucoin => self.to_kucoin(),
+        }
+    }
+
+    /// Binance kline interval, e.g. `15m` or `1M`.
+    pub fn to_binance(&self) -> Result<String, UnsupportedInterval> {
+        let supported = match self.unit {
+            Unit::Second => [1].as_slice(),
+            Unit::Minute => &[1, 3, 5, 15, 30],
+            Unit::Hour => &[1, 2, 4, 6, 8, 12],
+            Unit::Day => &[1, 3],
+            Unit::Week | Unit::Month => &[1],
+        };
+        self.supported(Exchange::Binance, supported.contains(&self.count), || {
+            self.to_string()
+        })
+    }
+
+    /// Bybit kline interval: minutes, or `D`, `W` and `M`.
+    pub fn to_bybit(&self) -> Result<String, UnsupportedInterval> {
+        let minutes = self.minutes();
+        let minute_interval =
+            minutes.filter(|minutes| [1, 3, 5, 15, 30, 60, 120, 240, 360, 720].contains(minutes));
+        let calendar = match (self.unit, self.count) {
+            (Unit::Day, 1) => Some("D"),
+            (Unit::Week, 1) => Some("W"),
+            (Unit::Month, 1) => Some("M"),
+            _ => None,
+        };
+        let supported = minute_interval.is_some() || calendar.is_some();
+        self.supported(Exchange::Bybit, supported, || match calendar {
+            Some(calendar) => calendar.to_string(),
+            None => minutes.unwrap_or_default().to_string(),
+        })
+    }
+
+    /// KuCoin futures kline granularity, in minutes.
+    pub fn to_kucoin(&self) -> Result<String, UnsupportedInterval> {
+        let minutes = self.minutes().filter(|minutes| {
+            [1, 5, 15, 30, 60, 120, 240, 480, 720, 1440, 10080].contains(minutes)
+        });
+        self.supported(Exchange::Kucoin, minutes.is_some(), || {
+            minutes.unwrap_or_default().to_string()
+        })
+    }
+
+    fn minutes(&self) -> Option<u64> {
+        let secs = self.duration()?.as_secs();
+        (secs % MINUTE as u64 == 0).then_some(secs / MINUTE as u64)
+    }
+
+    fn supported(
+        &self,
+        exchange: Exchange,
+        supported: bool,
+        interval: impl FnOnce() -> String,
+    ) -> Result<String, UnsupportedInterval> {
+        if supported {
+            Ok(interval())
+        } else {
+            Err(UnsupportedInterval {
+                exchange,
+                time_unit: self.clone(),
+            })
         }
     }
 }
 
+fn month_index(time: &DateTime<Utc>) -> i64 {
+    (time.year() as i64 - 1970) * 12 + time.month0() as i64
+}
+
+fn month_start(month_index: i64) -> SystemTime {
+    let year = 1970 + month_index.div_euclid(12);
+    let month = month_index.rem_euclid(12) as u32 + 1;
+    Utc.with_ymd_and_hms(year as i32, month, 1, 0, 0, 0)
+        .unwrap()
+        .into()
+}
+
+fn at(secs: i64) -> SystemTime {
+    Utc.timestamp_opt(secs, 0).unwrap().into()
+}
+
+impl fmt::Display for TimeUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.unit {
+            Unit::Second => "s",
+            Unit::Minute => "m",
+            Unit::Hour => "h",
+            Unit::Day => "d",
+            Unit::Week => "w",
+            Unit::Month => "M",
+        };
+        write!(f, "{}{unit}", self.count)
+    }
+}
+
 impl FromStr for TimeUnit {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "1s" => Ok(TimeUnit::secs(1)),
-            "1m" => Ok(TimeUnit::mins(1)),
-            "3m" => Ok(TimeUnit::mins(3)),
-            "5m" => Ok(TimeUnit::mins(5)),
-            "15m" => Ok(TimeUnit::mins(15)),
-            "30m" => Ok(TimeUnit::mins(30)),
-            "1h" => Ok(TimeUnit::hours(1)),
-            "2h" => Ok(TimeUnit::hours(2)),
-            "4h" => Ok(TimeUnit::hours(4)),
-            "6h" => Ok(TimeUnit::hours(6)),
-            "8h" => Ok(TimeUnit::hours(8)),
-            "12h" => Ok(TimeUnit::hours(12)),
-            "1d" => Ok(TimeUnit::days(1)),
-            "3d" => Ok(TimeUnit::days(3)),
-            "1w" => Ok(TimeUnit::weeks(1)),
-            "1M" => Ok(TimeUnit::months(1)),
-            _ => Err(format!("Incorrect time_unit: {s}")),
+        let error = || format!("Incorrect time_unit: {s}");
+        let split = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(error)?;
+        let (count, unit) = s.split_at(split);
+        let count: u32 = count.parse().map_err(|_| error())?;
+        let unit = match unit {
+            "s" => Unit::Second,
+            "m" => Unit::Minute,
+            "h" => Unit::Hour,
+            "d" => Unit::Day,
+            "w" => Unit::Week,
+            "M" => Unit::Month,
+            _ => return Err(error()),
+        };
+        if count == 0 {
+            return Err(error());
         }
+        Ok(TimeUnit::new(count, unit))
     }
 }
 
-pub fn ser_time_unit<S: Serializer>(
-    time_unit: &TimeUnit,
-    serializer: S,
-) -> Result<S::Ok, S::Error> {
-    let value = time_unit.fmt();
-    serializer.serialize_str(&value)
+impl Serialize for TimeUnit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
 }
 
-pub fn deser_time_unit_from_string<'de, D: Deserializer<'de>>(
-    deserializer: D,
-) -> Result<TimeUnit, D::Error> {
-    let string_value = Cow::<str>::deserialize(deserializer)?;
-    TimeUnit::from_str(&string_value).map_err(de::Error::custom)
+impl<'de> Deserialize<'de> for TimeUnit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let string_value = Cow::<str>::deserialize(deserializer)?;
+        TimeUnit::from_str(&string_value).map_err(de::Error::custom)
+    }
 }
 
-pub fn deser_time_unit_u32_from_string<'de, D: Deserializer<'de>>(
-    deserializer: D,
-) -> Result<u32, D::Error> {
-    let string_value = Cow::<str>::deserialize(deserializer)?;
-    TimeUnit::from_str(&string_value)
-        .map_err(de::Error::custom)
-        .map(|tu| tu.0)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32, s: u32) -> SystemTime {
+        Utc.with_ymd_and_hms(y, m, d, h, min, s).unwrap().into()
+    }
+
+    #[test]
+    fn parses_and_formats_any_interval() {
+        for interval in [
+            "1s", "5s", "7m", "90m", "1h", "36h", "1d", "3d", "2w", "1M", "3M",
+        ] {
+            let time_unit: TimeUnit = interval.parse().unwrap();
+            assert_eq!(time_unit.to_string(), interval);
+            assert_eq!(
+                serde_json::to_string(&time_unit).unwrap(),
+                format!("\"{interval}\"")
+            );
+        }
+        for interval in ["", "m", "0m", "1y", "-1m", "1.5h"] {
+            assert!(interval.parse::<TimeUnit>().is_err(), "{interval}");
+        }
+        let time_unit: TimeUnit = serde_json::from_str("\"15m\"").unwrap();
+        assert_eq!(time_unit, TimeUnit::mins(15));
+    }
+
+    #[test]
+    fn aligns_to_fixed_intervals() {
+        let time = utc(2023, 3, 15, 13, 47, 12);
+        let quarter = TimeUnit::mins(15);
+        assert_eq!(quarter.floor(time), utc(2023, 3, 15, 13, 45, 0));
+        assert_eq!(quarter.ceil(time), utc(2023, 3, 15, 14, 0, 0));
+        assert_eq!(quarter.next_boundary(time), utc(2023, 3, 15, 14, 0, 0));
+
+        let boundary = utc(2023, 3, 15, 12, 0, 0);
+        let four_hours = TimeUnit::hours(4);
+        assert_eq!(four_hours.floor(boundary), boundary);
+        assert_eq!(four_hours.ceil(boundary), boundary);
+        assert_eq!(
+            four_hours.next_boundary(boundary),
+            utc(2023, 3, 15, 16, 0, 0)
+        );
+    }
+
+    #[test]
+    fn aligns_to_weeks_and_calendar_months() {
+        // A Wednesday
+        let time = utc(2023, 3, 15, 13, 47, 12);
+        assert_eq!(TimeUnit::weeks(1).floor(time), utc(2023, 3, 13, 0, 0, 0));
+        assert_eq!(
+            TimeUnit::weeks(1).next_boundary(time),
+            utc(2023, 3, 20, 0, 0, 0)
+        );
+
+        assert_eq!(TimeUnit::months(1).floor(time), utc(2023, 3, 1, 0, 0, 0));
+        assert_eq!(TimeUnit::months(1).ceil(time), utc(2023, 4, 1, 0, 0, 0));
+        assert_eq!(TimeUnit::months(3).floor(time), utc(2023, 1, 1, 0, 0, 0));
+        assert_eq!(
+            TimeUnit::months(1).next_boundary(utc(2023, 12, 31, 23, 0, 0)),
+            utc(2024, 1, 1, 0, 0, 0)
+        );
+        assert_eq!(TimeUnit::months(1).duration(), None);
+    }
+
+    #[test]
+    fn maps_to_exchange_intervals() {
+        assert_eq!(TimeUnit::hours(4).to_binance().unwrap(), "4h");
+        assert_eq!(TimeUnit::hours(4).to_bybit().unwrap(), "240");
+        assert_eq!(TimeUnit::hours(4).to_kucoin().unwrap(), "240");
+        assert_eq!(TimeUnit::days(1).to_bybit().unwrap(), "D");
+        assert_eq!(TimeUnit::weeks(1).to_kucoin().unwrap(), "10080");
+        assert_eq!(TimeUnit::months(1).to_binance().unwrap(), "1M");
+
+        let error = TimeUnit::mins(7).to_binance().unwrap_err();
+        assert_eq!(error.to_string(), "binance has no 7m interval");
+        assert!(TimeUnit::secs(1).to_bybit().is_err());
+        assert!(TimeUnit::months(1).to_kucoin().is_err());
+        assert_eq!(
+            TimeUnit::hours(4).to_exchange(Exchange::Kucoin),
+            TimeUnit::hours(4).to_kucoin()
+        );
+    }
 }
@@ -0,0 +1,211 @@
+use std::{
+    fmt,
+    ops::{Add, Sub},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use toolset::epoch::EpochTime;
+
+/// Point in time in UTC, with microsecond precision. Arithmetic saturates
+/// instead of panicking, so that a timestamp from an exchange whose clock
+/// runs ahead of ours is merely "0s ago".
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    pub const UNIX_EPOCH: Self = Self(0);
+
+    pub fn now() -> Self {
+        SystemTime::now().into()
+    }
+
+    pub fn from_millis(millis: i64) -> Self {
+        Self(millis.saturating_mul(1000))
+    }
+
+    pub fn from_micros(micros: i64) -> Self {
+        Self(micros)
+    }
+
+    pub fn as_millis(&self) -> i64 {
+        self.0.div_euclid(1000)
+    }
+
+    pub fn as_micros(&self) -> i64 {
+        self.0
+    }
+
+    /// Time from `earlier` to `self`, zero if `earlier` is in fact later.
+    pub fn duration_since(&self, earlier: Timestamp) -> Duration {
+        let micros = self.0.saturating_sub(earlier.0).max(0);
+        Duration::from_micros(micros as u64)
+    }
+
+    pub fn to_chrono(&self) -> DateTime<Utc> {
+        let secs = self.0.div_euclid(1_000_000);
+        let nanos = self.0.rem_euclid(1_000_000) as u32 * 1000;
+        let bound = if self.0 < 0 {
+            DateTime::<Utc>::MIN_UTC
+        } else {
+            DateTime::<Utc>::MAX_UTC
+        };
+        Utc.timestamp_opt(secs, nanos).single().unwrap_or(bound)
+    }
+}
+
+fn duration_micros(duration: Duration) -> i64 {
+    duration.as_micros().try_into().unwrap_or(i64::MAX)
+}
+
+impl Add<Duration> for Timestamp {
+    type Output = Self;
+
+    fn add(self, rhs: Duration) -> Self {
+        Self(self.0.saturating_add(duration_micros(rhs)))
+    }
+}
+
+impl Sub<Duration> for Timestamp {
+    type Output = Self;
+
+    fn sub(self, rhs: Duration) -> Self {
+        Self(self.0.saturating_sub(duration_micros(rhs)))
+    }
+}
+
+/// Same as `duration_since`.
+impl Sub for Timestamp {
+    type Output = Duration;
+
+    fn sub(self, rhs: Self) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => Self::UNIX_EPOCH + after,
+            Err(before) => Self::UNIX_EPOCH - before.duration(),
+        }
+    }
+}
+
+impl From<Timestamp> for SystemTime {
+    fn from(timestamp: Timestamp) -> Self {
+        let since_epoch = Duration::from_micros(timestamp.0.unsigned_abs());
+        if timestamp.0 < 0 {
+            UNIX_EPOCH - since_epoch
+        } else {
+            UNIX_EPOCH + since_epoch
+        }
+    }
+}
+
+impl From<DateTime<Utc>> for Timestamp {
+    fn from(time: DateTime<Utc>) -> Self {
+        Self(time.timestamp_micros())
+    }
+}
+
+impl From<Timestamp> for DateTime<Utc> {
+    fn from(timestamp: Timestamp) -> Self {
+        timestamp.to_chrono()
+    }
+}
+
+/// RFC 3339, e.g. `2017-07-03T00:00:00.123Z`.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = self
+            .to_chrono()
+            .to_rfc3339_opts(SecondsFormat::AutoSi, true);
+        f.write_str(&time)
+    }
+}
+
+/// Microseconds since the epoch.
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        micros::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        micros::deserialize(deserializer)
+    }
+}
+
+/// `#[serde(with = "sources_common::timestamp::millis")]` for milliseconds
+/// since the epoch, likewise `micros` and `nanos`.
+pub use toolset::epoch::{micros, millis, nanos};
+
+/// Nanoseconds are truncated to whole microseconds.
+impl EpochTime for Timestamp {
+    fn from_epoch_nanos(nanos: i128) -> Self {
+        let micros = nanos.div_euclid(1000);
+        Self(micros.clamp(i64::MIN.into(), i64::MAX.into()) as i64)
+    }
+
+    fn epoch_nanos(&self) -> i128 {
+        i128::from(self.0) * 1000
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Event {
+        #[serde(with = "millis")]
+        ms: Timestamp,
+        #[serde(with = "micros")]
+        us: Timestamp,
+    }
+
+    #[test]
+    fn parses_millis_and_micros() {
+        let event: Event =
+            serde_json::from_str(r#"{"ms": 1499040000123, "us": "1499040000123456"}"#).unwrap();
+        assert_eq!(event.ms.as_micros(), 1499040000123000);
+        assert_eq!(event.us.as_millis(), 1499040000123);
+        assert_eq!(event.ms.to_string(), "2017-07-03T00:00:00.123Z");
+        assert_eq!(event.us.to_string(), "2017-07-03T00:00:00.123456Z");
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"ms":1499040000123,"us":1499040000123456}"#
+        );
+        assert!(serde_json::from_str::<Event>(r#"{"ms": "soon", "us": 0}"#).is_err());
+    }
+
+    #[test]
+    fn arithmetic_saturates() {
+        let now = Timestamp::from_millis(1_000);
+        let later = now + Duration::from_millis(250);
+        assert_eq!(later - now, Duration::from_millis(250));
+        // A trade stamped ahead of our clock
+        assert_eq!(now - later, Duration::ZERO);
+        assert_eq!(
+            Timestamp::from_micros(i64::MAX) + Duration::from_secs(1),
+            Timestamp::from_micros(i64::MAX)
+        );
+        assert_eq!(now - Duration::from_secs(2), Timestamp::from_millis(-1_000));
+    }
+
+    #[test]
+    fn converts_to_system_time_and_chrono() {
+        let time = UNIX_EPOCH + Duration::from_micros(1_499_040_000_123_456);
+        let timestamp = Timestamp::from(time);
+        assert_eq!(SystemTime::from(timestamp), time);
+        let chrono = timestamp.to_chrono();
+        assert_eq!(Timestamp::from(chrono), timestamp);
+        assert_eq!(
+            SystemTime::from(Timestamp::from_millis(-1)),
+            UNIX_EPOCH - Duration::from_millis(1)
+        );
+    }
+}
@@ -6,31 +6,34 @@ use url::Url;
 
 use super::AggregateOptions;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct CandleSettings {
     pub(super) time_unit: TimeUnit,
     #[serde(default = "default_candles_amount")]
     pub(super) amount: usize,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct OrderbookSettings {
     #[serde(default = "default_orderbook_depth")]
     pub(super) depth: u32,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct TradesSettings {
     #[serde(default = "default_trades_duration", with = "humantime_serde")]
     pub(super) window: Duration,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct PriceFeedConfig {
     #[serde(deserialize_with = "deserialize_url")]
     pub(super) api_host: Url,
     #[serde(deserialize_with = "deserialize_url")]
     pub(super) ws_host: Url,
+    /// Routes REST requests through a proxy, e.g. `http://proxy:3128`
+    #[serde(default, deserialize_with = "deserialize_optional_url")]
+    pub(super) proxy: Option<Url>,
 
     #[serde(default = "api_key_from_env")]
     pub(super) api_key: String,
@@ -42,6 +45,16 @@ pub struct PriceFeedConfig {
     pub(super) aggregate_options: AggregateOptions,
 }
 
+impl PriceFeedConfig {
+    /// The same feed for another ticker
+    pub fn with_ticker(self, ticker: impl Into<String>) -> Self {
+        Self {
+            ticker: ticker.into(),
+            ..self
+        }
+    }
+}
+
 fn api_key_from_env() -> String {
     std::env::var("BINANCE_API_KEY")
         .expect("Provide api key via config or via env var 'BINANCE_API_KEY'")
@@ -63,3 +76,7 @@ fn deserialize_url<'de, D: Deserializer<'de>>(deser: D) -> Result<Url, D::Error>
     let s = Cow::<str>::deserialize(deser)?;
     s.as_ref().parse().map_err(de::Error::custom)
 }
+
+fn deserialize_optional_url<'de, D: Deserializer<'de>>(deser: D) -> Result<Option<Url>, D::Error> {
+    deserialize_url(deser).map(Some)
+}
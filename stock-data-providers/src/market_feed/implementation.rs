@@ -1,14 +1,16 @@
-use app::{mpsc, BoxFuture, FutureExt, Sink, SinkExt, Stream, StreamExt};
+use app::{mpsc, worker::WorkerError, FutureExt, Sink, SinkExt, Stream, StreamExt};
 use market_feed::{
     candle::Candle,
     candles::Candles,
     create_market_feed, fetch_candles, fetch_historical_trades, fetch_orderbook,
     order_book::OrderBook,
-    trade::{Trade, Trades, TradesAggregate, AggregateOptions},
+    trade::{Trade, TradesAggregate, AggregateOptions},
     FetchCandlesInput, FetchHistoricalTradesInput, FetchOrderbookInput, MarketFeedInput,
     MarketFeedMessage, MarketFeedSettings,
 };
-use tracing::{error, info};
+use exchange_http::HttpClient;
+use sources_common::clock::{SharedClock, SystemClock};
+use tracing::{error, info, warn};
 
 use super::{config::PriceFeedConfig, PriceFeed};
 
@@ -21,209 +23,358 @@ impl PriceFeed {
             trades,
             api_host,
             ws_host,
+            proxy,
             ticker,
             aggregate_options,
         } = config;
 
+        let mut client = HttpClient::builder(api_host);
+        if let Some(proxy) = proxy {
+            client = client.proxy(proxy);
+        }
 
         Self {
             api_key,
             candles,
-            api_host,
+            client: client.build().expect("Invalid proxy for the price feed"),
             ws_host,
             ticker,
             orderbook,
             trades,
             aggregate_options,
+            clock: SystemClock::shared(),
         }
     }
 
+    /// Replaces the wall clock, e.g. with event time when replaying.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub async fn run_feed(
         self,
         candles_sink: impl Sink<Candles> + Sync + Send + Unpin,
         orderbook_sink: impl Sink<OrderBook> + Sync + Send + Unpin,
         trades_aggregate_sink: impl Sink<TradesAggregate> + Sync + Send + Unpin,
-    ) {
+    ) -> Result<(), WorkerError> {
         info!(?self, "Init stream");
         let candles = self
             .candles
             .as_ref()
             .map(|c| MarketFeedSettings::Candle(c.time_unit.clone()));
-        let trades = self.trades.as_ref().map(|c| MarketFeedSettings::Trades);
+        let trades = self.trades.as_ref().map(|_| MarketFeedSettings::Trades);
         let orderbook = self
             .orderbook
             .as_ref()
-            .map(|c| MarketFeedSettings::OrderBook);
+            .map(|_| MarketFeedSettings::OrderBook);
 
         let stream = create_market_feed(MarketFeedInput {
             ticker: self.ticker.clone(),
             settings: vec![candles, trades, orderbook]
                 .into_iter()
-                .filter_map(|item| item)
+                .flatten()
                 .collect(),
             ws_url: self.ws_host.clone(),
         })
-        .await;
+        .await
+        .map_err(WorkerError::other)?;
 
         info!("Stream connected - init snapshots");
 
-        if let Some(stream) = stream {
-            let (mut candle_tx, candle_rx) = mpsc::unbounded();
-            let (mut orderbook_tx, orderbook_rx) = mpsc::unbounded();
-            let (mut trades_tx, trades_rx) = mpsc::unbounded();
-            let mut futures = Vec::new();
-
-            futures.push(
-                async move {
-                    let mut stream = stream.boxed();
-                    while let Some(item) = stream.next().await {
-                        match item {
-                            MarketFeedMessage::Candle(c) => {
-                                candle_tx.send(c).await.unwrap();
-                            }
-                            MarketFeedMessage::OrderBook(o) => {
-                                orderbook_tx.send(o).await.unwrap();
-                            }
-                            MarketFeedMessage::Trade(t) => {
-                                trades_tx.send(t).await.unwrap();
-                            }
+        let (mut candle_tx, candle_rx) = updates("candles");
+        let (mut orderbook_tx, orderbook_rx) = updates("orderbook");
+        let (mut trades_tx, trades_rx) = updates("trades");
+        let mut futures = Vec::new();
+
+        futures.push(
+            async move {
+                let mut stream = stream.boxed();
+                while let Some(item) = stream.next().await {
+                    match item {
+                        MarketFeedMessage::Candle(c) => candle_tx.send(Update::Item(c)),
+                        MarketFeedMessage::OrderBook(o) => orderbook_tx.send(Update::Item(o)),
+                        MarketFeedMessage::Trade(t) => trades_tx.send(Update::Item(t)),
+                        MarketFeedMessage::Connected => info!("Stream connected"),
+                        MarketFeedMessage::Disconnected { reason } => {
+                            warn!(%reason, "Stream disconnected - snapshots go stale");
+                        }
+                        MarketFeedMessage::Resubscribed => {
+                            info!("Stream resubscribed - resync snapshots");
+                            candle_tx.send(Update::Resync);
+                            orderbook_tx.send(Update::Resync);
+                            trades_tx.send(Update::Resync);
                         }
                     }
                 }
+                Ok(())
+            }
+            .boxed(),
+        );
+
+        futures.push(self.run_candles_future(candle_rx, candles_sink).boxed());
+        futures.push(self.run_trades_future(trades_rx, trades_aggregate_sink).boxed());
+        futures.push(
+            self.run_orderbook_future(orderbook_rx, orderbook_sink)
                 .boxed(),
-            );
-
-            futures.push(self.run_candles_future(candle_rx, candles_sink).boxed());
-            futures.push(self.run_trades_future(trades_rx, trades_aggregate_sink).boxed());
-            futures.push(
-                self.run_orderbook_future(orderbook_rx, orderbook_sink)
-                    .boxed(),
-            );
-
-            futures::future::join_all(futures).await;
-            info!("exit");
-        } else {
-            panic!("Failed to init market stream");
-        }
+        );
+
+        futures::future::try_join_all(futures).await?;
+        info!("exit");
+        Ok(())
     }
 
-    fn candles_future<'f>(
+    async fn run_candles_future(
         &self,
-        fetched_candles: Candles,
-        mut candles_stream: impl Stream<Item = Candle> + Send + Sync + Unpin + 'f,
-        mut sink: impl Sink<Candles> + Send + Sync + Unpin + 'f,
-    ) -> BoxFuture<'f, ()> {
-        let candles_settings = self.candles.as_ref().unwrap();
-        let candles_amount = candles_settings.amount;
-        async move {
-            let mut candles = fetched_candles;
-            while let Some(candle) = candles_stream.next().await {
-                candles.join(candle);
-                candles.split_on(candles_amount);
-                if sink.send(candles.clone()).await.is_err() {
-                    error!("Sink must be ok");
-                    panic!();
+        mut candles_stream: impl Stream<Item = Update<Candle>> + Send + Sync + Unpin,
+        mut sink: impl Sink<Candles> + Send + Sync + Unpin,
+    ) -> Result<(), WorkerError> {
+        let Some(settings) = self.candles.as_ref() else {
+            return Ok(());
+        };
+        loop {
+            info!("Get candles snapshot");
+            let mut candles = fetch_candles(FetchCandlesInput {
+                ticker: self.ticker.clone(),
+                time_unit: settings.time_unit.clone(),
+                client: self.client.clone(),
+                countback: settings.amount,
+                clock: self.clock.clone(),
+            })
+            .await
+            .map_err(WorkerError::other)?;
+
+            loop {
+                match candles_stream.next().await {
+                    Some(Update::Item(candle)) => {
+                        candles.join(candle);
+                        candles.split_on(settings.amount);
+                        if sink.send(candles.clone()).await.is_err() {
+                            error!("Sink must be ok");
+                            panic!();
+                        }
+                    }
+                    Some(Update::Resync) => break,
+                    None => return Ok(()),
                 }
             }
         }
-        .boxed()
     }
 
-    async fn run_candles_future<'f>(
+    async fn run_trades_future(
         &self,
-        candles_stream: impl Stream<Item = Candle> + Send + Sync + Unpin + 'f,
-        sink: impl Sink<Candles> + Send + Sync + Unpin + 'f,
-    ) {
-        if let Some(candles) = self.candles.as_ref() {
-            info!("Get candles snapshot");
-            let result = fetch_candles(FetchCandlesInput {
+        mut trades_stream: impl Stream<Item = Update<Trade>> + Send + Sync + Unpin,
+        mut sink: impl Sink<TradesAggregate> + Send + Sync + Unpin,
+    ) -> Result<(), WorkerError> {
+        let Some(trades) = self.trades.as_ref() else {
+            return Ok(());
+        };
+        let options = AggregateOptions::new(
+            self.aggregate_options.speed_factor_window,
+            self.aggregate_options.tolerance,
+            0.0,
+        );
+        loop {
+            info!(?trades, "fetch trades");
+            let mut snapshot = fetch_historical_trades(FetchHistoricalTradesInput {
+                from: trades.window,
+                api_key: self.api_key.clone(),
                 ticker: self.ticker.clone(),
-                time_unit: candles.time_unit.clone(),
-                api_host: self.api_host.clone(),
-                countback: candles.amount,
+                client: self.client.clone(),
+                clock: self.clock.clone(),
             })
-            .await;
+            .await
+            .map_err(WorkerError::other)?;
 
-            self.candles_future(result, candles_stream, sink).await
+            loop {
+                match trades_stream.next().await {
+                    Some(Update::Item(trade)) => {
+                        snapshot.add(trade);
+                        snapshot.remove_old(trades.window);
+                        let agg: TradesAggregate = snapshot.calculate_aggregate(&options);
+                        if sink.send(agg.clone()).await.is_err() {
+                            error!("Sink must be ok");
+                            panic!();
+                        }
+                    }
+                    Some(Update::Resync) => break,
+                    None => return Ok(()),
+                }
+            }
         }
     }
 
-    fn orderbook_future<'f>(
+    async fn run_orderbook_future(
         &self,
-        orderbook_snapshot: OrderBook,
-        mut orderbook_stream: impl Stream<Item = OrderBook> + Send + Sync + Unpin + 'f,
-        mut sink: impl Sink<OrderBook> + Send + Sync + Unpin + 'f,
-    ) -> BoxFuture<'f, ()> {
-        async move {
-            let mut snapshot = orderbook_snapshot;
-            while let Some(ob) = orderbook_stream.next().await {
-                snapshot.join(ob);
-                if sink.send(snapshot.clone()).await.is_err() {
-                    error!("Sink must be ok");
-                    panic!();
+        mut orderbook_stream: impl Stream<Item = Update<OrderBook>> + Send + Sync + Unpin,
+        mut sink: impl Sink<OrderBook> + Send + Sync + Unpin,
+    ) -> Result<(), WorkerError> {
+        let Some(ob) = self.orderbook.as_ref() else {
+            return Ok(());
+        };
+        loop {
+            let mut snapshot = fetch_orderbook(FetchOrderbookInput {
+                ticker: self.ticker.clone(),
+                depth: ob.depth,
+                client: self.client.clone(),
+            })
+            .await
+            .map_err(WorkerError::other)?;
+
+            loop {
+                match orderbook_stream.next().await {
+                    Some(Update::Item(update)) => {
+                        snapshot.join(update);
+                        if sink.send(snapshot.clone()).await.is_err() {
+                            error!("Sink must be ok");
+                            panic!();
+                        }
+                    }
+                    Some(Update::Resync) => break,
+                    None => return Ok(()),
                 }
             }
         }
-        .boxed()
     }
+}
 
-    fn trades_future<'f>(
-        &self,
-        trades_history: Trades,
-        mut trades_stream: impl Stream<Item = Trade> + Send + Sync + Unpin + 'f,
-        mut sink: impl Sink<TradesAggregate> + Send + Sync + Unpin + 'f,
-    ) -> BoxFuture<'f, ()> {
-        let options = self.aggregate_options.clone();
-        let window = self.trades.as_ref().unwrap().window;
-        async move {
-            let options = AggregateOptions::new(options.speed_factor_window, options.tolerance, 0.0);
-            let mut snapshot = trades_history;
-            while let Some(trade) = trades_stream.next().await {
-                snapshot.add(trade);
-                snapshot.remove_old(window);
-                let agg: TradesAggregate = snapshot.calculate_aggregate(&options);
-                if sink.send(agg.clone()).await.is_err() {
-                    error!("Sink must be ok");
-                    panic!();
-                }
+/// Updates a snapshot can fall behind by, e.g. while it is fetched
+const UPDATES_CAPACITY: usize = 1024;
+
+/// What the stream hands to a snapshot: an update to apply, or word that
+/// updates were lost and the snapshot has to be fetched again.
+enum Update<T> {
+    Item(T),
+    Resync,
+}
+
+fn updates<T>(snapshot: &'static str) -> (Updates<T>, mpsc::Receiver<Update<T>>) {
+    let (tx, rx) = mpsc::channel(UPDATES_CAPACITY);
+    let updates = Updates {
+        snapshot,
+        tx,
+        lagged: false,
+    };
+    (updates, rx)
+}
+
+/// Never waits for a snapshot: once it falls behind by `UPDATES_CAPACITY`,
+/// updates are dropped until it can be told to resync.
+struct Updates<T> {
+    snapshot: &'static str,
+    tx: mpsc::Sender<Update<T>>,
+    lagged: bool,
+}
+
+impl<T> Updates<T> {
+    fn send(&mut self, update: Update<T>) {
+        if self.lagged {
+            if self.tx.try_send(Update::Resync).is_err() {
+                return;
+            }
+            self.lagged = false;
+        }
+        // Snapshots which are not configured have hung up already
+        match self.tx.try_send(update) {
+            Err(e) if e.is_full() => {
+                warn!(snapshot = self.snapshot, "Snapshot falls behind - drop updates and resync");
+                self.lagged = true;
             }
+            _ => {}
         }
-        .boxed()
     }
+}
 
-    async fn run_trades_future<'f>(
-        &self,
-        trades_stream: impl Stream<Item = Trade> + Send + Sync + Unpin + 'f,
-        sink: impl Sink<TradesAggregate> + Send + Sync + Unpin + 'f,
-    ) {
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
 
-        if let Some(trades) = self.trades.as_ref() {
-            info!(?trades, "fetch trades");
-            let result = fetch_historical_trades(FetchHistoricalTradesInput {
-                from: trades.window,
-                api_key: self.api_key.clone(),
-                ticker: self.ticker.clone(),
-                api_host: self.api_host.clone(),
-            })
-            .await;
+    use futures::sink::drain;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tokio_tungstenite::{accept_async, tungstenite::Message};
 
-            self.trades_future(result, trades_stream, sink).await
-        }
+    use super::*;
+
+    /// Answers every request with an empty order book and counts them
+    async fn rest_api(fetches: Arc<AtomicUsize>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                fetches.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut request = [0; 4096];
+                    let _ = stream.read(&mut request).await;
+                    let body = r#"{"lastUpdateId":1,"bids":[],"asks":[]}"#;
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\
+                         content-length: {}\r\nconnection: close\r\n\r\n{body}",
+                        body.len()
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        url
     }
-    async fn run_orderbook_future<'f>(
-        &self,
-        orderbook_stream: impl Stream<Item = OrderBook> + Send + Sync + Unpin + 'f,
-        sink: impl Sink<OrderBook> + Send + Sync + Unpin + 'f,
-    ) {
-        if let Some(ob) = self.orderbook.as_ref() {
-            let result = fetch_orderbook(FetchOrderbookInput {
-                ticker: self.ticker.clone(),
-                depth: ob.depth,
-                api_host: self.api_host.clone(),
-            })
-            .await;
 
-            self.orderbook_future(result, orderbook_stream, sink).await
+    /// Acks the subscription of two connections and drops the first one
+    async fn stream_api() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for connection in 0..2 {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut socket = accept_async(stream).await.unwrap();
+                let Some(Ok(Message::Text(command))) = socket.next().await else {
+                    panic!("no subscribe command");
+                };
+                let command: serde_json::Value = serde_json::from_str(&command).unwrap();
+                let ack = format!(r#"{{"result":null,"id":{}}}"#, command["id"]);
+                socket.send(Message::Text(ack)).await.unwrap();
+                if connection == 0 {
+                    socket.close(None).await.unwrap();
+                } else {
+                    while let Some(Ok(_)) = socket.next().await {}
+                }
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn resubscribed_stream_fetches_snapshot_again() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let config: PriceFeedConfig = serde_json::from_value(serde_json::json!({
+            "api_host": rest_api(fetches.clone()).await,
+            "ws_host": stream_api().await,
+            "api_key": "key",
+            "ticker": "BTCUSDT",
+            "orderbook": { "depth": 5 },
+            "aggregate_options": { "tolerance": 0.1, "speed_factor_window": "1m" },
+        }))
+        .unwrap();
+        let feed = PriceFeed::new(config).run_feed(drain(), drain(), drain());
+
+        let refetched = async {
+            while fetches.load(Ordering::SeqCst) < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::select! {
+            result = feed => panic!("feed ended: {result:?}"),
+            _ = tokio::time::timeout(Duration::from_secs(10), refetched) => {}
         }
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
     }
 }
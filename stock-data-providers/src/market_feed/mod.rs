@@ -1,10 +1,22 @@
-use std::time::Duration;
+use std::{
+    convert::Infallible,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
 
-use app::{mpsc, worker::ProducerWorker, BoxFuture, FutureExt, SinkExt, StreamExt};
-use futures::select;
+use app::{
+    registry::Registry,
+    shutdown::Shutdown,
+    worker::{ProducerWorker, WorkerError},
+    BoxFuture, FutureExt, InjectedTo, Sink, SinkExt, StreamExt,
+};
+use exchange_http::HttpClient;
+use futures::{future::Either, select, stream::BoxStream};
 use market_feed::{candles::Candles, order_book::OrderBook, trade::{TradesAggregate, }};
-use serde::Deserialize;
-use sources_common::symbol::Symbol;
+use serde::{Deserialize, Serialize};
+use sources_common::clock::SharedClock;
+use tokio::sync::watch;
 use tracing::info;
 use url::Url;
 
@@ -13,7 +25,7 @@ use self::config::{CandleSettings, OrderbookSettings, TradesSettings};
 pub mod config;
 mod implementation;
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct PriceFeedData {
     pub candles: Option<Candles>,
     pub orderbook: Option<OrderBook>,
@@ -30,7 +42,7 @@ pub struct AggregateOptions {
 
 #[derive(Debug)]
 pub struct PriceFeed {
-    api_host: Url,
+    client: HttpClient,
     api_key: String,
     ws_host: Url,
     ticker: String,
@@ -38,26 +50,42 @@ pub struct PriceFeed {
     orderbook: Option<OrderbookSettings>,
     trades: Option<TradesSettings>,
     aggregate_options: AggregateOptions,
+    clock: SharedClock,
+}
+
+/// Registers `PriceFeed` as `price_feed`, configured by `PriceFeedConfig`.
+pub fn register<'f, S>(registry: Registry<'f, S>) -> Registry<'f, S>
+where
+    'f: 'static,
+    S: Clone + Send + Sync + 'static,
+    PriceFeedData: InjectedTo<S>,
+{
+    registry.producer("price_feed", PriceFeed::new)
 }
 
 impl<'f> ProducerWorker<'f, PriceFeedData> for PriceFeed {
-    fn work(self: Box<Self>, mut state_tx: app::mpsc::Sender<PriceFeedData>) -> BoxFuture<'f, ()> {
+    fn work(
+        self: Box<Self>,
+        mut state_tx: app::mpsc::Sender<PriceFeedData>,
+        mut shutdown: Shutdown,
+    ) -> BoxFuture<'f, Result<(), WorkerError>> {
         async move {
             let mut accumulated = PriceFeedData::default();
-            let (candles_tx, mut candles_rx) = mpsc::unbounded();
-            let (orderbook_tx, mut orderbook_rx) = mpsc::unbounded();
-            let (trades_tx, mut trades_rx) = mpsc::unbounded();
+            let (candles_tx, candles_rx) = latest();
+            let (orderbook_tx, orderbook_rx) = latest();
+            let (trades_tx, trades_rx) = latest();
+            let (mut candles_rx, mut orderbook_rx, mut trades_rx) =
+                (candles_rx.fuse(), orderbook_rx.fuse(), trades_rx.fuse());
 
-            let mut futures = Vec::new();
-            futures.push(self.run_feed(candles_tx, orderbook_tx, trades_tx).boxed());
-            futures.push(
+            let feed = self.run_feed(candles_tx, orderbook_tx, trades_tx);
+            let forward: BoxFuture<'_, Result<(), WorkerError>> =
                 async move {
                     loop {
                         select! {
                             maybe_candles = candles_rx.next() => {
                                 if let Some (candles) = maybe_candles {
                                     accumulated = PriceFeedData{ candles: Some(candles), ..accumulated};
-                                    state_tx.send(accumulated.clone()).await.expect("Channel expected to be good");
+                                    state_tx.send(accumulated.clone()).await?;
                                 } else {
                                     info!("Candles stream finished - exit data feed");
                                 }
@@ -65,7 +93,7 @@ impl<'f> ProducerWorker<'f, PriceFeedData> for PriceFeed {
                             maybe_orderbook = orderbook_rx.next() =>{
                                 if let Some (orderbook) = maybe_orderbook {
                                 accumulated = PriceFeedData{ orderbook: Some(orderbook), ..accumulated};
-                                state_tx.send(accumulated.clone()).await.expect("Channel expected to be good");
+                                state_tx.send(accumulated.clone()).await?;
                                 } else {
                                     info!("OrderBook stream finished - exit data feed");
                                 }
@@ -73,7 +101,7 @@ impl<'f> ProducerWorker<'f, PriceFeedData> for PriceFeed {
                             maybe_trades = trades_rx.next() =>{
                                 if let Some (trades_aggregate) = maybe_trades {
                                 accumulated = PriceFeedData{ trades_aggregate: Some(trades_aggregate), ..accumulated};
-                                state_tx.send(accumulated.clone()).await.expect("Channel expected to be good");
+                                state_tx.send(accumulated.clone()).await?;
                                 } else {
                                     info!("Trades stream finished - exit data feed");
                                 }
@@ -81,12 +109,73 @@ impl<'f> ProducerWorker<'f, PriceFeedData> for PriceFeed {
                         }
                     }
                 }
-                .boxed(),
-            );
-
-            futures::future::join_all(futures).await;
+                .boxed();
 
+            let work = futures::future::try_join(feed, forward).map(|result| result.map(|_| ()));
+            let result = match futures::future::select(work.boxed(), shutdown.wait().boxed()).await {
+                Either::Left((result, _)) => result,
+                Either::Right(_) => Ok(()),
+            };
+            info!("Price feed stopped");
+            result
         }
         .boxed()
     }
 }
+
+/// Holds the newest snapshot until the worker gets to send it, so a slow
+/// store makes the feed skip snapshots rather than queue them up.
+fn latest<T>() -> (Latest<T>, BoxStream<'static, T>)
+where
+    T: Clone + Send + Sync + 'static,
+{
+    let (tx, rx) = watch::channel(None);
+    let snapshots = futures::stream::unfold(rx, |mut rx| async move {
+        rx.changed().await.ok()?;
+        let snapshot = rx.borrow_and_update().clone()?;
+        Some((snapshot, rx))
+    });
+    (Latest(tx), snapshots.boxed())
+}
+
+struct Latest<T>(watch::Sender<Option<T>>);
+
+impl<T> Sink<T> for Latest<T> {
+    type Error = Infallible;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, snapshot: T) -> Result<(), Self::Error> {
+        self.0.send_replace(Some(snapshot));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn latest_keeps_only_the_newest_snapshot() {
+        let (mut tx, mut rx) = latest();
+        for snapshot in 1..=3 {
+            tx.send(snapshot).await.unwrap();
+        }
+        assert_eq!(rx.next().await, Some(3));
+
+        tx.send(4).await.unwrap();
+        drop(tx);
+        assert_eq!(rx.next().await, Some(4));
+        assert_eq!(rx.next().await, None);
+    }
+}
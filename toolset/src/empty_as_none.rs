@@ -0,0 +1,133 @@
+//! Optional fields for which exchanges send `""` rather than `null` or
+//! nothing, e.g. the delivery time of a perpetual. `None` is serialized
+//! back as `""`.
+//!
+//! `#[serde(default, with = "toolset::empty_as_none")]`
+
+use std::{fmt, marker::PhantomData};
+
+use serde::{
+    de::{
+        self,
+        value::{MapAccessDeserializer, SeqAccessDeserializer},
+        IntoDeserializer, MapAccess, SeqAccess,
+    },
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+pub fn serialize<T: Serialize, S: Serializer>(
+    value: &Option<T>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => value.serialize(serializer),
+        None => serializer.serialize_str(""),
+    }
+}
+
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(EmptyAsNone(PhantomData))
+}
+
+struct EmptyAsNone<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> de::Visitor<'de> for EmptyAsNone<T> {
+    type Value = Option<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a value, null or an empty string")
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        if v.is_empty() {
+            return Ok(None);
+        }
+        T::deserialize(v.into_deserializer()).map(Some)
+    }
+
+    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+        if v.is_empty() {
+            return Ok(None);
+        }
+        T::deserialize(de::value::BorrowedStrDeserializer::new(v)).map(Some)
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+        T::deserialize(v.into_deserializer()).map(Some)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        T::deserialize(v.into_deserializer()).map(Some)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        T::deserialize(v.into_deserializer()).map(Some)
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        T::deserialize(v.into_deserializer()).map(Some)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        T::deserialize(SeqAccessDeserializer::new(seq)).map(Some)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        T::deserialize(MapAccessDeserializer::new(map)).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Contract {
+        #[serde(default, with = "crate::empty_as_none")]
+        mode: Option<String>,
+        #[serde(default, with = "crate::empty_as_none")]
+        delivery_time: Option<u64>,
+    }
+
+    #[test]
+    fn round_trips_empty_strings() {
+        let contract: Contract =
+            serde_json::from_str(r#"{"mode": "", "delivery_time": null}"#).unwrap();
+        assert_eq!(
+            contract,
+            Contract {
+                mode: None,
+                delivery_time: None
+            }
+        );
+        let json = serde_json::to_string(&contract).unwrap();
+        assert_eq!(json, r#"{"mode":"","delivery_time":""}"#);
+        assert_eq!(serde_json::from_str::<Contract>(&json).unwrap(), contract);
+
+        let contract: Contract =
+            serde_json::from_str(r#"{"mode": "NONE", "delivery_time": 1700000000000}"#).unwrap();
+        assert_eq!(contract.mode.as_deref(), Some("NONE"));
+        assert_eq!(contract.delivery_time, Some(1700000000000));
+        let json = serde_json::to_string(&contract).unwrap();
+        assert_eq!(serde_json::from_str::<Contract>(&json).unwrap(), contract);
+
+        assert_eq!(serde_json::from_str::<Contract>("{}").unwrap().mode, None);
+        assert!(serde_json::from_str::<Contract>(r#"{"delivery_time": "soon"}"#).is_err());
+    }
+}
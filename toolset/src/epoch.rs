@@ -0,0 +1,137 @@
+//! Times sent as whole milliseconds, microseconds or nanoseconds since the
+//! Unix epoch, as numbers or as strings of digits. Serialized back as
+//! numbers in the same unit.
+//!
+//! `#[serde(with = "toolset::epoch::millis")]`, and so on for `micros` and
+//! `nanos`, on any type implementing [`EpochTime`].
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserializer, Serializer};
+
+use crate::string_or_int::IntVisitor;
+
+/// A point in time the helpers can convert from and to nanoseconds since
+/// the epoch. Conversions saturate at what the type can hold.
+pub trait EpochTime {
+    fn from_epoch_nanos(nanos: i128) -> Self;
+    fn epoch_nanos(&self) -> i128;
+}
+
+/// Time since the epoch; times before it become zero.
+impl EpochTime for Duration {
+    fn from_epoch_nanos(nanos: i128) -> Self {
+        let nanos = nanos.clamp(0, u64::MAX.into());
+        Duration::from_nanos(nanos as u64)
+    }
+
+    fn epoch_nanos(&self) -> i128 {
+        self.as_nanos() as i128
+    }
+}
+
+impl EpochTime for SystemTime {
+    fn from_epoch_nanos(nanos: i128) -> Self {
+        let since_epoch = Duration::from_epoch_nanos(nanos.abs());
+        if nanos < 0 {
+            UNIX_EPOCH - since_epoch
+        } else {
+            UNIX_EPOCH + since_epoch
+        }
+    }
+
+    fn epoch_nanos(&self) -> i128 {
+        match self.duration_since(UNIX_EPOCH) {
+            Ok(after) => after.epoch_nanos(),
+            Err(before) => -before.duration().epoch_nanos(),
+        }
+    }
+}
+
+fn serialize<T: EpochTime, S: Serializer>(
+    time: &T,
+    nanos_per_unit: i128,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let units = time.epoch_nanos().div_euclid(nanos_per_unit);
+    serializer.serialize_i64(units.clamp(i64::MIN.into(), i64::MAX.into()) as i64)
+}
+
+fn deserialize<'de, T: EpochTime, D: Deserializer<'de>>(
+    nanos_per_unit: i128,
+    deserializer: D,
+) -> Result<T, D::Error> {
+    let units = deserializer.deserialize_any(IntVisitor)?;
+    Ok(T::from_epoch_nanos(units.saturating_mul(nanos_per_unit)))
+}
+
+macro_rules! epoch_unit {
+    ($name:ident, $nanos_per_unit:expr) => {
+        pub mod $name {
+            use super::*;
+
+            pub fn serialize<T: EpochTime, S: Serializer>(
+                time: &T,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                super::serialize(time, $nanos_per_unit, serializer)
+            }
+
+            pub fn deserialize<'de, T: EpochTime, D: Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<T, D::Error> {
+                super::deserialize($nanos_per_unit, deserializer)
+            }
+        }
+    };
+}
+
+epoch_unit!(millis, 1_000_000);
+epoch_unit!(micros, 1_000);
+epoch_unit!(nanos, 1);
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Event {
+        #[serde(with = "millis")]
+        ms: SystemTime,
+        #[serde(with = "micros")]
+        us: Duration,
+        #[serde(with = "nanos")]
+        ns: SystemTime,
+    }
+
+    #[test]
+    fn round_trips_every_unit() {
+        let input =
+            r#"{"ms": 1499040000123, "us": "1499040000123456", "ns": "1499040000123456789"}"#;
+        let event: Event = serde_json::from_str(input).unwrap();
+        assert_eq!(
+            event.ms,
+            UNIX_EPOCH + Duration::from_millis(1_499_040_000_123)
+        );
+        assert_eq!(event.us, Duration::from_micros(1_499_040_000_123_456));
+        assert_eq!(event.ns.epoch_nanos(), 1_499_040_000_123_456_789);
+
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            r#"{"ms":1499040000123,"us":1499040000123456,"ns":1499040000123456789}"#
+        );
+        assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+    }
+
+    #[test]
+    fn times_before_the_epoch() {
+        let json = r#"{"ms": -1, "us": -1, "ns": 0}"#;
+        let event: Event = serde_json::from_str(json).unwrap();
+        assert_eq!(event.ms, UNIX_EPOCH - Duration::from_millis(1));
+        assert_eq!(event.us, Duration::ZERO);
+        assert!(serde_json::from_str::<Event>(r#"{"ms": "soon", "us": 0, "ns": 0}"#).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! Order book levels sent as arrays of strings, `[price, qty]` or with
+//! more fields after those, e.g. kucoin's `[price, qty, sequence]`. Only
+//! price and quantity are kept; they are serialized back as
+//! `[["price", "qty"], ...]`.
+//!
+//! `#[serde(with = "toolset::levels")]` on a `Vec<(P, Q)>`, where `P` and
+//! `Q` parse from strings, like `f64` or exact decimals.
+
+use std::{fmt, marker::PhantomData, str::FromStr};
+
+use serde::{
+    de::{self, IgnoredAny, SeqAccess},
+    ser::SerializeSeq,
+    Deserialize, Deserializer, Serializer,
+};
+
+pub fn serialize<P, Q, S>(levels: &[(P, Q)], serializer: S) -> Result<S::Ok, S::Error>
+where
+    P: fmt::Display,
+    Q: fmt::Display,
+    S: Serializer,
+{
+    let mut seq = serializer.serialize_seq(Some(levels.len()))?;
+    for (price, qty) in levels {
+        seq.serialize_element(&[price.to_string(), qty.to_string()])?;
+    }
+    seq.end()
+}
+
+pub fn deserialize<'de, P, Q, D>(deserializer: D) -> Result<Vec<(P, Q)>, D::Error>
+where
+    P: FromStr,
+    P::Err: fmt::Display,
+    Q: FromStr,
+    Q::Err: fmt::Display,
+    D: Deserializer<'de>,
+{
+    let levels = Vec::<Level<P, Q>>::deserialize(deserializer)?;
+    Ok(levels.into_iter().map(|level| (level.0, level.1)).collect())
+}
+
+struct Level<P, Q>(P, Q);
+
+impl<'de, P, Q> Deserialize<'de> for Level<P, Q>
+where
+    P: FromStr,
+    P::Err: fmt::Display,
+    Q: FromStr,
+    Q::Err: fmt::Display,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(LevelVisitor(PhantomData))
+    }
+}
+
+struct LevelVisitor<P, Q>(PhantomData<(P, Q)>);
+
+impl<'de, P, Q> de::Visitor<'de> for LevelVisitor<P, Q>
+where
+    P: FromStr,
+    P::Err: fmt::Display,
+    Q: FromStr,
+    Q::Err: fmt::Display,
+{
+    type Value = Level<P, Q>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array starting with price and quantity")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let price = seq
+            .next_element::<Field>()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let qty = seq
+            .next_element::<Field>()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(Level(
+            price.0.parse().map_err(de::Error::custom)?,
+            qty.0.parse().map_err(de::Error::custom)?,
+        ))
+    }
+}
+
+/// A string, or a number taken as its string.
+struct Field(String);
+
+impl<'de> Deserialize<'de> for Field {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(FieldVisitor)
+    }
+}
+
+struct FieldVisitor;
+
+impl<'de> de::Visitor<'de> for FieldVisitor {
+    type Value = Field;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a number or a string with one")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(Field(v.to_string()))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(Field(v.to_string()))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(Field(v.to_string()))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(Field(v.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Book {
+        #[serde(with = "crate::levels")]
+        bids: Vec<(f64, f64)>,
+        #[serde(with = "crate::levels")]
+        asks: Vec<(f64, u32)>,
+    }
+
+    #[test]
+    fn round_trips_levels_of_any_arity() {
+        let input = r#"{
+            "bids": [["0.0024", "10"], [0.0023, 5]],
+            "asks": [["0.0026", "100", 1234567], ["0.0027", "3", "extra", []]]
+        }"#;
+        let book: Book = serde_json::from_str(input).unwrap();
+        assert_eq!(
+            book,
+            Book {
+                bids: vec![(0.0024, 10.0), (0.0023, 5.0)],
+                asks: vec![(0.0026, 100), (0.0027, 3)],
+            }
+        );
+
+        let json = serde_json::to_string(&book).unwrap();
+        assert_eq!(
+            json,
+            r#"{"bids":[["0.0024","10"],["0.0023","5"]],"asks":[["0.0026","100"],["0.0027","3"]]}"#
+        );
+        assert_eq!(serde_json::from_str::<Book>(&json).unwrap(), book);
+
+        assert!(serde_json::from_str::<Book>(r#"{"bids": [["1"]], "asks": []}"#).is_err());
+        assert!(serde_json::from_str::<Book>(r#"{"bids": [], "asks": [["1", "x"]]}"#).is_err());
+    }
+}
@@ -1,17 +1,7 @@
-use std::{borrow::Cow, time::Duration};
+//! Serde helpers for exchange payloads, to be used with `#[serde(with)]`.
 
-use serde::{de, Deserialize, Deserializer};
-
-pub fn deser_duration_from_integer<'de, D: Deserializer<'de>>(
-    deserializer: D,
-) -> Result<Duration, D::Error> {
-    let number = <u64>::deserialize(deserializer)?;
-    Ok(Duration::from_millis(number))
-}
-
-pub fn deser_float_from_string<'de, D: Deserializer<'de>>(
-    deserializer: D,
-) -> Result<f64, D::Error> {
-    let string_value = Cow::<str>::deserialize(deserializer)?;
-    string_value.as_ref().parse().map_err(de::Error::custom)
-}
+pub mod empty_as_none;
+pub mod epoch;
+pub mod levels;
+pub mod string_or_float;
+pub mod string_or_int;
@@ -0,0 +1,71 @@
+//! Floats sent either as JSON numbers or as strings, e.g. `"0.01634790"`.
+//! Serialized back as the shortest string which reads as the same float.
+//!
+//! `#[serde(with = "toolset::string_or_float")]`
+
+use std::fmt;
+
+use serde::{de, Deserializer, Serializer};
+
+pub fn serialize<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    deserializer.deserialize_any(FloatVisitor)
+}
+
+struct FloatVisitor;
+
+impl<'de> de::Visitor<'de> for FloatVisitor {
+    type Value = f64;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a number or a string with one")
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(v)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(v as f64)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(v as f64)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        v.trim().parse().map_err(E::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Ticker {
+        #[serde(with = "crate::string_or_float")]
+        price: f64,
+    }
+
+    #[test]
+    fn round_trips_strings_and_numbers() {
+        for input in [r#"{"price": "0.1"}"#, r#"{"price": 0.1}"#] {
+            let ticker: Ticker = serde_json::from_str(input).unwrap();
+            assert_eq!(ticker, Ticker { price: 0.1 });
+        }
+        let ticker: Ticker = serde_json::from_str(r#"{"price": 3}"#).unwrap();
+        assert_eq!(ticker.price, 3.0);
+
+        let json = serde_json::to_string(&Ticker { price: 0.01634790 }).unwrap();
+        assert_eq!(json, r#"{"price":"0.0163479"}"#);
+        assert_eq!(
+            serde_json::from_str::<Ticker>(&json).unwrap().price,
+            0.01634790
+        );
+        assert!(serde_json::from_str::<Ticker>(r#"{"price": "n/a"}"#).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+//! Integers sent either as JSON numbers or as strings of digits, e.g.
+//! `"200000"`. Serialized back as strings.
+//!
+//! `#[serde(with = "toolset::string_or_int")]`
+
+use std::fmt;
+
+use serde::{de, Deserializer, Serializer};
+
+pub fn serialize<T: fmt::Display, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: TryFrom<i128>,
+    T::Error: fmt::Display,
+    D: Deserializer<'de>,
+{
+    let value = deserializer.deserialize_any(IntVisitor)?;
+    T::try_from(value).map_err(de::Error::custom)
+}
+
+/// Wide enough for any integer a payload holds, checked against the
+/// target type afterwards.
+pub(crate) struct IntVisitor;
+
+impl<'de> de::Visitor<'de> for IntVisitor {
+    type Value = i128;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an integer or a string with one")
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        v.trim().parse().map_err(E::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Response {
+        #[serde(with = "crate::string_or_int")]
+        code: u32,
+        #[serde(with = "crate::string_or_int")]
+        offset: i64,
+    }
+
+    #[test]
+    fn round_trips_strings_and_numbers() {
+        let response: Response =
+            serde_json::from_str(r#"{"code": "200000", "offset": -5}"#).unwrap();
+        assert_eq!(
+            response,
+            Response {
+                code: 200000,
+                offset: -5
+            }
+        );
+        let json = serde_json::to_string(&response).unwrap();
+        assert_eq!(json, r#"{"code":"200000","offset":"-5"}"#);
+        assert_eq!(serde_json::from_str::<Response>(&json).unwrap(), response);
+
+        assert!(serde_json::from_str::<Response>(r#"{"code": -1, "offset": 0}"#).is_err());
+        assert!(serde_json::from_str::<Response>(r#"{"code": 1.5, "offset": 0}"#).is_err());
+    }
+}